@@ -0,0 +1,61 @@
+//! Shared fuzzing harness for the `UpEnc` schemes.
+
+use recrypt::{Cipher, UpEnc, UpEncCtxtIndep};
+
+const PLAINTEXT: &[u8] = b"The quick brown fox jumps over the lazy dog, twice over: \
+                           the quick brown fox jumps over the lazy dog.";
+
+// Builds a valid ciphertext and token, then replaces one part of it with the
+// fuzz input, selected by the first byte:
+//   0: the raw ciphertext header
+//   1: the ciphertext body
+//   2: the re-keying token
+//   3: the header plaintext, encrypted under the real key with `A`, so the
+//      parsing of its contents is reached
+// Every algorithm which reads that input is then run. Errors are expected;
+// panics are bugs.
+pub fn fuzz_upenc<S: UpEnc, A: Cipher<K = S::K>>(data: &[u8]) {
+    if data.is_empty() {
+        return;
+    }
+    let (selector, input) = (data[0], &data[1..]);
+
+    let k1 = S::keygen();
+    let k2 = S::keygen();
+    let mut hdr = Vec::new();
+    let mut body = Vec::new();
+    S::encrypt(k1.clone(), &mut &PLAINTEXT[..], &mut hdr, &mut body).unwrap();
+    let mut token = Vec::new();
+    S::rekeygen(k1.clone(), k2.clone(), &mut &hdr[..], &mut token).unwrap();
+
+    match selector % 4 {
+        0 => hdr = input.to_vec(),
+        1 => body = input.to_vec(),
+        2 => token = input.to_vec(),
+        _ => {
+            hdr = Vec::new();
+            A::encrypt(k1.clone(), &mut &input[..], &mut hdr).unwrap();
+        }
+    }
+
+    let _ = S::decrypt(k1.clone(), &mut &hdr[..], &mut &body[..], &mut Vec::new());
+    let _ = S::rekeygen(k1, k2.clone(), &mut &hdr[..], &mut Vec::new());
+
+    let mut hdr2 = Vec::new();
+    let mut body2 = Vec::new();
+    if S::reencrypt(&mut &token[..], &mut &hdr[..], &mut &body[..], &mut hdr2, &mut body2).is_ok() {
+        let _ = S::decrypt(k2, &mut &hdr2[..], &mut &body2[..], &mut Vec::new());
+    }
+}
+
+// Feeds the input to a ciphertext-independent scheme as a ciphertext, and as
+// a token applied to a valid ciphertext.
+pub fn fuzz_upenc_ctxt_indep<C: UpEncCtxtIndep>(data: &[u8]) {
+    let key = C::keygen();
+    let _ = C::decrypt(key.clone(), &mut &data[..], &mut Vec::new());
+    let _ = C::reencrypt(&mut &data[..], &mut &data[..], &mut Vec::new());
+
+    let mut ct = Vec::new();
+    C::encrypt(key, &mut &PLAINTEXT[..], &mut ct).unwrap();
+    let _ = C::reencrypt(&mut &data[..], &mut &ct[..], &mut Vec::new());
+}
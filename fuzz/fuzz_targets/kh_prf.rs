@@ -0,0 +1,12 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate recrypt;
+
+use recrypt::{KhPrf, RistrettoKhPrf};
+
+mod common;
+
+fuzz_target!(|data: &[u8]| {
+    common::fuzz_upenc_ctxt_indep::<KhPrf>(data);
+    common::fuzz_upenc_ctxt_indep::<RistrettoKhPrf>(data);
+});
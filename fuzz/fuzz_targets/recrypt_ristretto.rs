@@ -0,0 +1,12 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate recrypt;
+
+use recrypt::generic::ReCrypt;
+use recrypt::{RistrettoKhPrf, RingAes};
+
+mod common;
+
+fuzz_target!(|data: &[u8]| {
+    common::fuzz_upenc::<ReCrypt<RingAes, RistrettoKhPrf>, RingAes>(data);
+});
@@ -0,0 +1,946 @@
+//! Async versions of `Cipher`, `UpEncCtxtIndep` and `UpEnc`, enabled by the
+//! `async` feature.
+//!
+//! The calls are futures over Tokio's `AsyncRead`/`AsyncWrite`, run on the
+//! calling task rather than a thread pool. The key-homomorphic PRFs, and so
+//! the bodies of `ReCrypt`, are streamed a block at a time. `Kss` hashes its
+//! body as it passes to and from the body cipher, and re-encrypts by copying
+//! the body through. The other ciphers and schemes read their input into
+//! memory, as the blocking versions of the AEADs do, and then write the
+//! output. Either way the ciphertext formats and errors are exactly those of
+//! the blocking versions. The output streams are handed back once they have
+//! been flushed.
+//!
+//! Ciphers and schemes outside this crate get the in-memory versions with an
+//! empty `impl`.
+
+use super::errors::*;
+use super::{Cipher, Key, KeyHomomorphicPrf, UpEnc, UpEncCtxtIndep};
+use common::{pad, remove_padding};
+use generic::{KemDem, Kss, MerkleReCrypt, Naive, ReCrypt};
+use io::SecretBuf;
+use kh_prf::block_ctr;
+use {AesGcmSiv, KhPrf, LwrKhPrf, RingAes, RingAes256, RingAes256Stream, RingAesStream, RingChaCha, RingChaChaStream, RistrettoKhPrf, XChaCha};
+
+use std::cmp::max;
+use std::future::{self, Future};
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Add, Sub};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use ring::digest;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use zeroize::Zeroizing;
+
+// Bytes copied at a time when a stream is passed through unchanged.
+const COPY_SIZE: usize = 8192;
+
+// As `?` for a `Poll` of a `Result`, returning early while pending.
+macro_rules! try_ready {
+    ($e:expr) => (match $e {
+        Poll::Pending => return Poll::Pending,
+        Poll::Ready(Ok(t)) => t,
+        Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+    })
+}
+
+// As `?` inside `poll`.
+macro_rules! try_poll {
+    ($e:expr) => (match $e {
+        Ok(t) => t,
+        Err(e) => return Poll::Ready(Err(e.into())),
+    })
+}
+
+// As `?` in a function returning a `Task`.
+macro_rules! try_task {
+    ($e:expr) => (match $e {
+        Ok(t) => t,
+        Err(e) => return Task::ready(Err(e)),
+    })
+}
+
+/// A running async call, resolving to its result.
+pub struct Task<T> {
+    fut: Pin<Box<Future<Output = Result<T>> + Send>>,
+}
+
+impl<T: Send + 'static> Task<T> {
+    fn new<F: Future<Output = Result<T>> + Send + 'static>(fut: F) -> Task<T> {
+        Task { fut: Box::pin(fut) }
+    }
+
+    fn ready(res: Result<T>) -> Task<T> {
+        Task::new(future::ready(res))
+    }
+
+    // Runs `f` on the result once this has finished, and then the task it
+    // returns.
+    fn and_then<U: Send + 'static, F>(self, f: F) -> Task<U>
+        where F: FnOnce(T) -> Task<U> + Send + 'static
+    {
+        Task::new(AndThen { first: self, f: Some(f), second: None })
+    }
+
+    fn map<U: Send + 'static, F>(self, f: F) -> Task<U>
+        where F: FnOnce(T) -> U + Send + 'static
+    {
+        self.and_then(move |t| Task::ready(Ok(f(t))))
+    }
+}
+
+impl<T> Future for Task<T> {
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T>> {
+        self.get_mut().fut.as_mut().poll(cx)
+    }
+}
+
+struct AndThen<T, U, F> {
+    first: Task<T>,
+    f: Option<F>,
+    second: Option<Task<U>>,
+}
+
+// Nothing is pinned in place; the tasks are boxed.
+impl<T, U, F> Unpin for AndThen<T, U, F> {}
+
+impl<T, U, F: FnOnce(T) -> Task<U>> Future for AndThen<T, U, F> {
+    type Output = Result<U>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<U>> {
+        let this = self.get_mut();
+        if this.second.is_none() {
+            let t = try_ready!(Pin::new(&mut this.first).poll(cx));
+            let f = this.f.take().expect("polled after completion");
+            this.second = Some(f(t));
+        }
+        Pin::new(this.second.as_mut().unwrap()).poll(cx)
+    }
+}
+
+// Reads the whole of a stream into memory, returning the stream and its
+// contents.
+struct ReadToEnd<In> {
+    input: Option<In>,
+    buf: Zeroizing<Vec<u8>>,
+    len: usize,
+}
+
+impl<In: AsyncRead + Unpin> Future for ReadToEnd<In> {
+    type Output = Result<(In, Zeroizing<Vec<u8>>)>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            // Grow into a fresh buffer, so that no copy is left behind
+            // unwiped by a reallocation.
+            if this.len == this.buf.len() {
+                let mut bigger = Zeroizing::new(vec![0u8; max(2 * this.len, COPY_SIZE)]);
+                bigger[..this.len].copy_from_slice(&this.buf[..this.len]);
+                this.buf = bigger;
+            }
+            let input = this.input.as_mut().expect("polled after completion");
+            let mut read_buf = ReadBuf::new(&mut this.buf[this.len..]);
+            try_ready!(Pin::new(input).poll_read(cx, &mut read_buf));
+            let n = read_buf.filled().len();
+            if n == 0 {
+                let mut buf = mem::replace(&mut this.buf, Zeroizing::new(Vec::new()));
+                buf.truncate(this.len);
+                return Poll::Ready(Ok((this.input.take().unwrap(), buf)));
+            }
+            this.len += n;
+        }
+    }
+}
+
+fn read_to_end<In>(input: In) -> Task<(In, Zeroizing<Vec<u8>>)>
+    where In: AsyncRead + Unpin + Send + 'static
+{
+    Task::new(ReadToEnd { input: Some(input), buf: Zeroizing::new(Vec::new()), len: 0 })
+}
+
+// Writes all of `buf` and flushes, returning the stream.
+struct WriteAll<Out> {
+    out: Option<Out>,
+    buf: Zeroizing<Vec<u8>>,
+    written: usize,
+}
+
+impl<Out: AsyncWrite + Unpin> Future for WriteAll<Out> {
+    type Output = Result<Out>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<Out>> {
+        let this = self.get_mut();
+        let out = this.out.as_mut().expect("polled after completion");
+        try_ready!(poll_write_all(out, cx, &this.buf, &mut this.written));
+        try_ready!(Pin::new(&mut *out).poll_flush(cx));
+        Poll::Ready(Ok(this.out.take().unwrap()))
+    }
+}
+
+fn write_all<Out>(out: Out, buf: Zeroizing<Vec<u8>>) -> Task<Out>
+    where Out: AsyncWrite + Unpin + Send + 'static
+{
+    Task::new(WriteAll { out: Some(out), buf: buf, written: 0 })
+}
+
+// Writes `buf[*written..]`, counting the bytes written so far in `written`.
+fn poll_write_all<Out: AsyncWrite + Unpin>(out: &mut Out, cx: &mut Context, buf: &[u8], written: &mut usize) -> Poll<Result<()>> {
+    while *written < buf.len() {
+        let n = try_ready!(Pin::new(&mut *out).poll_write(cx, &buf[*written..]));
+        if n == 0 {
+            return Poll::Ready(Err(io::Error::from(io::ErrorKind::WriteZero).into()));
+        }
+        *written += n;
+    }
+    Poll::Ready(Ok(()))
+}
+
+// A reader over the bytes received so far, which notes how many bytes a
+// read would have needed instead of returning part of them.
+struct Probe<'a> {
+    data: &'a [u8],
+    pos: usize,
+    need: Option<usize>,
+}
+
+impl<'a> io::Read for Probe<'a> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.data.len() - self.pos < out.len() {
+            self.need = Some(self.pos + out.len());
+            return Ok(0);
+        }
+        out.copy_from_slice(&self.data[self.pos..self.pos + out.len()]);
+        self.pos += out.len();
+        Ok(out.len())
+    }
+}
+
+// Reads a key from the start of a stream, returning the stream positioned
+// just after it, or an error chained with `context`. `Key::read_key` reads
+// fixed-size fields, so it is retried as each field arrives, and nothing
+// past the key is read.
+struct ReadKey<In, K> {
+    input: Option<In>,
+    buf: Zeroizing<Vec<u8>>,
+    len: usize,
+    eof: bool,
+    context: fn() -> ErrorKind,
+    key: PhantomData<fn() -> K>,
+}
+
+impl<In: AsyncRead + Unpin, K: Key> Future for ReadKey<In, K> {
+    type Output = Result<(In, K)>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            let need = {
+                let mut probe = Probe { data: &this.buf[..this.len], pos: 0, need: None };
+                match K::read_key(&mut probe) {
+                    Ok(key) => return Poll::Ready(Ok((this.input.take().unwrap(), key))),
+                    Err(e) => match probe.need {
+                        Some(need) if !this.eof => need,
+                        _ => return Poll::Ready(Err(e).chain_err(this.context)),
+                    },
+                }
+            };
+            if need > this.buf.len() {
+                let mut bigger = Zeroizing::new(vec![0u8; need]);
+                bigger[..this.len].copy_from_slice(&this.buf[..this.len]);
+                this.buf = bigger;
+            }
+            while this.len < need {
+                let input = this.input.as_mut().expect("polled after completion");
+                let mut read_buf = ReadBuf::new(&mut this.buf[this.len..need]);
+                try_ready!(Pin::new(input).poll_read(cx, &mut read_buf));
+                let n = read_buf.filled().len();
+                if n == 0 {
+                    this.eof = true;
+                    break;
+                }
+                this.len += n;
+            }
+        }
+    }
+}
+
+fn read_key<K, In>(input: In, context: fn() -> ErrorKind) -> Task<(In, K)>
+    where K: Key + Send + 'static, In: AsyncRead + Unpin + Send + 'static
+{
+    Task::new(ReadKey {
+        input: Some(input),
+        buf: Zeroizing::new(Vec::new()),
+        len: 0,
+        eof: false,
+        context: context,
+        key: PhantomData,
+    })
+}
+
+// Transforms a stream in pieces, as driven by `Pump`.
+trait Codec {
+    // Bytes of input to pass to the next `update`.
+    fn want(&self) -> usize;
+    // Processes the next `want()` bytes of input, or fewer once `eof` is set
+    // and the input has run out, returning the output.
+    fn update(&mut self, input: &[u8], eof: bool) -> Result<Zeroizing<Vec<u8>>>;
+}
+
+// Reads `input` through a codec into `out`, then flushes, returning the
+// streams and the codec.
+struct Pump<In, Out, C> {
+    input: Option<In>,
+    out: Option<Out>,
+    codec: Option<C>,
+    buf: Zeroizing<Vec<u8>>,
+    filled: usize,
+    pending: Zeroizing<Vec<u8>>,
+    written: usize,
+    eof: bool,
+}
+
+// Nothing is pinned in place; the streams are `Unpin`.
+impl<In, Out, C> Unpin for Pump<In, Out, C> {}
+
+impl<In, Out, C> Future for Pump<In, Out, C>
+    where In: AsyncRead + Unpin, Out: AsyncWrite + Unpin, C: Codec
+{
+    type Output = Result<(In, Out, C)>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            {
+                let out = this.out.as_mut().expect("polled after completion");
+                try_ready!(poll_write_all(out, cx, &this.pending, &mut this.written));
+                if this.eof {
+                    try_ready!(Pin::new(&mut *out).poll_flush(cx));
+                    return Poll::Ready(Ok((this.input.take().unwrap(), this.out.take().unwrap(), this.codec.take().unwrap())));
+                }
+            }
+
+            let want = this.codec.as_ref().unwrap().want();
+            if want > this.buf.len() {
+                this.buf = Zeroizing::new(vec![0u8; want]);
+            }
+            while this.filled < want {
+                let input = this.input.as_mut().unwrap();
+                let mut read_buf = ReadBuf::new(&mut this.buf[this.filled..want]);
+                try_ready!(Pin::new(input).poll_read(cx, &mut read_buf));
+                let n = read_buf.filled().len();
+                if n == 0 {
+                    this.eof = true;
+                    break;
+                }
+                this.filled += n;
+            }
+
+            this.pending = try_poll!(this.codec.as_mut().unwrap().update(&this.buf[..this.filled], this.eof));
+            this.written = 0;
+            this.filled = 0;
+        }
+    }
+}
+
+fn pump<In, Out, C>(input: In, out: Out, codec: C) -> Task<(In, Out, C)>
+    where In: AsyncRead + Unpin + Send + 'static, Out: AsyncWrite + Unpin + Send + 'static,
+          C: Codec + Send + 'static
+{
+    Task::new(Pump {
+        input: Some(input),
+        out: Some(out),
+        codec: Some(codec),
+        buf: Zeroizing::new(Vec::new()),
+        filled: 0,
+        pending: Zeroizing::new(Vec::new()),
+        written: 0,
+        eof: false,
+    })
+}
+
+// Passes the input through unchanged.
+struct PassThrough;
+
+impl Codec for PassThrough {
+    fn want(&self) -> usize {
+        COPY_SIZE
+    }
+
+    fn update(&mut self, input: &[u8], _: bool) -> Result<Zeroizing<Vec<u8>>> {
+        Ok(Zeroizing::new(input.to_vec()))
+    }
+}
+
+// Hashes the input or the output of a codec with SHA-256.
+struct Hashing<C> {
+    codec: C,
+    hash: digest::Context,
+    input: bool,
+}
+
+impl<C> Hashing<C> {
+    fn input(codec: C) -> Hashing<C> {
+        Hashing { codec: codec, hash: digest::Context::new(&digest::SHA256), input: true }
+    }
+
+    fn output(codec: C) -> Hashing<C> {
+        Hashing { codec: codec, hash: digest::Context::new(&digest::SHA256), input: false }
+    }
+
+    fn finish(self) -> digest::Digest {
+        self.hash.finish()
+    }
+}
+
+impl<C: Codec> Codec for Hashing<C> {
+    fn want(&self) -> usize {
+        self.codec.want()
+    }
+
+    fn update(&mut self, input: &[u8], eof: bool) -> Result<Zeroizing<Vec<u8>>> {
+        if self.input {
+            self.hash.update(input);
+        }
+        let out = self.codec.update(input, eof)?;
+        if !self.input {
+            self.hash.update(&out);
+        }
+        Ok(out)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Encrypt,
+    Decrypt,
+    Update,
+}
+
+// The blocks of a `KeyHomomorphicPrf` ciphertext, starting with the nonce,
+// as written by `Cipher::encrypt` and read by `Cipher::decrypt` and
+// `UpEncCtxtIndep::reencrypt`.
+struct Blocks<B: KeyHomomorphicPrf> {
+    mode: Mode,
+    key: B::K,
+    nonce: Option<Vec<u8>>,
+    base: u64,
+    index: u64,
+    // The last plaintext block, held back until it is known whether it is
+    // the padded one.
+    prev: Zeroizing<Vec<u8>>,
+    prf: PhantomData<fn() -> B>,
+}
+
+impl<B: KeyHomomorphicPrf> Blocks<B> {
+    fn new(mode: Mode, key: B::K) -> Blocks<B> {
+        let ctr = B::counter(&key);
+        Blocks { mode: mode, key: key, nonce: None, base: ctr, index: 0, prev: Zeroizing::new(Vec::new()), prf: PhantomData }
+    }
+}
+
+impl<B: KeyHomomorphicPrf> Codec for Blocks<B> {
+    fn want(&self) -> usize {
+        match (self.mode, self.nonce.is_some()) {
+            (Mode::Encrypt, _) => B::PT_BLOCK_SIZE,
+            (_, false) => B::nonce_size(&self.key),
+            (_, true) => B::CT_BLOCK_SIZE,
+        }
+    }
+
+    fn update(&mut self, input: &[u8], _: bool) -> Result<Zeroizing<Vec<u8>>> {
+        let mut out = Zeroizing::new(Vec::new());
+        if self.nonce.is_none() {
+            if self.mode == Mode::Encrypt {
+                let nonce = B::new_nonce(&self.key);
+                out.extend_from_slice(&nonce);
+                self.nonce = Some(nonce);
+            } else {
+                if input.len() < B::nonce_size(&self.key) {
+                    return Err(ErrorKind::TruncatedCiphertext(input.len() as u64).into());
+                }
+                if self.mode == Mode::Update {
+                    out.extend_from_slice(input);
+                }
+                self.nonce = Some(input.to_vec());
+                return Ok(out);
+            }
+        }
+        let nonce = self.nonce.as_ref().unwrap();
+        let index = self.index;
+        let ctr = block_ctr(self.base, index);
+
+        match self.mode {
+            Mode::Encrypt => {
+                let mut block = Zeroizing::new(Vec::with_capacity(B::PT_BLOCK_SIZE));
+                block.extend_from_slice(input);
+                // Pad if it's not a full block.
+                if block.len() < B::PT_BLOCK_SIZE {
+                    pad(&mut block, B::PT_BLOCK_SIZE);
+                }
+                out.extend_from_slice(&B::encrypt_block(&self.key, &block, nonce, ctr?)
+                    .chain_err(|| ErrorKind::InvalidBlock(index))?);
+            },
+            _ if input.is_empty() => {
+                // Only the final block carries padding.
+                let last = mem::take(&mut *self.prev);
+                if let Some(pt) = remove_padding(last) {
+                    out = Zeroizing::new(pt);
+                }
+                return Ok(out);
+            },
+            _ if input.len() != B::CT_BLOCK_SIZE => {
+                return Err(ErrorKind::TruncatedBlock(index, input.len()).into());
+            },
+            Mode::Decrypt => {
+                let block = B::decrypt_block(&self.key, input, nonce, ctr?)
+                    .chain_err(|| ErrorKind::InvalidBlock(index))?;
+                out = mem::replace(&mut self.prev, Zeroizing::new(block));
+            },
+            Mode::Update => {
+                out.extend_from_slice(&B::update_block(&self.key, input, nonce, ctr?)
+                    .chain_err(|| ErrorKind::InvalidBlock(index))?);
+            },
+        }
+        self.index += 1;
+        Ok(out)
+    }
+}
+
+// A stream which hashes what is read from or written to it, into a hash
+// which is shared so that it can be read after the stream is handed off.
+struct Hashed<T> {
+    inner: T,
+    hash: Arc<Mutex<digest::Context>>,
+}
+
+impl<T> Hashed<T> {
+    fn new(inner: T) -> Hashed<T> {
+        Hashed { inner: inner, hash: Arc::new(Mutex::new(digest::Context::new(&digest::SHA256))) }
+    }
+}
+
+fn finish_hash(hash: &Arc<Mutex<digest::Context>>) -> digest::Digest {
+    hash.lock().unwrap().clone().finish()
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Hashed<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let before = buf.filled().len();
+        try_ready!(Pin::new(&mut this.inner).poll_read(cx, buf));
+        this.hash.lock().unwrap().update(&buf.filled()[before..]);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Hashed<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let n = try_ready!(Pin::new(&mut this.inner).poll_write(cx, buf));
+        this.hash.lock().unwrap().update(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+// Reads all of `input`, passes it to `f`, then writes what `f` produced to
+// `out`.
+fn buffered<In, Out, F>(input: In, out: Out, f: F) -> Task<Out>
+    where In: AsyncRead + Unpin + Send + 'static, Out: AsyncWrite + Unpin + Send + 'static,
+          F: FnOnce(&[u8], &mut SecretBuf) -> Result<()> + Send + 'static
+{
+    read_to_end(input).and_then(move |(_, buf)| {
+        let mut res = SecretBuf::new();
+        match f(&buf, &mut res) {
+            Ok(()) => write_all(out, res.into_inner()),
+            Err(e) => Task::ready(Err(e)),
+        }
+    })
+}
+
+/// Async versions of the `Cipher` algorithms.
+pub trait AsyncCipher: Cipher {
+    fn encrypt_async<In, Out>(key: Self::K, pt: In, ct: Out) -> Task<Out>
+        where Self: Sized + 'static, Self::K: Send + 'static,
+              In: AsyncRead + Unpin + Send + 'static, Out: AsyncWrite + Unpin + Send + 'static
+    {
+        buffered(pt, ct, move |pt, ct| Self::encrypt(key, &mut &pt[..], ct))
+    }
+
+    fn decrypt_async<In, Out>(key: Self::K, ct: In, pt: Out) -> Task<Out>
+        where Self: Sized + 'static, Self::K: Send + 'static,
+              In: AsyncRead + Unpin + Send + 'static, Out: AsyncWrite + Unpin + Send + 'static
+    {
+        buffered(ct, pt, move |ct, pt| Self::decrypt(key, &mut &ct[..], pt))
+    }
+}
+
+impl AsyncCipher for RingAes {}
+impl AsyncCipher for RingAes256 {}
+impl AsyncCipher for RingChaCha {}
+impl AsyncCipher for RingAesStream {}
+impl AsyncCipher for RingAes256Stream {}
+impl AsyncCipher for RingChaChaStream {}
+impl AsyncCipher for AesGcmSiv {}
+impl AsyncCipher for XChaCha {}
+
+/// Async versions of the `UpEncCtxtIndep` algorithms.
+pub trait AsyncUpEncCtxtIndep: UpEncCtxtIndep {
+    fn rekeygen_async<Out>(k1: Self::K, k2: Self::K, token: Out) -> Task<Out>
+        where Self: Sized + 'static, Self::K: Send + 'static,
+              Out: AsyncWrite + Unpin + Send + 'static
+    {
+        let mut buf = SecretBuf::new();
+        match Self::rekeygen(k1, k2, &mut buf) {
+            Ok(()) => write_all(token, buf.into_inner()),
+            Err(e) => Task::ready(Err(e)),
+        }
+    }
+
+    fn reencrypt_async<In1, In2, Out>(rk: In1, ct_old: In2, ct_new: Out) -> Task<Out>
+        where Self: Sized + 'static,
+              In1: AsyncRead + Unpin + Send + 'static, In2: AsyncRead + Unpin + Send + 'static,
+              Out: AsyncWrite + Unpin + Send + 'static
+    {
+        read_to_end(rk).and_then(move |(_, rk)| {
+            buffered(ct_old, ct_new, move |ct, out| Self::reencrypt(&mut &rk[..], &mut &ct[..], out))
+        })
+    }
+}
+
+// The key-homomorphic PRFs stream each block through `Blocks`.
+macro_rules! kh_prf_async {
+    ($name:ident) => (
+        impl AsyncCipher for $name {
+            fn encrypt_async<In, Out>(key: Self::K, pt: In, ct: Out) -> Task<Out>
+                where In: AsyncRead + Unpin + Send + 'static, Out: AsyncWrite + Unpin + Send + 'static
+            {
+                pump(pt, ct, Blocks::<Self>::new(Mode::Encrypt, key)).map(|(_, ct, _)| ct)
+            }
+
+            fn decrypt_async<In, Out>(key: Self::K, ct: In, pt: Out) -> Task<Out>
+                where In: AsyncRead + Unpin + Send + 'static, Out: AsyncWrite + Unpin + Send + 'static
+            {
+                pump(ct, pt, Blocks::<Self>::new(Mode::Decrypt, key)).map(|(_, pt, _)| pt)
+            }
+        }
+
+        impl AsyncUpEncCtxtIndep for $name {
+            fn reencrypt_async<In1, In2, Out>(rk: In1, ct_old: In2, ct_new: Out) -> Task<Out>
+                where In1: AsyncRead + Unpin + Send + 'static, In2: AsyncRead + Unpin + Send + 'static,
+                      Out: AsyncWrite + Unpin + Send + 'static
+            {
+                read_key(rk, || ErrorKind::InvalidToken).and_then(move |(_, rk_token)| {
+                    pump(ct_old, ct_new, Blocks::<Self>::new(Mode::Update, rk_token))
+                }).map(|(_, ct_new, _)| ct_new)
+            }
+        }
+    )
+}
+
+kh_prf_async!(KhPrf);
+kh_prf_async!(RistrettoKhPrf);
+kh_prf_async!(LwrKhPrf);
+
+/// Async versions of the `UpEnc` algorithms.
+pub trait AsyncUpEnc: UpEnc {
+    fn rekeygen_async<In, Out>(k1: Self::K, k2: Self::K, ct_hdr: In, token: Out) -> Task<Out>
+        where Self: Sized + 'static, Self::K: Send + 'static,
+              In: AsyncRead + Unpin + Send + 'static, Out: AsyncWrite + Unpin + Send + 'static
+    {
+        buffered(ct_hdr, token, move |hdr, token| Self::rekeygen(k1, k2, &mut &hdr[..], token))
+    }
+
+    /// Returns the header and body streams.
+    fn encrypt_async<In, Out>(key: Self::K, pt: In, ct_hdr: Out, ct_body: Out) -> Task<(Out, Out)>
+        where Self: Sized + 'static, Self::K: Send + 'static,
+              In: AsyncRead + Unpin + Send + 'static, Out: AsyncWrite + Unpin + Send + 'static
+    {
+        read_to_end(pt).and_then(move |(_, pt)| {
+            let mut hdr = Zeroizing::new(Vec::new());
+            let mut body = Zeroizing::new(Vec::new());
+            match Self::encrypt(key, &mut &pt[..], &mut *hdr, &mut *body) {
+                Ok(()) => write_pair(ct_hdr, hdr, ct_body, body),
+                Err(e) => Task::ready(Err(e)),
+            }
+        })
+    }
+
+    /// Returns the new header and body streams.
+    fn reencrypt_async<In, Out>(rk: In, ct1_hdr: In, ct1_body: In, ct2_hdr: Out, ct2_body: Out) -> Task<(Out, Out)>
+        where Self: Sized + 'static,
+              In: AsyncRead + Unpin + Send + 'static, Out: AsyncWrite + Unpin + Send + 'static
+    {
+        read_to_end(rk).and_then(move |(_, rk)| {
+            read_to_end(ct1_hdr).and_then(move |(_, ct1_hdr)| {
+                read_to_end(ct1_body).and_then(move |(_, ct1_body)| {
+                    let mut hdr = Zeroizing::new(Vec::new());
+                    let mut body = Zeroizing::new(Vec::new());
+                    match Self::reencrypt(&mut &rk[..], &mut &ct1_hdr[..], &mut &ct1_body[..], &mut *hdr, &mut *body) {
+                        Ok(()) => write_pair(ct2_hdr, hdr, ct2_body, body),
+                        Err(e) => Task::ready(Err(e)),
+                    }
+                })
+            })
+        })
+    }
+
+    fn decrypt_async<In, Out>(key: Self::K, ct_hdr: In, ct_body: In, pt: Out) -> Task<Out>
+        where Self: Sized + 'static, Self::K: Send + 'static,
+              In: AsyncRead + Unpin + Send + 'static, Out: AsyncWrite + Unpin + Send + 'static
+    {
+        read_to_end(ct_hdr).and_then(move |(_, hdr)| {
+            buffered(ct_body, pt, move |body, pt| Self::decrypt(key, &mut &hdr[..], &mut &body[..], pt))
+        })
+    }
+
+    // The `_aad` versions are buffered, as the streaming versions are only
+    // written for headers without associated data.
+
+    /// As `UpEnc::rekeygen_aad`.
+    fn rekeygen_aad_async<In, Out>(k1: Self::K, k2: Self::K, aad: Vec<u8>, ct_hdr: In, token: Out) -> Task<Out>
+        where Self: Sized + 'static, Self::K: Send + 'static,
+              In: AsyncRead + Unpin + Send + 'static, Out: AsyncWrite + Unpin + Send + 'static
+    {
+        buffered(ct_hdr, token, move |hdr, token| Self::rekeygen_aad(k1, k2, &aad, &mut &hdr[..], token))
+    }
+
+    /// As `UpEnc::encrypt_aad`. Returns the header and body streams.
+    fn encrypt_aad_async<In, Out>(key: Self::K, aad: Vec<u8>, pt: In, ct_hdr: Out, ct_body: Out) -> Task<(Out, Out)>
+        where Self: Sized + 'static, Self::K: Send + 'static,
+              In: AsyncRead + Unpin + Send + 'static, Out: AsyncWrite + Unpin + Send + 'static
+    {
+        read_to_end(pt).and_then(move |(_, pt)| {
+            let mut hdr = Zeroizing::new(Vec::new());
+            let mut body = Zeroizing::new(Vec::new());
+            match Self::encrypt_aad(key, &aad, &mut &pt[..], &mut *hdr, &mut *body) {
+                Ok(()) => write_pair(ct_hdr, hdr, ct_body, body),
+                Err(e) => Task::ready(Err(e)),
+            }
+        })
+    }
+
+    /// As `UpEnc::reencrypt_aad`. Returns the new header and body streams.
+    fn reencrypt_aad_async<In, Out>(rk: In, aad: Vec<u8>, ct1_hdr: In, ct1_body: In, ct2_hdr: Out, ct2_body: Out) -> Task<(Out, Out)>
+        where Self: Sized + 'static,
+              In: AsyncRead + Unpin + Send + 'static, Out: AsyncWrite + Unpin + Send + 'static
+    {
+        read_to_end(rk).and_then(move |(_, rk)| {
+            read_to_end(ct1_hdr).and_then(move |(_, ct1_hdr)| {
+                read_to_end(ct1_body).and_then(move |(_, ct1_body)| {
+                    let mut hdr = Zeroizing::new(Vec::new());
+                    let mut body = Zeroizing::new(Vec::new());
+                    match Self::reencrypt_aad(&mut &rk[..], &aad, &mut &ct1_hdr[..], &mut &ct1_body[..], &mut *hdr, &mut *body) {
+                        Ok(()) => write_pair(ct2_hdr, hdr, ct2_body, body),
+                        Err(e) => Task::ready(Err(e)),
+                    }
+                })
+            })
+        })
+    }
+
+    /// As `UpEnc::decrypt_aad`.
+    fn decrypt_aad_async<In, Out>(key: Self::K, aad: Vec<u8>, ct_hdr: In, ct_body: In, pt: Out) -> Task<Out>
+        where Self: Sized + 'static, Self::K: Send + 'static,
+              In: AsyncRead + Unpin + Send + 'static, Out: AsyncWrite + Unpin + Send + 'static
+    {
+        read_to_end(ct_hdr).and_then(move |(_, hdr)| {
+            buffered(ct_body, pt, move |body, pt| Self::decrypt_aad(key, &aad, &mut &hdr[..], &mut &body[..], pt))
+        })
+    }
+}
+
+// Writes a header and body, returning both streams.
+fn write_pair<Out>(ct_hdr: Out, hdr: Zeroizing<Vec<u8>>, ct_body: Out, body: Zeroizing<Vec<u8>>) -> Task<(Out, Out)>
+    where Out: AsyncWrite + Unpin + Send + 'static
+{
+    write_all(ct_hdr, hdr).and_then(move |ct_hdr| write_all(ct_body, body).map(move |ct_body| (ct_hdr, ct_body)))
+}
+
+// Decrypts a header written without associated data, as `read_hdr` in the
+// hybrid schemes.
+fn open_hdr<A: Cipher>(key: A::K, hdr: &[u8]) -> Result<SecretBuf> {
+    let mut buf = SecretBuf::new();
+    A::decrypt_aad(key, &[], &mut &hdr[..], &mut buf)?;
+    Ok(buf)
+}
+
+// Encrypts a header without associated data, as `write_hdr` in the hybrid
+// schemes.
+fn seal_hdr<A: Cipher>(key: A::K, buf: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+    let mut hdr = Zeroizing::new(Vec::new());
+    A::encrypt_aad(key, &[], &mut &buf[..], &mut *hdr)?;
+    Ok(hdr)
+}
+
+// Writes a key to a new buffer.
+fn key_bytes<K: Key>(key: &K) -> Result<SecretBuf> {
+    let mut buf = SecretBuf::new();
+    key.write_key(&mut buf)?;
+    Ok(buf)
+}
+
+// Writes the contents of a hybrid header, `chi || tau`, to a new buffer.
+fn hdr_bytes<K: Key>(chi: &K, tau: &[u8]) -> Result<SecretBuf> {
+    let mut buf = key_bytes(chi)?;
+    io::Write::write_all(&mut buf, tau)?;
+    Ok(buf)
+}
+
+// `ReCrypt` streams the body through `Blocks`, hashing the plaintext as it
+// goes. The headers and tokens are small, and are read into memory.
+impl<A, B> AsyncUpEnc for ReCrypt<A, B>
+    where A: Cipher + 'static, B: KeyHomomorphicPrf + 'static, B::K: Send + 'static
+{
+    fn encrypt_async<In, Out>(key: A::K, pt: In, ct_hdr: Out, ct_body: Out) -> Task<(Out, Out)>
+        where A::K: Send + 'static,
+              In: AsyncRead + Unpin + Send + 'static, Out: AsyncWrite + Unpin + Send + 'static
+    {
+        let x = B::keygen();
+        let y = B::keygen();
+        let chi = B::add_keys(&x, &y);
+        let y_buf = try_task!(key_bytes(&y));
+        let codec = Hashing::input(Blocks::<B>::new(Mode::Encrypt, x.clone()));
+
+        // Write y || C to the ciphertext body
+        write_all(ct_body, y_buf.into_inner()).and_then(move |ct_body| pump(pt, ct_body, codec)).and_then(move |(_, ct_body, codec)| {
+            let hm = codec.finish();
+            // The header contains chi || tau, where tau = h(m) + F(x, 0)
+            let buf = try_task!(hdr_bytes(&chi, &B::encrypt_tag(&x, hm.as_ref())));
+            let hdr = try_task!(seal_hdr::<A>(key, &buf));
+            write_all(ct_hdr, hdr).map(move |ct_hdr| (ct_hdr, ct_body))
+        })
+    }
+
+    fn reencrypt_async<In, Out>(rk: In, _: In, ct1_body: In, ct2_hdr: Out, ct2_body: Out) -> Task<(Out, Out)>
+        where In: AsyncRead + Unpin + Send + 'static, Out: AsyncWrite + Unpin + Send + 'static
+    {
+        read_to_end(rk).and_then(move |(_, token)| {
+            let mut reader = &token[..];
+            let x_new = try_task!(B::K::read_key(&mut reader).chain_err(|| ErrorKind::InvalidToken));
+            let y_new = try_task!(B::K::read_key(&mut reader).chain_err(|| ErrorKind::InvalidToken));
+            // can directly read out rest of rk to header
+            write_all(ct2_hdr, Zeroizing::new(reader.to_vec())).and_then(move |ct2_hdr| {
+                read_key::<B::K, _>(ct1_body, || ErrorKind::InvalidBodyKey).and_then(move |(ct1_body, y)| {
+                    let y_buf = try_task!(key_bytes(&B::add_keys(&y, &y_new)));
+                    write_all(ct2_body, y_buf.into_inner()).and_then(move |ct2_body| {
+                        pump(ct1_body, ct2_body, Blocks::<B>::new(Mode::Update, x_new))
+                    })
+                }).map(move |(_, ct2_body, _)| (ct2_hdr, ct2_body))
+            })
+        })
+    }
+
+    fn decrypt_async<In, Out>(key: A::K, ct_hdr: In, ct_body: In, pt: Out) -> Task<Out>
+        where A::K: Send + 'static,
+              In: AsyncRead + Unpin + Send + 'static, Out: AsyncWrite + Unpin + Send + 'static
+    {
+        read_to_end(ct_hdr).and_then(move |(_, hdr)| {
+            let buf = try_task!(open_hdr::<A>(key, &hdr));
+            let mut reader = &buf[..];
+            let chi = try_task!(B::K::read_key(&mut reader).chain_err(|| ErrorKind::InvalidHeader));
+            let tau = reader.to_vec();
+
+            read_key::<B::K, _>(ct_body, || ErrorKind::InvalidBodyKey).and_then(move |(ct_body, y)| {
+                let x = B::sub_keys(&chi, &y);
+                pump(ct_body, pt, Hashing::output(Blocks::<B>::new(Mode::Decrypt, x.clone())))
+                    .and_then(move |(_, pt, codec)| {
+                        let hm = codec.finish();
+                        // The plaintext is already written before the
+                        // integrity is checked, as for `UpEnc::decrypt`.
+                        let valid = try_task!(B::verify_tag(&x, hm.as_ref(), &tau)
+                            .chain_err(|| ErrorKind::InvalidHeader));
+                        if !valid {
+                            return Task::ready(Err(ErrorKind::IntegrityCheckFailed.into()));
+                        }
+                        Task::ready(Ok(pt))
+                    })
+            })
+        })
+    }
+}
+
+// `Kss` streams the body through `B`, hashing the ciphertext as it goes,
+// and only copies it on re-encryption.
+impl<A, B> AsyncUpEnc for Kss<A, B>
+    where A: Cipher + 'static, B: AsyncCipher + 'static, B::K: Send + 'static,
+          for<'a> &'a B::K: Add<Output=B::K>, for<'a> &'a B::K: Sub<Output=B::K>
+{
+    fn encrypt_async<In, Out>(key: A::K, pt: In, ct_hdr: Out, ct_body: Out) -> Task<(Out, Out)>
+        where A::K: Send + 'static,
+              In: AsyncRead + Unpin + Send + 'static, Out: AsyncWrite + Unpin + Send + 'static
+    {
+        let x = B::keygen();
+        let y = B::keygen();
+        let chi = &x + &y;
+        let y_buf = try_task!(key_bytes(&y));
+
+        write_all(ct_body, y_buf.into_inner()).and_then(move |ct_body| {
+            B::encrypt_async(x, pt, Hashed::new(ct_body))
+        }).and_then(move |ct_body| {
+            let tau = finish_hash(&ct_body.hash);
+            let buf = try_task!(hdr_bytes(&chi, tau.as_ref()));
+            let hdr = try_task!(seal_hdr::<A>(key, &buf));
+            write_all(ct_hdr, hdr).map(move |ct_hdr| (ct_hdr, ct_body.inner))
+        })
+    }
+
+    fn reencrypt_async<In, Out>(rk: In, _: In, ct1_body: In, ct2_hdr: Out, ct2_body: Out) -> Task<(Out, Out)>
+        where In: AsyncRead + Unpin + Send + 'static, Out: AsyncWrite + Unpin + Send + 'static
+    {
+        read_to_end(rk).and_then(move |(_, token)| {
+            let mut reader = &token[..];
+            let y_new = try_task!(B::K::read_key(&mut reader).chain_err(|| ErrorKind::InvalidToken));
+            let hdr = Zeroizing::new(reader.to_vec());
+
+            read_key::<B::K, _>(ct1_body, || ErrorKind::InvalidBodyKey).and_then(move |(ct1_body, y)| {
+                let y_buf = try_task!(key_bytes(&(&y + &y_new)));
+                // Write the rest of ct1 to ct2
+                write_all(ct2_body, y_buf.into_inner()).and_then(move |ct2_body| pump(ct1_body, ct2_body, PassThrough))
+            }).and_then(move |(_, ct2_body, _)| {
+                write_all(ct2_hdr, hdr).map(move |ct2_hdr| (ct2_hdr, ct2_body))
+            })
+        })
+    }
+
+    fn decrypt_async<In, Out>(key: A::K, ct_hdr: In, ct_body: In, pt: Out) -> Task<Out>
+        where A::K: Send + 'static,
+              In: AsyncRead + Unpin + Send + 'static, Out: AsyncWrite + Unpin + Send + 'static
+    {
+        read_to_end(ct_hdr).and_then(move |(_, hdr)| {
+            let buf = try_task!(open_hdr::<A>(key, &hdr));
+            let mut reader = &buf[..];
+            let chi = try_task!(B::K::read_key(&mut reader).chain_err(|| ErrorKind::InvalidHeader));
+            let tau = reader.to_vec();
+
+            read_key::<B::K, _>(ct_body, || ErrorKind::InvalidBodyKey).and_then(move |(ct_body, y)| {
+                let ct_body = Hashed::new(ct_body);
+                let hash = ct_body.hash.clone();
+                B::decrypt_async(&chi - &y, ct_body, pt).and_then(move |pt| {
+                    if finish_hash(&hash).as_ref() != &tau[..] {
+                        return Task::ready(Err(ErrorKind::IntegrityCheckFailed.into()));
+                    }
+                    Task::ready(Ok(pt))
+                })
+            })
+        })
+    }
+}
+
+impl<A: Cipher, B: KeyHomomorphicPrf> AsyncUpEnc for MerkleReCrypt<A, B> {}
+impl<C: Cipher> AsyncUpEnc for Naive<C> {}
+impl<C: Cipher> AsyncUpEnc for KemDem<C> {}
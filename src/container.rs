@@ -0,0 +1,499 @@
+//! Self-describing single-file ciphertexts.
+//!
+//! A container holds the header and body of an `UpEnc` ciphertext, along
+//! with the suite of scheme and ciphers which produced it:
+//!
+//! ```text
+//! MAGIC || VERSION || suite length (1 byte) || suite
+//!       || body chunks || 0u32 || header length (u32) || header
+//! ```
+//!
+//! Containers encrypted under a key derived from a passphrase are version
+//! `VERSION_KDF`, and record the key-derivation parameters after the suite,
+//! as `KdfParams` preceded by their length as a big-endian `u16`.
+//!
+//! The body is written as chunks of at most `CHUNK_SIZE` bytes, each
+//! preceded by its length as a big-endian `u32`, and ended by an empty chunk.
+//! The header comes last, since schemes such as `ReCrypt` only know it once
+//! the whole body has been written, so containers can be written to any
+//! stream. Readers seek past the body to find the header first.
+
+use super::errors::*;
+use super::UpEnc;
+use generic::{KemDem, Kss, MerkleReCrypt, Naive, ReCrypt};
+use io::{read_chunk, read_field, SecretBuf};
+use kdf::{DerivableKey, KdfParams};
+use kh_prf::{u64_to_u8, u8_to_u64};
+use {AesGcmSiv, KhPrf, LwrKhPrf, RingAes, RingAes256, RingAes256Stream, RingAesStream, RingChaCha, RingChaChaStream, RistrettoKhPrf, XChaCha};
+
+use std::cmp::min;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+
+use zeroize::Zeroizing;
+
+/// Bytes at the start of every container.
+pub const MAGIC: [u8; 8] = *b"RECRYPT\0";
+/// Version of the container format.
+pub const VERSION: u8 = 1;
+/// Version of the container format with key-derivation parameters.
+pub const VERSION_KDF: u8 = 2;
+/// Maximum size of a body chunk.
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Identifies a cipher or scheme, and the ciphers it is built from, in
+/// containers.
+///
+/// Each cipher is one byte, and each scheme is one byte followed by the
+/// suites of its ciphers, so suites are never a prefix of one another.
+pub trait SuiteId {
+    fn suite_id() -> Vec<u8>;
+}
+
+macro_rules! suite_id {
+    ($name:ident<$($param:ident),*>, $id:expr) => (
+        impl<$($param: SuiteId),*> SuiteId for $name<$($param),*> {
+            fn suite_id() -> Vec<u8> {
+                let mut id = vec![$id];
+                $( id.extend($param::suite_id()); )*
+                id
+            }
+        }
+    );
+    ($name:ident, $id:expr) => (
+        impl SuiteId for $name {
+            fn suite_id() -> Vec<u8> {
+                vec![$id]
+            }
+        }
+    );
+}
+
+suite_id!(RingAes, 0x01);
+suite_id!(RingChaCha, 0x02);
+suite_id!(RingAesStream, 0x03);
+suite_id!(RingChaChaStream, 0x04);
+suite_id!(AesGcmSiv, 0x05);
+suite_id!(XChaCha, 0x06);
+suite_id!(RingAes256, 0x07);
+suite_id!(RingAes256Stream, 0x08);
+suite_id!(KhPrf, 0x10);
+suite_id!(RistrettoKhPrf, 0x11);
+suite_id!(LwrKhPrf, 0x12);
+suite_id!(Naive<C>, 0x20);
+suite_id!(KemDem<C>, 0x21);
+suite_id!(Kss<A, B>, 0x22);
+suite_id!(ReCrypt<A, B>, 0x23);
+suite_id!(MerkleReCrypt<A, B>, 0x24);
+
+/// The `UpEnc` algorithms, reading and writing containers instead of a
+/// separate header and body.
+pub trait Container: UpEnc + SuiteId {
+    /// Encrypts `pt` as a container written to `ct`.
+    fn encrypt_container<In: Read, Out: Write>(key: Self::K, pt: &mut In, ct: &mut Out) -> Result<()>
+        where Self: Sized
+    {
+        encrypt::<Self, _, _>(key, None, None, pt, ct)
+    }
+
+    /// As `encrypt_container`, under the key derived from `password` with
+    /// `params`, which are recorded in the container.
+    fn encrypt_container_with_password<In: Read, Out: Write>(password: &[u8], params: &KdfParams, pt: &mut In, ct: &mut Out) -> Result<()>
+        where Self: Sized, Self::K: DerivableKey
+    {
+        encrypt::<Self, _, _>(params.derive_key(password)?, Some(params), None, pt, ct)
+    }
+
+    /// Writes a re-keying token for the ciphertext in `ct`.
+    fn rekeygen_container<In: Read + Seek, Out: Write>(k1: Self::K, k2: Self::K, ct: &mut In, token: &mut Out) -> Result<()>
+        where Self: Sized
+    {
+        let hdr = open(ct, &Self::suite_id())?;
+        Self::rekeygen(k1, k2, &mut &hdr[..], token)
+    }
+
+    /// Re-encrypts the container `ct1` under the token `rk`, writing the
+    /// new container to `ct2`.
+    ///
+    /// The new container does not record any key-derivation parameters, even
+    /// if `ct1` does; use `reencrypt_container_with_kdf` when the new key is
+    /// derived from a passphrase.
+    fn reencrypt_container<Tok: Read, In: Read + Seek, Out: Write>(rk: &mut Tok, ct1: &mut In, ct2: &mut Out) -> Result<()>
+        where Self: Sized
+    {
+        reencrypt::<Self, _, _, _>(rk, None, None, ct1, ct2)
+    }
+
+    /// As `reencrypt_container`, recording `params` as the parameters which
+    /// derive the new key.
+    fn reencrypt_container_with_kdf<Tok: Read, In: Read + Seek, Out: Write>(rk: &mut Tok, params: &KdfParams, ct1: &mut In, ct2: &mut Out) -> Result<()>
+        where Self: Sized
+    {
+        reencrypt::<Self, _, _, _>(rk, Some(params), None, ct1, ct2)
+    }
+
+    fn decrypt_container<In: Read + Seek, Out: Write>(key: Self::K, ct: &mut In, pt: &mut Out) -> Result<()>
+        where Self: Sized
+    {
+        let hdr = open(ct, &Self::suite_id())?;
+        let mut hdr = Section::Buffer(Cursor::new(hdr));
+        let mut body = Section::Stream(ChunkReader::new(ct));
+        Self::decrypt(key, &mut hdr, &mut body, pt)
+    }
+
+    /// As `decrypt_container`, under the key derived from `password` with the
+    /// parameters recorded in the container.
+    ///
+    /// The cost parameters are read from `ct`. Those beyond the limits of
+    /// `KdfParams::check` are rejected, but a container from an untrusted
+    /// source can still ask for a derivation up to those limits. Check them
+    /// with `read_kdf_params` first if that matters.
+    fn decrypt_container_with_password<In: Read + Seek, Out: Write>(password: &[u8], ct: &mut In, pt: &mut Out) -> Result<()>
+        where Self: Sized, Self::K: DerivableKey
+    {
+        let start = ct.seek(SeekFrom::Current(0))?;
+        let params = read_kdf_params(ct)?
+            .ok_or(ErrorKind::InvalidKdf("container has no key-derivation parameters"))?;
+        ct.seek(SeekFrom::Start(start))?;
+        Self::decrypt_container(params.derive_key(password)?, ct, pt)
+    }
+
+    /// As `encrypt_container`, binding the container to the associated data
+    /// `aad` as for `UpEnc::encrypt_aad`. The associated data is not stored
+    /// in the container, and must be given again to rotate or decrypt it.
+    fn encrypt_container_aad<In: Read, Out: Write>(key: Self::K, aad: &[u8], pt: &mut In, ct: &mut Out) -> Result<()>
+        where Self: Sized
+    {
+        encrypt::<Self, _, _>(key, None, Some(aad), pt, ct)
+    }
+
+    /// As `rekeygen_container`, for a container written with `aad`.
+    fn rekeygen_container_aad<In: Read + Seek, Out: Write>(k1: Self::K, k2: Self::K, aad: &[u8], ct: &mut In, token: &mut Out) -> Result<()>
+        where Self: Sized
+    {
+        let hdr = open(ct, &Self::suite_id())?;
+        Self::rekeygen_aad(k1, k2, aad, &mut &hdr[..], token)
+    }
+
+    /// As `reencrypt_container`, for a container written with `aad`.
+    fn reencrypt_container_aad<Tok: Read, In: Read + Seek, Out: Write>(rk: &mut Tok, aad: &[u8], ct1: &mut In, ct2: &mut Out) -> Result<()>
+        where Self: Sized
+    {
+        reencrypt::<Self, _, _, _>(rk, None, Some(aad), ct1, ct2)
+    }
+
+    /// As `decrypt_container`, for a container written with `aad`.
+    fn decrypt_container_aad<In: Read + Seek, Out: Write>(key: Self::K, aad: &[u8], ct: &mut In, pt: &mut Out) -> Result<()>
+        where Self: Sized
+    {
+        let hdr = open(ct, &Self::suite_id())?;
+        let mut hdr = Section::Buffer(Cursor::new(hdr));
+        let mut body = Section::Stream(ChunkReader::new(ct));
+        Self::decrypt_aad(key, aad, &mut hdr, &mut body, pt)
+    }
+}
+
+impl<S: UpEnc + SuiteId> Container for S {}
+
+fn encrypt<S: Container, In: Read, Out: Write>(key: S::K, kdf: Option<&KdfParams>, aad: Option<&[u8]>, pt: &mut In, ct: &mut Out) -> Result<()> {
+    write_preamble(ct, &S::suite_id(), kdf)?;
+    let hdr = {
+        let mut hdr = Section::Buffer(Cursor::new(Vec::new()));
+        let mut body = Section::Stream(ChunkWriter::new(&mut *ct));
+        match aad {
+            Some(aad) => S::encrypt_aad(key, aad, pt, &mut hdr, &mut body)?,
+            None => S::encrypt(key, pt, &mut hdr, &mut body)?,
+        }
+        body.finish()?;
+        hdr.into_buffer()
+    };
+    write_hdr(ct, &hdr)
+}
+
+fn reencrypt<S, Tok, In, Out>(rk: &mut Tok, kdf: Option<&KdfParams>, aad: Option<&[u8]>, ct1: &mut In, ct2: &mut Out) -> Result<()>
+    where S: Container, Tok: Read, In: Read + Seek, Out: Write
+{
+    let mut token = SecretBuf::new();
+    token.read_from(rk)?;
+    let hdr1 = open(ct1, &S::suite_id())?;
+
+    write_preamble(ct2, &S::suite_id(), kdf)?;
+    let hdr2 = {
+        let mut rk = Section::Secret(Cursor::new(token.into_inner()));
+        let mut hdr1 = Section::Buffer(Cursor::new(hdr1));
+        let mut body1 = Section::Stream(ChunkReader::new(&mut *ct1));
+        let mut hdr2 = Section::Buffer(Cursor::new(Vec::new()));
+        let mut body2 = Section::Stream(ChunkWriter::new(&mut *ct2));
+        match aad {
+            Some(aad) => S::reencrypt_aad(&mut rk, aad, &mut hdr1, &mut body1, &mut hdr2, &mut body2)?,
+            None => S::reencrypt(&mut rk, &mut hdr1, &mut body1, &mut hdr2, &mut body2)?,
+        }
+        body2.finish()?;
+        hdr2.into_buffer()
+    };
+    write_hdr(ct2, &hdr2)
+}
+
+/// Reads the suite identifying the scheme which wrote a container, leaving
+/// `ct` at the start of the body.
+pub fn read_suite<In: Read>(ct: &mut In) -> Result<Vec<u8>> {
+    Ok(read_preamble(ct)?.0)
+}
+
+/// Reads the key-derivation parameters recorded in a container, if any,
+/// leaving `ct` at the start of the body.
+pub fn read_kdf_params<In: Read>(ct: &mut In) -> Result<Option<KdfParams>> {
+    Ok(read_preamble(ct)?.1)
+}
+
+fn read_preamble<In: Read>(ct: &mut In) -> Result<(Vec<u8>, Option<KdfParams>)> {
+    let mut magic = [0u8; 8];
+    read_field(ct, &mut magic, 0)?;
+    if magic != MAGIC {
+        return Err(ErrorKind::InvalidContainer("not a ReCrypt container").into());
+    }
+    let mut version = [0u8; 2];
+    read_field(ct, &mut version, 8)?;
+    if version[0] != VERSION && version[0] != VERSION_KDF {
+        return Err(ErrorKind::UnsupportedContainerVersion(version[0]).into());
+    }
+    let mut suite = vec![0u8; version[1] as usize];
+    read_field(ct, &mut suite, 10)?;
+    if version[0] == VERSION {
+        return Ok((suite, None));
+    }
+
+    let offset = 10 + suite.len() as u64;
+    let mut len = [0u8; 8];
+    read_field(ct, &mut len[6..], offset)?;
+    let mut buf = vec![0u8; u8_to_u64(len) as usize];
+    read_field(ct, &mut buf, offset + 2)?;
+    let mut reader = &buf[..];
+    // Parameters which parse but fail `KdfParams::check` stay `InvalidKdf`.
+    let params = KdfParams::read(&mut reader).map_err(|e| {
+        let invalid = match *e.kind() {
+            ErrorKind::InvalidKdf(_) => true,
+            _ => false,
+        };
+        if invalid { e } else { e.chain_err(|| ErrorKind::InvalidContainer("malformed key-derivation parameters")) }
+    })?;
+    if !reader.is_empty() {
+        return Err(ErrorKind::InvalidContainer("malformed key-derivation parameters").into());
+    }
+    Ok((suite, Some(params)))
+}
+
+fn write_preamble<Out: Write>(ct: &mut Out, suite: &[u8], kdf: Option<&KdfParams>) -> Result<()> {
+    if suite.len() > 255 {
+        return Err(ErrorKind::InvalidContainer("suite identifier too long").into());
+    }
+    ct.write_all(&MAGIC)?;
+    let version = if kdf.is_some() { VERSION_KDF } else { VERSION };
+    ct.write_all(&[version, suite.len() as u8])?;
+    ct.write_all(suite)?;
+    if let Some(params) = kdf {
+        let mut buf = Vec::new();
+        params.write(&mut buf)?;
+        ct.write_all(&u64_to_u8(buf.len() as u64)[6..])?;
+        ct.write_all(&buf)?;
+    }
+    Ok(())
+}
+
+fn write_hdr<Out: Write>(ct: &mut Out, hdr: &[u8]) -> Result<()> {
+    if hdr.len() > u32::max_value() as usize {
+        return Err(ErrorKind::InvalidContainer("header too long").into());
+    }
+    ct.write_all(&u32_to_u8(hdr.len() as u32))?;
+    ct.write_all(hdr)?;
+    ct.flush()?;
+    Ok(())
+}
+
+// Checks the suite, returns the header from after the body, and leaves `ct`
+// at the start of the body.
+fn open<In: Read + Seek>(ct: &mut In, suite: &[u8]) -> Result<Vec<u8>> {
+    let start = ct.seek(SeekFrom::Current(0))?;
+    let found = read_suite(ct)?;
+    if found != suite {
+        return Err(ErrorKind::SuiteMismatch(found).into());
+    }
+    let body = ct.seek(SeekFrom::Current(0))?;
+    let end = ct.seek(SeekFrom::End(0))?;
+    ct.seek(SeekFrom::Start(body))?;
+
+    // Skip over the body chunks, checking that each is complete.
+    let mut pos = body;
+    loop {
+        let len = read_len(ct, pos - start)?;
+        pos += 4;
+        if len == 0 {
+            break;
+        }
+        if pos + len > end {
+            return Err(ErrorKind::TruncatedCiphertext(end - start).into());
+        }
+        pos = ct.seek(SeekFrom::Current(len as i64))?;
+    }
+
+    let len = read_len(ct, pos - start)?;
+    pos += 4;
+    if pos + len > end {
+        return Err(ErrorKind::TruncatedCiphertext(end - start).into());
+    }
+    if pos + len < end {
+        return Err(ErrorKind::InvalidContainer("data after the header").into());
+    }
+    let hdr = read_chunk(ct, len as usize)?;
+    ct.seek(SeekFrom::Start(body))?;
+    Ok(hdr)
+}
+
+fn read_len<In: Read>(ct: &mut In, offset: u64) -> Result<u64> {
+    let mut len = [0u8; 4];
+    read_field(ct, &mut len, offset)?;
+    let mut be = [0u8; 8];
+    be[4..].copy_from_slice(&len);
+    Ok(u8_to_u64(be))
+}
+
+fn u32_to_u8(v: u32) -> [u8; 4] {
+    [(v >> 24) as u8, (v >> 16) as u8, (v >> 8) as u8, v as u8]
+}
+
+// The header and body passed to a scheme must share a type, so the header
+// is buffered while the body is streamed through the same type. A token is
+// buffered as a secret, so that it is wiped once the scheme is done with it.
+enum Section<T> {
+    Buffer(Cursor<Vec<u8>>),
+    Secret(Cursor<Zeroizing<Vec<u8>>>),
+    Stream(T),
+}
+
+impl<T> Section<T> {
+    fn into_buffer(self) -> Vec<u8> {
+        match self {
+            Section::Buffer(buf) => buf.into_inner(),
+            Section::Secret(_) | Section::Stream(_) => unreachable!("the header is always buffered"),
+        }
+    }
+}
+
+impl<'a, W: Write> Section<ChunkWriter<'a, W>> {
+    fn finish(self) -> Result<()> {
+        match self {
+            Section::Buffer(_) | Section::Secret(_) => Ok(()),
+            Section::Stream(writer) => writer.finish(),
+        }
+    }
+}
+
+impl<T: Read> Read for Section<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            Section::Buffer(ref mut inner) => inner.read(buf),
+            Section::Secret(ref mut inner) => inner.read(buf),
+            Section::Stream(ref mut inner) => inner.read(buf),
+        }
+    }
+}
+
+impl<T: Write> Write for Section<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Section::Buffer(ref mut inner) => inner.write(buf),
+            Section::Secret(_) => Err(io::Error::new(io::ErrorKind::Other, "a token section is read-only")),
+            Section::Stream(ref mut inner) => inner.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Section::Buffer(ref mut inner) => inner.flush(),
+            Section::Secret(_) => Ok(()),
+            Section::Stream(ref mut inner) => inner.flush(),
+        }
+    }
+}
+
+// Writes the body as length-prefixed chunks.
+struct ChunkWriter<'a, W: 'a> {
+    inner: &'a mut W,
+    buf: Vec<u8>,
+}
+
+impl<'a, W: Write> ChunkWriter<'a, W> {
+    fn new(inner: &'a mut W) -> ChunkWriter<'a, W> {
+        ChunkWriter { inner: inner, buf: Vec::with_capacity(CHUNK_SIZE) }
+    }
+
+    fn write_chunk(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.inner.write_all(&u32_to_u8(self.buf.len() as u32))?;
+            self.inner.write_all(&self.buf)?;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+
+    // Writes any buffered data, then the empty chunk ending the body.
+    fn finish(mut self) -> Result<()> {
+        self.write_chunk()?;
+        self.inner.write_all(&[0u8; 4])?;
+        Ok(())
+    }
+}
+
+impl<'a, W: Write> Write for ChunkWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = min(buf.len(), CHUNK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&buf[..n]);
+        if self.buf.len() == CHUNK_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_chunk()?;
+        self.inner.flush()
+    }
+}
+
+// Reads the body back out of its chunks, which `open` has already checked.
+struct ChunkReader<'a, R: 'a> {
+    inner: &'a mut R,
+    remaining: u64,
+    done: bool,
+}
+
+impl<'a, R: Read> ChunkReader<'a, R> {
+    fn new(inner: &'a mut R) -> ChunkReader<'a, R> {
+        ChunkReader { inner: inner, remaining: 0, done: false }
+    }
+}
+
+impl<'a, R: Read> Read for ChunkReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            let mut len = [0u8; 4];
+            self.inner.read_exact(&mut len)?;
+            let mut be = [0u8; 8];
+            be[4..].copy_from_slice(&len);
+            self.remaining = u8_to_u64(be);
+            if self.remaining == 0 {
+                self.done = true;
+                return Ok(0);
+            }
+        }
+        let n = min(buf.len() as u64, self.remaining) as usize;
+        let n = self.inner.read(&mut buf[..n])?;
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "container ended inside a body chunk"));
+        }
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
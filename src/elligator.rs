@@ -0,0 +1,110 @@
+//! Elligator 2 on curve25519, for the `Legacy` hash suite of `KhPrf`.
+//!
+//! `KhPrf` originally took this map from a fork of `curve25519_dalek`, which
+//! no longer resolves, so it is rebuilt here on the field arithmetic from
+//! `hash_to_curve`. The map is the one from section 5 of "Elligator:
+//! Elliptic-curve points indistinguishable from uniform random strings"
+//! (Bernstein, Hamburg, Krasnova and Lange), with 2 as the non-square and
+//! the result moved to edwards25519 by the rational map.
+//!
+//! A representative `r` and its negation map to the same point, so the
+//! inverse returns the one in `[0, (p - 1) / 2]`. `KhPrf` only encodes
+//! 31-byte blocks, which are all in this range.
+
+use dalek::constants::EIGHT_TORSION;
+use dalek::edwards::EdwardsPoint;
+
+use hash_to_curve::{montgomery_to_edwards, FieldElement, SQRT_MINUS_486664};
+
+// The curve25519 coefficient A.
+const A: u64 = 486662;
+
+/// Maps the little-endian field element `bytes` to a point on edwards25519.
+/// The top bit is ignored.
+pub fn from_uniform_representative(bytes: &[u8; 32]) -> EdwardsPoint {
+    let one = FieldElement::from_u64(1);
+    let a = FieldElement::from_u64(A);
+    let r = FieldElement::from_bytes(bytes);
+
+    // w = -A / (1 + 2r^2), which is never a pole since -1/2 is not a square.
+    let two_r2 = &FieldElement::from_u64(2) * &r.square();
+    let w = -&(&a * &(&one + &two_r2).invert());
+    let gw = &(&(&(&w + &a) * &w) + &one) * &w;
+
+    // Take u = w if w is on the curve, and u = -w - A (with
+    // g(u) = 2r^2 * g(w)) otherwise. v is negative exactly in the first case.
+    let e = gw.is_square();
+    let (u, v2) = if e { (w, gw) } else { (&(-&w) - &a, &two_r2 * &gw) };
+    let mut v = v2.sqrt();
+    if v.is_negative() != e {
+        v = -&v;
+    }
+
+    // Only r = 0 gives u = 0, which is (0, 0) on curve25519. The rational
+    // map in `hash_to_curve` sends this to the identity, as RFC 9380 asks,
+    // rather than to the point (0, -1) of order 2.
+    if u.is_zero() {
+        return EIGHT_TORSION[4];
+    }
+    montgomery_to_edwards(&u, &v)
+}
+
+/// Inverts `from_uniform_representative`, returning the representative in
+/// `[0, (p - 1) / 2]`, or `None` if `point` is not in the image of the map.
+pub fn to_uniform_representative(point: &EdwardsPoint) -> Option<[u8; 32]> {
+    let one = FieldElement::from_u64(1);
+    let a = FieldElement::from_u64(A);
+
+    // Recover x from the compressed point: x^2 = (y^2 - 1) / (d y^2 + 1),
+    // with d = -121665 / 121666.
+    let compressed = point.compress();
+    let bytes = compressed.as_bytes();
+    let y = FieldElement::from_bytes(bytes);
+    let d = -&(&FieldElement::from_u64(121665) * &FieldElement::from_u64(121666).invert());
+    let y2 = y.square();
+    let mut x = (&(&y2 - &one) * &(&(&d * &y2) + &one).invert()).sqrt();
+    if x.sgn0() != bytes[31] >> 7 {
+        x = -&x;
+    }
+
+    // The points with x = 0 are the identity, which is not in the image, and
+    // (0, -1), which is the image of 0.
+    if x.is_zero() {
+        return if (&y + &one).is_zero() { Some([0u8; 32]) } else { None };
+    }
+
+    // Back to curve25519: u = (1 + y) / (1 - y) and v = sqrt(-486664) u / x.
+    let u = &(&one + &y) * &(&one - &y).invert();
+    let v = &(&SQRT_MINUS_486664 * &u) * &x.invert();
+
+    // r^2 = -(u + A) / 2u if v is negative, and -u / 2(u + A) otherwise.
+    let u_plus_a = &u + &a;
+    let (num, den) = if v.is_negative() { (u_plus_a, u) } else { (u, u_plus_a) };
+    if den.is_zero() {
+        return None;
+    }
+    let r2 = -&(&num * &(&FieldElement::from_u64(2) * &den).invert());
+    if !r2.is_square() {
+        return None;
+    }
+    let mut r = r2.sqrt();
+    if r.is_negative() {
+        r = -&r;
+    }
+    Some(r.to_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn representative_rt() {
+        for &fill in [0x00u8, 0x01, 0x5a, 0xff].iter() {
+            let mut bytes = [fill; 32];
+            bytes[31] = 0;
+            let point = from_uniform_representative(&bytes);
+            assert_eq!(to_uniform_representative(&point), Some(bytes));
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use super::errors::*;
+use kh_prf::{u64_to_u8, u8_to_u64};
+
+use std::io::{Read, Write};
+use std::ops::Range;
+
+/// Records which key epoch each block of a ciphertext is under, so that a
+/// ciphertext can be rotated a range of blocks at a time.
+///
+/// Stored as runs of blocks: each `(start, epoch)` covers the blocks from
+/// `start` up to the start of the next run, and the last run has no end.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyEpochs {
+    runs: Vec<(u64, u32)>,
+}
+
+impl KeyEpochs {
+    /// All blocks under `epoch`.
+    pub fn new(epoch: u32) -> KeyEpochs {
+        KeyEpochs { runs: vec![(0, epoch)] }
+    }
+
+    /// The epoch of block `index`.
+    pub fn epoch(&self, index: u64) -> u32 {
+        self.runs.iter().rev()
+            .find(|&&(start, _)| start <= index)
+            .map(|&(_, epoch)| epoch)
+            .expect("runs start from block 0")
+    }
+
+    /// The distinct epochs of the blocks in `blocks`, in block order.
+    pub fn epochs_in(&self, blocks: Range<u64>) -> Vec<u32> {
+        if blocks.start >= blocks.end {
+            return Vec::new();
+        }
+        let mut epochs = vec![self.epoch(blocks.start)];
+        for &(start, epoch) in &self.runs {
+            if start > blocks.start && start < blocks.end && !epochs.contains(&epoch) {
+                epochs.push(epoch);
+            }
+        }
+        epochs
+    }
+
+    /// Moves the blocks in `blocks` to `epoch`.
+    pub fn set(&mut self, blocks: Range<u64>, epoch: u32) {
+        if blocks.start >= blocks.end {
+            return;
+        }
+        let after = self.epoch(blocks.end);
+        let mut runs: Vec<(u64, u32)> = self.runs.iter().cloned()
+            .filter(|&(start, _)| start < blocks.start)
+            .collect();
+        runs.push((blocks.start, epoch));
+        runs.push((blocks.end, after));
+        runs.extend(self.runs.iter().cloned().filter(|&(start, _)| start > blocks.end));
+
+        // Merge neighbouring runs under the same epoch.
+        runs.dedup_by(|next, prev| next.1 == prev.1);
+        self.runs = runs;
+    }
+
+    /// Reads the runs, as a count followed by `start || epoch` pairs.
+    pub fn read_epochs<In: Read>(epochs_in: &mut In) -> Result<KeyEpochs> {
+        let mut buf = [0u8; 8];
+        epochs_in.read_exact(&mut buf)?;
+        let n = u8_to_u64(buf);
+        if n == 0 {
+            return Err(ErrorKind::InvalidEpochs("malformed key epochs").into());
+        }
+
+        let mut runs = Vec::new();
+        for _ in 0..n {
+            let mut start = [0u8; 8];
+            let mut epoch = [0u8; 4];
+            epochs_in.read_exact(&mut start)?;
+            epochs_in.read_exact(&mut epoch)?;
+            let start = u8_to_u64(start);
+            let epoch = (epoch[0] as u32) << 24 | (epoch[1] as u32) << 16
+                | (epoch[2] as u32) << 8 | epoch[3] as u32;
+
+            // Runs start at block 0 and are in order.
+            let valid = match runs.last() {
+                None => start == 0,
+                Some(&(prev, _)) => start > prev,
+            };
+            if !valid {
+                return Err(ErrorKind::InvalidEpochs("malformed key epochs").into());
+            }
+            runs.push((start, epoch));
+        }
+        Ok(KeyEpochs { runs: runs })
+    }
+
+    /// Writes the runs in the format read by `read_epochs`.
+    pub fn write_epochs<Out: Write>(&self, epochs_out: &mut Out) -> Result<()> {
+        epochs_out.write_all(&u64_to_u8(self.runs.len() as u64))?;
+        for &(start, epoch) in &self.runs {
+            epochs_out.write_all(&u64_to_u8(start))?;
+            epochs_out.write_all(&[(epoch >> 24) as u8, (epoch >> 16) as u8,
+                                   (epoch >> 8) as u8, epoch as u8])?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_merges_runs() {
+        let mut epochs = KeyEpochs::new(0);
+        epochs.set(10..20, 1);
+        epochs.set(30..40, 1);
+        assert_eq!(epochs.epochs_in(0..50), vec![0, 1]);
+        assert_eq!(epochs.epoch(15), 1);
+        assert_eq!(epochs.epoch(25), 0);
+
+        epochs.set(20..30, 1);
+        assert_eq!(epochs.runs, vec![(0, 0), (10, 1), (40, 0)]);
+        epochs.set(0..10, 1);
+        epochs.set(40..50, 1);
+        assert_eq!(epochs.runs, vec![(0, 1), (50, 0)]);
+        assert_eq!(epochs.epochs_in(0..50), vec![1]);
+    }
+
+    #[test]
+    fn epochs_rt() {
+        let mut epochs = KeyEpochs::new(3);
+        epochs.set(5..7, 4);
+        let mut buf = Vec::new();
+        epochs.write_epochs(&mut buf).unwrap();
+        assert_eq!(KeyEpochs::read_epochs(&mut &buf[..]).unwrap(), epochs);
+
+        // Runs out of order
+        buf[8 + 12 + 7] = 0;
+        assert!(KeyEpochs::read_epochs(&mut &buf[..]).is_err());
+    }
+}
@@ -1,10 +1,12 @@
 use super::super::*;
-use super::super::kh_prf::KhKey;
 use ::io::*;
 
-use std::io::{Read, Write, BufReader, BufWriter, Cursor};
+use std::cmp::max;
+use std::io::{Read, Write, Seek, SeekFrom, BufReader, BufWriter};
 use std::marker::PhantomData;
-use std::ops::{Add,Sub};
+use std::ops::{Add, Range, Sub};
+
+use ring::rand::{self, SecureRandom};
 
 /// `KSS` Scheme: KEM-DEM with Secret Sharing
 ///
@@ -31,11 +33,80 @@ pub struct Kss<A, B>{
 /// This updatable encryption scheme meets the strongest set of security notions.
 /// On an update (`rekeygen` followed by `reencrypt`), the entire ciphertext
 /// is "refreshed", resulting in an entirely refreshed ciphertext.
+///
+/// `A` is an authenticated encryption scheme for the header, and `B` is any
+/// `KeyHomomorphicPrf` for the body.
 pub struct ReCrypt<A, B>{
     kem_cipher: PhantomData<A>,
     upenc_cipher: PhantomData<B>
 }
 
+// Headers written by the `_aad` methods start with a random binding value,
+// which stays with the object when it is re-keyed, and are encrypted with the
+// binding value and the associated data as the AEAD's associated data. The
+// binding value reveals nothing about the associated data, and lets a token
+// be checked against the ciphertext it is applied to.
+const BINDING_SIZE: usize = 16;
+
+// A fresh binding value for a header bound to `aad`, or none if there is no
+// associated data.
+fn new_binding(aad: Option<&[u8]>) -> Vec<u8> {
+    if aad.is_none() {
+        return Vec::new();
+    }
+    let mut bind = vec![0u8; BINDING_SIZE];
+    rand::SystemRandom::new().fill(&mut bind).expect("could not generate random bytes for binding value");
+    bind
+}
+
+// Checks that the header in a token has the same binding value as the header
+// `ct1_hdr` it replaces. Does nothing for headers written without associated
+// data.
+fn check_binding<In: Read>(aad: Option<&[u8]>, token_hdr: &[u8], ct1_hdr: &mut In) -> Result<()> {
+    if aad.is_some() {
+        let mut bind = vec![0u8; BINDING_SIZE];
+        ct1_hdr.read_exact(&mut bind).chain_err(|| ErrorKind::AssociatedDataMismatch)?;
+        if !token_hdr.starts_with(&bind) {
+            return Err(ErrorKind::AssociatedDataMismatch.into());
+        }
+    }
+    Ok(())
+}
+
+// Writes the header `E(key, buf)`, bound to `aad` with the binding value
+// `bind` if there is any.
+fn write_hdr<A: Cipher, Out: Write>(key: A::K, aad: Option<&[u8]>, bind: &[u8], buf: &[u8], ct_hdr: &mut Out) -> Result<()> {
+    match aad {
+        Some(aad) => {
+            ct_hdr.write_all(bind)?;
+            A::encrypt_aad(key, &[bind, aad].concat(), &mut &buf[..], ct_hdr)
+        },
+        None => A::encrypt_aad(key, &[], &mut &buf[..], ct_hdr),
+    }
+}
+
+// Reads the header written by `write_hdr`, returning its contents and the
+// binding value, which is empty without associated data.
+fn read_bound_hdr<A: Cipher, In: Read>(key: A::K, aad: Option<&[u8]>, ct_hdr: &mut In) -> Result<(SecretBuf, Vec<u8>)> {
+    let mut buf = SecretBuf::new();
+    match aad {
+        Some(aad) => {
+            let mut bind = vec![0u8; BINDING_SIZE];
+            read_field(ct_hdr, &mut bind, 0)?;
+            A::decrypt_aad(key, &[&bind[..], aad].concat(), ct_hdr, &mut buf)?;
+            Ok((buf, bind))
+        },
+        None => {
+            A::decrypt_aad(key, &[], ct_hdr, &mut buf)?;
+            Ok((buf, Vec::new()))
+        },
+    }
+}
+
+fn read_hdr<A: Cipher, In: Read>(key: A::K, aad: Option<&[u8]>, ct_hdr: &mut In) -> Result<SecretBuf> {
+    Ok(read_bound_hdr::<A, _>(key, aad, ct_hdr)?.0)
+}
+
 impl<A: Cipher, B: Cipher> UpEnc for Kss<A,B>
     where for<'a> &'a B::K: Add<Output=B::K>, for<'a> &'a B::K: Sub<Output=B::K>
 {
@@ -49,29 +120,64 @@ impl<A: Cipher, B: Cipher> UpEnc for Kss<A,B>
 
     /* Writes a re-keying token to a file for a pair of keys and a ciphertext */
     fn rekeygen<In: Read, Out: Write>(k1: Self::K, k2: Self::K, ct_hdr: &mut In, token: &mut Out) -> Result<()> {
-        let mut buf = Vec::new();
+        Self::rekeygen_with(k1, k2, None, ct_hdr, token)
+    }
+
+    fn encrypt<In: Read, Out: Write>(key: Self::K, pt: &mut In, ct_hdr: &mut Out, ct_body: &mut Out) -> Result<()> {
+        Self::encrypt_with(key, None, pt, ct_hdr, ct_body)
+    }
+
+    fn reencrypt<In: Read, Out: Write>(rk: &mut In, ct1_hdr: &mut In, ct1_body: &mut In, ct2_hdr: &mut Out, ct2_body: &mut Out) -> Result<()> {
+        Self::reencrypt_with(rk, None, ct1_hdr, ct1_body, ct2_hdr, ct2_body)
+    }
+
+    fn decrypt<In: Read, Out: Write>(key: Self::K, ct_hdr: &mut In, ct_body: &mut In, pt: &mut Out) -> Result<()> {
+        Self::decrypt_with(key, None, ct_hdr, ct_body, pt)
+    }
+
+    fn encrypt_aad<In: Read, Out: Write>(key: Self::K, aad: &[u8], pt: &mut In, ct_hdr: &mut Out, ct_body: &mut Out) -> Result<()> {
+        Self::encrypt_with(key, Some(aad), pt, ct_hdr, ct_body)
+    }
+
+    fn rekeygen_aad<In: Read, Out: Write>(k1: Self::K, k2: Self::K, aad: &[u8], ct_hdr: &mut In, token: &mut Out) -> Result<()> {
+        Self::rekeygen_with(k1, k2, Some(aad), ct_hdr, token)
+    }
+
+    fn reencrypt_aad<In: Read, Out: Write>(rk: &mut In, aad: &[u8], ct1_hdr: &mut In, ct1_body: &mut In, ct2_hdr: &mut Out, ct2_body: &mut Out) -> Result<()> {
+        Self::reencrypt_with(rk, Some(aad), ct1_hdr, ct1_body, ct2_hdr, ct2_body)
+    }
+
+    fn decrypt_aad<In: Read, Out: Write>(key: Self::K, aad: &[u8], ct_hdr: &mut In, ct_body: &mut In, pt: &mut Out) -> Result<()> {
+        Self::decrypt_with(key, Some(aad), ct_hdr, ct_body, pt)
+    }
+}
+
+impl<A: Cipher, B: Cipher> Kss<A, B>
+    where for<'a> &'a B::K: Add<Output=B::K>, for<'a> &'a B::K: Sub<Output=B::K>
+{
+    fn rekeygen_with<In: Read, Out: Write>(k1: A::K, k2: A::K, aad: Option<&[u8]>, ct_hdr: &mut In, token: &mut Out) -> Result<()> {
         // buf contains chi || tau
-        A::decrypt(k1, ct_hdr, &mut buf)?;
+        let (hdr_buf, bind) = read_bound_hdr::<A, _>(k1, aad, ct_hdr)?;
 
         let y_new = B::keygen();
-        let mut reader = Cursor::new(buf);
-        let chi = B::K::read_key(&mut reader)?;
+        let mut reader = &hdr_buf[..];
+        let chi = B::K::read_key(&mut reader).chain_err(|| ErrorKind::InvalidHeader)?;
         let rk = &chi + &y_new;
-        buf = Vec::new();
+        let mut buf = SecretBuf::new();
         rk.write_key(&mut buf)?;
         // buf should contain (chi' || tau)
-        reader.read_to_end(&mut buf)?;
+        buf.read_from(&mut reader)?;
         // Write out to token y'
         y_new.write_key(token)?;
         // Write out to token E(k2, chi' || tau)
-        A::encrypt(k2, &mut (&buf[..]), token)
+        write_hdr::<A, _>(k2, aad, &bind, &buf, token)
     }
 
-    fn encrypt<In: Read, Out: Write>(key: Self::K, pt: &mut In, ct_hdr: &mut Out, ct_body: &mut Out) -> Result<()> {
+    fn encrypt_with<In: Read, Out: Write>(key: A::K, aad: Option<&[u8]>, pt: &mut In, ct_hdr: &mut Out, ct_body: &mut Out) -> Result<()> {
         let x = B::keygen();
         let y = B::keygen();
         let chi = &x + &y;
-        let mut buf = Vec::new();
+        let mut buf = SecretBuf::new();
         chi.write_key(&mut buf)?;
         y.write_key(ct_body)?;
         // Computes the hash as each ciphertext block is output
@@ -79,23 +185,24 @@ impl<A: Cipher, B: Cipher> UpEnc for Kss<A,B>
         B::encrypt(x, pt, &mut hash_ct)?;
         let tau = hash_ct.finish();
         buf.write_all(tau.as_ref())?;
-        A::encrypt(key, &mut (&buf[..]), ct_hdr)
+        write_hdr::<A, _>(key, aad, &new_binding(aad), &buf, ct_hdr)
     }
 
-    fn reencrypt<In: Read, Out: Write>(rk: &mut In, _: &mut In, ct1_body: &mut In, ct2_hdr: &mut Out, ct2_body: &mut Out) -> Result<()> {
+    fn reencrypt_with<In: Read, Out: Write>(rk: &mut In, aad: Option<&[u8]>, ct1_hdr: &mut In, ct1_body: &mut In, ct2_hdr: &mut Out, ct2_body: &mut Out) -> Result<()> {
         let mut buf = Vec::new();
-        let y_new = B::K::read_key(rk)?;
+        let y_new = B::K::read_key(rk).chain_err(|| ErrorKind::InvalidToken)?;
         rk.read_to_end(&mut buf)?;
+        check_binding(aad, &buf, ct1_hdr)?;
 
         let mut reader = BufReader::new(ct1_body);
-        let y = B::K::read_key(&mut reader)?;
+        let y = B::K::read_key(&mut reader).chain_err(|| ErrorKind::InvalidBodyKey)?;
         let mut writer = BufWriter::new(ct2_body);
 
         (&y + &y_new).write_key(&mut writer)?;
 
         // Write the rest of ct1 to ct2
         loop {
-            let chunk = read_chunk(&mut reader, 128).unwrap();
+            let chunk = read_chunk(&mut reader, 128)?;
             match chunk.len() {
                 // EOF
                 0 => break,
@@ -112,31 +219,28 @@ impl<A: Cipher, B: Cipher> UpEnc for Kss<A,B>
         Ok(())
     }
 
-    fn decrypt<In: Read, Out: Write>(key: Self::K, ct_hdr: &mut In, ct_body: &mut In, pt: &mut Out) -> Result<()> {
-        let mut buf = Vec::new();
-        A::decrypt(key, ct_hdr, &mut buf)?;
+    fn decrypt_with<In: Read, Out: Write>(key: A::K, aad: Option<&[u8]>, ct_hdr: &mut In, ct_body: &mut In, pt: &mut Out) -> Result<()> {
+        let hdr_buf = read_hdr::<A, _>(key, aad, ct_hdr)?;
 
-        let mut reader = Cursor::new(buf);
-        let chi = B::K::read_key(&mut reader)?;
-        buf = Vec::new();
+        let mut reader = &hdr_buf[..];
+        let chi = B::K::read_key(&mut reader).chain_err(|| ErrorKind::InvalidHeader)?;
+        let mut buf = Vec::new();
         reader.read_to_end(&mut buf)?;
 
         let mut ct_reader = BufReader::new(ct_body);
-        let y = B::K::read_key(&mut ct_reader)?;
+        let y = B::K::read_key(&mut ct_reader).chain_err(|| ErrorKind::InvalidBodyKey)?;
 
         let mut ct_and_hash = RwAndHash::new(ct_reader);
         B::decrypt(&chi - &y, &mut ct_and_hash, pt)?;
         let tau_check = ct_and_hash.finish();
         if buf != tau_check.as_ref() {
-            return Err("integrity check failed".into());
+            return Err(ErrorKind::IntegrityCheckFailed.into());
         }
         Ok(())
     }
 }
 
-impl<A: Cipher> UpEnc for ReCrypt<A, KhPrf>
-    // where for<'a> &'a B::K: Add<Output=B::K>, for<'a> &'a B::K: Sub<Output=B::K>, B::K: Add<u64, Output=B::K>,
-{
+impl<A: Cipher, B: KeyHomomorphicPrf> UpEnc for ReCrypt<A, B> {
     // Type of the key variable
     type K = A::K;
 
@@ -147,107 +251,343 @@ impl<A: Cipher> UpEnc for ReCrypt<A, KhPrf>
 
     /* Writes a re-keying token to a file for a pair of keys and a ciphertext */
     fn rekeygen<In: Read, Out: Write>(k1: Self::K, k2: Self::K, ct_hdr: &mut In, token: &mut Out) -> Result<()> {
+        Self::rekeygen_with(k1, k2, None, ct_hdr, token)
+    }
+
+    fn encrypt<In: Read, Out: Write>(key: Self::K, pt: &mut In, ct_hdr: &mut Out, ct_body: &mut Out) -> Result<()> {
+        Self::encrypt_with(key, None, pt, ct_hdr, ct_body)
+    }
+
+    fn reencrypt<In: Read, Out: Write>(rk: &mut In, ct1_hdr: &mut In, ct1_body: &mut In, ct2_hdr: &mut Out, ct2_body: &mut Out) -> Result<()> {
+        Self::reencrypt_with(rk, None, ct1_hdr, ct1_body, ct2_hdr, ct2_body, |token, reader, writer| {
+            B::reencrypt(token, reader, writer)
+        })
+    }
+
+    fn decrypt<In: Read, Out: Write>(key: Self::K, ct_hdr: &mut In, ct_body: &mut In, pt: &mut Out) -> Result<()> {
+        Self::decrypt_with(key, None, ct_hdr, ct_body, pt)
+    }
+
+    fn encrypt_aad<In: Read, Out: Write>(key: Self::K, aad: &[u8], pt: &mut In, ct_hdr: &mut Out, ct_body: &mut Out) -> Result<()> {
+        Self::encrypt_with(key, Some(aad), pt, ct_hdr, ct_body)
+    }
+
+    fn rekeygen_aad<In: Read, Out: Write>(k1: Self::K, k2: Self::K, aad: &[u8], ct_hdr: &mut In, token: &mut Out) -> Result<()> {
+        Self::rekeygen_with(k1, k2, Some(aad), ct_hdr, token)
+    }
+
+    fn reencrypt_aad<In: Read, Out: Write>(rk: &mut In, aad: &[u8], ct1_hdr: &mut In, ct1_body: &mut In, ct2_hdr: &mut Out, ct2_body: &mut Out) -> Result<()> {
+        Self::reencrypt_with(rk, Some(aad), ct1_hdr, ct1_body, ct2_hdr, ct2_body, |token, reader, writer| {
+            B::reencrypt(token, reader, writer)
+        })
+    }
+
+    fn decrypt_aad<In: Read, Out: Write>(key: Self::K, aad: &[u8], ct_hdr: &mut In, ct_body: &mut In, pt: &mut Out) -> Result<()> {
+        Self::decrypt_with(key, Some(aad), ct_hdr, ct_body, pt)
+    }
+}
+
+impl<A: Cipher, B: KeyHomomorphicPrf> ReCrypt<A, B> {
+    /// As `UpEnc::reencrypt`, but the ciphertext body is updated on
+    /// `workers` threads. The output is identical to `reencrypt`, or to
+    /// `reencrypt_aad` if `aad` is given.
+    pub fn reencrypt_parallel<In: Read, Out: Write>(rk: &mut In, aad: Option<&[u8]>, ct1_hdr: &mut In, ct1_body: &mut In, ct2_hdr: &mut Out, ct2_body: &mut Out, workers: usize) -> Result<()>
+        where B: 'static, B::K: Send + Sync + 'static
+    {
+        Self::reencrypt_with(rk, aad, ct1_hdr, ct1_body, ct2_hdr, ct2_body, |token, reader, writer| {
+            B::reencrypt_parallel(token, reader, writer, workers)
+        })
+    }
+
+    /// Decrypts only the plaintext bytes in `range`, reading just the
+    /// ciphertext blocks which cover it. `aad` is the associated data the
+    /// ciphertext was written with, if any.
+    ///
+    /// Note that the integrity tag covers the whole plaintext, so it is
+    /// *not* checked here.
+    pub fn decrypt_range<In: Read, Body: Read + Seek, Out: Write>(key: A::K, aad: Option<&[u8]>, ct_hdr: &mut In, ct_body: &mut Body, range: Range<u64>, pt: &mut Out) -> Result<()> {
+        let hdr_buf = read_hdr::<A, _>(key, aad, ct_hdr)?;
+        let chi = B::K::read_key(&mut &hdr_buf[..]).chain_err(|| ErrorKind::InvalidHeader)?;
+
+        // The body is y || C, so C starts right after reading y.
+        let y = B::K::read_key(ct_body).chain_err(|| ErrorKind::InvalidBodyKey)?;
+        B::decrypt_range(B::sub_keys(&chi, &y), ct_body, range, pt)
+    }
+
+    /// Appends plaintext to a ciphertext, writing the new header to
+    /// `ct2_hdr`.
+    ///
+    /// The body is extended in place as for `KeyHomomorphicPrf::append`. As
+    /// the integrity tag covers the whole plaintext, the existing body is
+    /// decrypted (but not rewritten) to check the old tag and compute the new
+    /// one. The new header keeps the associated data `aad`, if any.
+    ///
+    /// Each append therefore costs as much as decrypting the whole object.
+    /// `MerkleReCrypt` has no `append` yet, so for objects which mostly grow
+    /// it is cheaper to write each appended piece as a ciphertext of its own.
+    pub fn append<In: Read, P: Read, Body: Read + Write + Seek, Out: Write>(key: A::K, aad: Option<&[u8]>, ct_hdr: &mut In, ct_body: &mut Body, pt: &mut P, ct2_hdr: &mut Out) -> Result<()> {
+        let (hdr_buf, bind) = read_bound_hdr::<A, _>(key.clone(), aad, ct_hdr)?;
+        let mut reader = &hdr_buf[..];
+        let chi = B::K::read_key(&mut reader).chain_err(|| ErrorKind::InvalidHeader)?;
+        let mut tau = Vec::new();
+        reader.read_to_end(&mut tau)?;
+
+        let y = B::K::read_key(ct_body).chain_err(|| ErrorKind::InvalidBodyKey)?;
+        let x = B::sub_keys(&chi, &y);
+        let body = ct_body.seek(SeekFrom::Current(0))?;
+
+        // Hash the existing plaintext, checking it against the old tag.
+        let mut pt_hash = RwAndHash::new(::std::io::sink());
+        B::decrypt(x.clone(), &mut BufReader::new(&mut *ct_body), &mut pt_hash)?;
+        let valid = B::verify_tag(&x, pt_hash.digest().as_ref(), &tau)
+            .chain_err(|| ErrorKind::InvalidHeader)?;
+        if !valid {
+            return Err(ErrorKind::IntegrityCheckFailed.into());
+        }
+
+        // Append the new plaintext, adding it to the hash as it is read.
+        ct_body.seek(SeekFrom::Start(body))?;
+        let mut pt_and_hash = pt_hash.chain(pt);
+        B::append(x.clone(), &mut pt_and_hash, ct_body)?;
+        let hm = pt_and_hash.finish();
+
+        let mut buf = SecretBuf::new();
+        chi.write_key(&mut buf)?;
+        buf.write_all(&B::encrypt_tag(&x, hm.as_ref()))?;
+        write_hdr::<A, _>(key, aad, &bind, &buf, ct2_hdr)
+    }
+
+    /// As `UpEnc::reencrypt`, but the ciphertext body is updated in place.
+    ///
+    /// The body blocks are updated first and the body key `y` last, each step
+    /// recorded in `journal` as for `KeyHomomorphicPrf::reencrypt_in_place`,
+    /// so an interrupted update can be resumed with the same token and
+    /// journal. The new header is written to `ct2_hdr` on every call, and
+    /// should replace the old header once this returns. With associated
+    /// data `aad`, the token is checked against the old header `ct1_hdr`
+    /// first, as for `reencrypt_aad`.
+    pub fn reencrypt_in_place<In: Read, H: Read, F: Read + Write + Seek, J: Read + Write + Seek, Out: Write>(rk: &mut In, aad: Option<&[u8]>, ct1_hdr: &mut H, ct_body: &mut F, journal: &mut J, ct2_hdr: &mut Out) -> Result<()> {
+        let mut token = SecretBuf::new();
+        token.read_from(rk)?;
+        let mut reader = &token[..];
+        let x_new = B::K::read_key(&mut reader).chain_err(|| ErrorKind::InvalidToken)?;
+        let y_new = B::K::read_key(&mut reader).chain_err(|| ErrorKind::InvalidToken)?;
+        check_binding(aad, reader, ct1_hdr)?;
+        // can directly read out rest of rk to header
+        ct2_hdr.write_all(reader)?;
+
+        let mut buf = Vec::new();
+        y_new.write_key(&mut buf)?;
+        let key_len = buf.len();
+        let start = ct_body.seek(SeekFrom::Current(0))?;
+        let mut journal = Journal::new(journal, h!(&token[..]).as_ref(),
+            max(B::CT_BLOCK_SIZE * BLOCKS_PER_ENTRY, key_len));
+        let from = match journal.recover(ct_body)? {
+            Some(from) => from,
+            None => return Ok(()),
+        };
+
+        // The body is y || nonce || C, and y' must fit where y was.
+        ct_body.seek(SeekFrom::Start(start))?;
+        B::K::read_key(ct_body).chain_err(|| ErrorKind::InvalidBodyKey)?;
+        if ct_body.seek(SeekFrom::Current(0))? - start != key_len as u64 {
+            return Err(ErrorKind::Unsupported("updating in place a body key which changes length").into());
+        }
+        let nonce = B::read_nonce(&x_new, ct_body)?;
+        let body = start + (key_len + nonce.len()) as u64;
+        let ctr = B::counter(&x_new);
+        let n = update_in_place(ct_body, &mut journal, body, B::CT_BLOCK_SIZE, ctr, from,
+            |block, ctr| B::update_block(&x_new, block, &nonce, ctr))?;
+
+        journal.rewrite(ct_body, n, start, key_len, |old| {
+            let y = B::K::read_key(&mut &old[..]).chain_err(|| ErrorKind::InvalidBodyKey)?;
+            let mut buf = Vec::new();
+            B::add_keys(&y, &y_new).write_key(&mut buf)?;
+            Ok(buf)
+        })?;
+        journal.finish(n + 1)
+    }
+
+    /// Starts or continues a partial rotation, updating only the blocks in
+    /// `blocks` of the body in place.
+    ///
+    /// `epochs` should start out as `KeyEpochs::new(0)`, and the updated
+    /// blocks move from epoch 0 to epoch 1. Until `finish_reencrypt_range` is
+    /// called, the old header stays in use and the ciphertext is read with
+    /// `decrypt_epochs`. The blocks are journaled as for
+    /// `KeyHomomorphicPrf::reencrypt_range`, so an interrupted call can be
+    /// resumed with the same token, range, epochs and journal.
+    pub fn reencrypt_range<In: Read, F: Read + Write + Seek, J: Read + Write + Seek>(rk: &mut In, ct_body: &mut F, journal: &mut J, epochs: &mut KeyEpochs, blocks: Range<u64>) -> Result<()> {
+        if epochs.epochs_in(blocks.clone()).iter().any(|&e| e != 0) {
+            return Err(ErrorKind::InvalidEpochs("blocks have already been updated with this token").into());
+        }
+        let x_new = B::K::read_key(rk).chain_err(|| ErrorKind::InvalidToken)?;
+        B::K::read_key(ct_body).chain_err(|| ErrorKind::InvalidBodyKey)?;
+
+        let mut buf = SecretBuf::new();
+        x_new.write_key(&mut buf)?;
+        B::reencrypt_range(&mut &buf[..], ct_body, journal, epochs, blocks)
+    }
+
+    /// Finishes a partial rotation once every block is in epoch 1, updating
+    /// the body key in place and writing the new header to `ct2_hdr`.
+    /// `epochs` is reset to all blocks in epoch 0. With associated data
+    /// `aad`, the token is checked against the old header `ct1_hdr` first.
+    pub fn finish_reencrypt_range<In: Read, H: Read, F: Read + Write + Seek, Out: Write>(rk: &mut In, aad: Option<&[u8]>, ct1_hdr: &mut H, ct_body: &mut F, epochs: &mut KeyEpochs, ct2_hdr: &mut Out) -> Result<()> {
         let mut buf = Vec::new();
+        B::K::read_key(rk).chain_err(|| ErrorKind::InvalidToken)?;
+        let y_new = B::K::read_key(rk).chain_err(|| ErrorKind::InvalidToken)?;
+        rk.read_to_end(&mut buf)?;
+        check_binding(aad, &buf, ct1_hdr)?;
+
+        let start = ct_body.seek(SeekFrom::Current(0))?;
+        let y = B::K::read_key(ct_body).chain_err(|| ErrorKind::InvalidBodyKey)?;
+        let key_len = ct_body.seek(SeekFrom::Current(0))? - start;
+        let ct_len = ct_body.seek(SeekFrom::End(0))? - start - key_len;
+        let n_blocks = ct_len.saturating_sub(B::nonce_size(&y) as u64) / B::CT_BLOCK_SIZE as u64;
+        if epochs.epochs_in(0..n_blocks) != vec![1] {
+            return Err(ErrorKind::InvalidEpochs("not all blocks have been updated").into());
+        }
+
+        let mut y_buf = Vec::new();
+        B::add_keys(&y, &y_new).write_key(&mut y_buf)?;
+        if y_buf.len() as u64 != key_len {
+            return Err(ErrorKind::Unsupported("updating in place a body key which changes length").into());
+        }
+        ct_body.seek(SeekFrom::Start(start))?;
+        ct_body.write_all(&y_buf)?;
+        ct_body.flush()?;
+        ct2_hdr.write_all(&buf)?;
+        *epochs = KeyEpochs::new(0);
+        Ok(())
+    }
+
+    /// Decrypts a ciphertext part-way through a partial rotation, with the
+    /// old key and the token used to update blocks in epoch 1. `aad` is the
+    /// associated data the ciphertext was written with, if any.
+    pub fn decrypt_epochs<In: Read, Tok: Read, Body: Read, Out: Write>(key: A::K, aad: Option<&[u8]>, rk: &mut Tok, ct_hdr: &mut In, ct_body: &mut Body, epochs: &KeyEpochs, pt: &mut Out) -> Result<()> {
+        let hdr_buf = read_hdr::<A, _>(key, aad, ct_hdr)?;
+        let mut reader = &hdr_buf[..];
+        let chi = B::K::read_key(&mut reader).chain_err(|| ErrorKind::InvalidHeader)?;
+        let mut tau = Vec::new();
+        reader.read_to_end(&mut tau)?;
+        let x_new = B::K::read_key(rk).chain_err(|| ErrorKind::InvalidToken)?;
+
+        let mut ct_reader = BufReader::new(ct_body);
+        let y = B::K::read_key(&mut ct_reader).chain_err(|| ErrorKind::InvalidBodyKey)?;
+        let x = B::sub_keys(&chi, &y);
+        let keys = [x.clone(), B::add_keys(&x, &x_new)];
+
+        let mut pt_and_hash = RwAndHash::new(pt);
+        B::decrypt_epochs(&keys, epochs, &mut ct_reader, &mut pt_and_hash)?;
+        let hm = pt_and_hash.finish();
+        let valid = B::verify_tag(&x, hm.as_ref(), &tau)
+            .chain_err(|| ErrorKind::InvalidHeader)?;
+        if !valid {
+            return Err(ErrorKind::IntegrityCheckFailed.into());
+        }
+        Ok(())
+    }
+
+    fn rekeygen_with<In: Read, Out: Write>(k1: A::K, k2: A::K, aad: Option<&[u8]>, ct_hdr: &mut In, token: &mut Out) -> Result<()> {
         // buf contains chi || tau
-        A::decrypt(k1, ct_hdr, &mut buf)?;
-
-        let x_new = KhPrf::keygen();
-        let y_new = KhPrf::keygen();
-        let mut reader = Cursor::new(buf);
-        let chi = KhKey::read_key(&mut reader)?;
-        let chi_new = &chi + &(&x_new + &y_new);
-        buf = Vec::new();
+        let (hdr_buf, bind) = read_bound_hdr::<A, _>(k1, aad, ct_hdr)?;
+
+        let mut reader = &hdr_buf[..];
+        let chi = B::K::read_key(&mut reader).chain_err(|| ErrorKind::InvalidHeader)?;
+        // x' counts the update, which chi' then inherits.
+        let x_new = B::keygen_next(&chi)?;
+        let y_new = B::keygen_like(&chi);
+        let chi_new = B::add_keys(&chi, &B::add_keys(&x_new, &y_new));
+        let mut buf = SecretBuf::new();
         // buf contains chi'
         chi_new.write_key(&mut buf)?;
 
         let mut tau = Vec::new();
         reader.read_to_end(&mut tau)?;
-        let tau_new = kh_prf::update_block(x_new.0, &tau, 0);
-        buf.extend_from_slice(&tau_new);
+        let tau_new = B::update_tag(&x_new, &tau).chain_err(|| ErrorKind::InvalidHeader)?;
+        buf.write_all(&tau_new)?;
         // buf should contain (chi' || tau')
 
         // Write out to token x', y'
         x_new.write_key(token)?;
         y_new.write_key(token)?;
         // Write out to token E(k2, chi' || tau)
-        A::encrypt(k2, &mut (&buf[..]), token)
+        write_hdr::<A, _>(k2, aad, &bind, &buf, token)
     }
 
-    fn encrypt<In: Read, Out: Write>(key: Self::K, pt: &mut In, ct_hdr: &mut Out, ct_body: &mut Out) -> Result<()> {
-        let x = KhPrf::keygen();
-        let y = KhPrf::keygen();
-        let chi = &x + &y;
-        let mut buf = Vec::new();
+    fn encrypt_with<In: Read, Out: Write>(key: A::K, aad: Option<&[u8]>, pt: &mut In, ct_hdr: &mut Out, ct_body: &mut Out) -> Result<()> {
+        let x = B::keygen();
+        let y = B::keygen();
+        let chi = B::add_keys(&x, &y);
+        let mut buf = SecretBuf::new();
         chi.write_key(&mut buf)?;
         let mut hash_pt = RwAndHash::new(pt);
 
-        // The key to encrypt tau uses 0 to avoid overlapping with encryption
-        let prf_x = KhKey(x.0.clone(), 0);
-
         // Write y || C to the ciphertext body
         y.write_key(ct_body)?;
-        KhPrf::encrypt(x, &mut hash_pt, ct_body)?;
+        B::encrypt(x.clone(), &mut hash_pt, ct_body)?;
         let hm = hash_pt.finish();
 
         // Encrypt tau into the header
         // Here the header contains chi || tau, where tau = h(m) + F(x, 0)
-        let y = kh_prf::hash_tag_to_group(&hm.as_ref());
-        let tau = kh_prf::encrypt_point(prf_x.0, y, 0).compress_edwards();
-        buf.extend_from_slice(tau.as_bytes());
+        let tau = B::encrypt_tag(&x, hm.as_ref());
+        buf.write_all(&tau)?;
         // AEAD encrypt the header into the ciphertext header
-        A::encrypt(key, &mut (&buf[..]), ct_hdr)
+        write_hdr::<A, _>(key, aad, &new_binding(aad), &buf, ct_hdr)
     }
 
-    fn reencrypt<In: Read, Out: Write>(rk: &mut In, _: &mut In, ct1_body: &mut In, ct2_hdr: &mut Out, ct2_body: &mut Out) -> Result<()> {
-        let mut buf = Vec::new();
-        let x_new = KhKey::read_key(rk)?;
-        let y_new = KhKey::read_key(rk)?;
-        rk.read_to_end(&mut buf)?;
-        // can directly read out rest of rk to header
-        ct2_hdr.write_all(&buf).chain_err(|| "failed to write out")?;
-
-        let mut reader = BufReader::new(ct1_body);
-        let y = KhKey::read_key(&mut reader)?;
-        let mut writer = BufWriter::new(ct2_body);
+    fn decrypt_with<In: Read, Out: Write>(key: A::K, aad: Option<&[u8]>, ct_hdr: &mut In, ct_body: &mut In, pt: &mut Out) -> Result<()> {
+        let hdr_buf = read_hdr::<A, _>(key, aad, ct_hdr)?;
 
-        (&y + &y_new).write_key(&mut writer)?;
-
-        buf = Vec::new();
-        x_new.write_key(&mut buf)?;
-
-        // Write the rest of ct1 to ct2
-        KhPrf::reencrypt(&mut (&buf[..]), &mut reader, &mut writer)
-
-    }
-
-    fn decrypt<In: Read, Out: Write>(key: Self::K, ct_hdr: &mut In, ct_body: &mut In, pt: &mut Out) -> Result<()> {
-        let mut hdr_buf = Vec::new();
-        A::decrypt(key, ct_hdr, &mut hdr_buf)?;
-
-        let mut reader = Cursor::new(hdr_buf);
-        let chi = KhKey::read_key(&mut reader)?;
-        // reader.read_to_end(&mut buf)?;
-        // KhPrf::decrypt()
+        let mut reader = &hdr_buf[..];
+        let chi = B::K::read_key(&mut reader).chain_err(|| ErrorKind::InvalidHeader)?;
 
         let mut ct_reader = BufReader::new(ct_body);
-        let y = KhKey::read_key(&mut ct_reader)?;
-
-        let x = &chi - &y;
-        let prf_x = KhKey(x.0.clone(), 0);
+        let y = B::K::read_key(&mut ct_reader).chain_err(|| ErrorKind::InvalidBodyKey)?;
 
+        let x = B::sub_keys(&chi, &y);
 
         let mut pt_and_hash = RwAndHash::new(pt);
-        KhPrf::decrypt(x, &mut ct_reader, &mut pt_and_hash)?;
+        B::decrypt(x.clone(), &mut ct_reader, &mut pt_and_hash)?;
         let tau_check = pt_and_hash.finish();
         let mut tau_buf = Vec::new();
         reader.read_to_end(&mut tau_buf)?;
-        let y = kh_prf::decrypt_point(prf_x.0, kh_prf::deserialize_point(&tau_buf), 0);
 
-        // This isn't great; the plaintext is already written to file before the
-        // integrity is checked.
-        if y.compress_edwards() != kh_prf::hash_tag_to_group(tau_check.as_ref()).compress_edwards() {
-            return Err("integrity check failed".into());
+        // The plaintext is already written to file before the integrity
+        // is checked; `decrypt_verified` withholds it until then.
+        let valid = B::verify_tag(&x, tau_check.as_ref(), &tau_buf)
+            .chain_err(|| ErrorKind::InvalidHeader)?;
+        if !valid {
+            return Err(ErrorKind::IntegrityCheckFailed.into());
         }
         Ok(())
     }
+
+    // Copies the new header out of the token and updates y, then
+    // passes the token for x to `update` to re-encrypt the rest of
+    // the body.
+    fn reencrypt_with<In: Read, Out: Write, F>(rk: &mut In, aad: Option<&[u8]>, ct1_hdr: &mut In, ct1_body: &mut In, ct2_hdr: &mut Out, ct2_body: &mut Out, update: F) -> Result<()>
+        where F: FnOnce(&mut &[u8], &mut BufReader<&mut In>, &mut BufWriter<&mut Out>) -> Result<()>
+    {
+        let mut buf = Vec::new();
+        let x_new = B::K::read_key(rk).chain_err(|| ErrorKind::InvalidToken)?;
+        let y_new = B::K::read_key(rk).chain_err(|| ErrorKind::InvalidToken)?;
+        rk.read_to_end(&mut buf)?;
+        check_binding(aad, &buf, ct1_hdr)?;
+        // can directly read out rest of rk to header
+        ct2_hdr.write_all(&buf)?;
+
+        let mut reader = BufReader::new(ct1_body);
+        let y = B::K::read_key(&mut reader).chain_err(|| ErrorKind::InvalidBodyKey)?;
+        let mut writer = BufWriter::new(ct2_body);
+
+        B::add_keys(&y, &y_new).write_key(&mut writer)?;
+
+        let mut x_buf = SecretBuf::new();
+        x_new.write_key(&mut x_buf)?;
+
+        // Write the rest of ct1 to ct2
+        update(&mut (&x_buf[..]), &mut reader, &mut writer)
+    }
 }
@@ -0,0 +1,539 @@
+use super::super::*;
+use super::ReCrypt;
+use ::io::*;
+use common::{pad, remove_padding};
+use kh_prf::{u64_to_u8, u8_to_u64};
+use merkle_tree::{self, Hash, NODE_SIZE};
+use parallel;
+
+use std::cmp::{max, min};
+use std::collections::BTreeMap;
+use std::io::{Read, Write, Seek, SeekFrom, BufReader, BufWriter};
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::Range;
+use std::sync::Arc;
+
+// Number of plaintext blocks in each chunk of the hash tree.
+const CHUNK_BLOCKS: usize = 32;
+
+// Number of chunks hashed by each worker per batch.
+const CHUNKS_PER_WORKER: usize = 64;
+
+/// `ReCrypt` with a hash tree over the plaintext.
+///
+/// The body is `y || nonce || C || T`, where `C` encrypts the plaintext as in
+/// `ReCrypt`, and `T` encrypts the nodes of a hash tree over chunks of
+/// 32 plaintext blocks at the counters following `C`. The header
+/// tag encrypts the root of the tree rather than a hash of the whole
+/// plaintext, so a range can be read, or a chunk edited, with only the path
+/// from its chunks to the root. The body is still `y` followed by a single
+/// `B` ciphertext, so tokens are the same as for `ReCrypt`.
+///
+/// The header is `chi || data blocks || edits || tau`. An edit moves each
+/// chunk or tree block it rewrites to the key `x + z`, for a fresh `z` kept
+/// in the header, so no keystream is used twice. Since the body is updated
+/// by adding to `x`, the `z`s carry over unchanged when re-keying.
+pub struct MerkleReCrypt<A, B>{
+    kem_cipher: PhantomData<A>,
+    upenc_cipher: PhantomData<B>
+}
+
+// Number of blocks needed to hold the nodes of a tree of width `width`.
+fn tree_blocks(width: u64, pt_block_size: usize) -> u64 {
+    let len = (2 * width - 1) * NODE_SIZE as u64;
+    let bs = pt_block_size as u64;
+    (len + bs - 1) / bs
+}
+
+// Where the data and the tree sit in the body.
+struct Layout {
+    data_blocks: u64,
+    width: u64,
+    tree_blocks: u64,
+}
+
+impl Layout {
+    fn new(data_blocks: u64, pt_block_size: usize) -> Layout {
+        let n_chunks = (data_blocks + CHUNK_BLOCKS as u64 - 1) / CHUNK_BLOCKS as u64;
+        let width = merkle_tree::width(n_chunks);
+        Layout { data_blocks: data_blocks, width: width, tree_blocks: tree_blocks(width, pt_block_size) }
+    }
+
+    fn n_chunks(&self) -> u64 {
+        (self.data_blocks + CHUNK_BLOCKS as u64 - 1) / CHUNK_BLOCKS as u64
+    }
+
+    fn n_blocks(&self) -> u64 {
+        self.data_blocks + self.tree_blocks
+    }
+
+    // Data blocks in chunk `index`.
+    fn chunk(&self, index: u64) -> Range<u64> {
+        let start = index * CHUNK_BLOCKS as u64;
+        start..min(start + CHUNK_BLOCKS as u64, self.data_blocks)
+    }
+
+    // Edits rewrite whole segments: each chunk is one, followed by each
+    // block of the tree.
+    fn segment(&self, block: u64) -> u64 {
+        if block < self.data_blocks {
+            block / CHUNK_BLOCKS as u64
+        } else {
+            self.n_chunks() + block - self.data_blocks
+        }
+    }
+
+    fn n_segments(&self) -> u64 {
+        self.n_chunks() + self.tree_blocks
+    }
+}
+
+// The decrypted header. `edits` maps each edited segment to its `z`.
+struct Header<K> {
+    chi: K,
+    data_blocks: u64,
+    edits: BTreeMap<u64, K>,
+    tau: Vec<u8>,
+}
+
+impl<K: Key> Header<K> {
+    fn read(buf: &[u8]) -> Result<Header<K>> {
+        let mut reader = buf;
+        let chi = K::read_key(&mut reader).chain_err(|| ErrorKind::InvalidHeader)?;
+        let mut n = [0u8; 8];
+        reader.read_exact(&mut n).chain_err(|| ErrorKind::InvalidHeader)?;
+        let data_blocks = u8_to_u64(n);
+        reader.read_exact(&mut n).chain_err(|| ErrorKind::InvalidHeader)?;
+        let mut edits = BTreeMap::new();
+        for _ in 0..u8_to_u64(n) {
+            let mut segment = [0u8; 8];
+            reader.read_exact(&mut segment).chain_err(|| ErrorKind::InvalidHeader)?;
+            let z = K::read_key(&mut reader).chain_err(|| ErrorKind::InvalidHeader)?;
+            edits.insert(u8_to_u64(segment), z);
+        }
+        Ok(Header { chi: chi, data_blocks: data_blocks, edits: edits, tau: reader.to_vec() })
+    }
+
+    fn write(&self) -> Result<SecretBuf> {
+        let mut buf = SecretBuf::new();
+        self.chi.write_key(&mut buf)?;
+        buf.write_all(&u64_to_u8(self.data_blocks))?;
+        buf.write_all(&u64_to_u8(self.edits.len() as u64))?;
+        for (&segment, z) in &self.edits {
+            buf.write_all(&u64_to_u8(segment))?;
+            z.write_key(&mut buf)?;
+        }
+        buf.write_all(&self.tau)?;
+        Ok(buf)
+    }
+}
+
+// A ciphertext opened with the header key, for random access to the body.
+struct Opened<K> {
+    hdr: Header<K>,
+    x: K,
+    // `x + z` for each edited segment.
+    keys: BTreeMap<u64, K>,
+    nonce: Vec<u8>,
+    // Offset of the first block of the body.
+    start: u64,
+    layout: Layout,
+}
+
+impl<K> Opened<K> {
+    // The key for body block `block`.
+    fn key(&self, block: u64) -> &K {
+        self.keys.get(&self.layout.segment(block)).unwrap_or(&self.x)
+    }
+}
+
+// The value encrypted into the header tag.
+fn root_hash(data_blocks: u64, root: &Hash) -> Vec<u8> {
+    h!(b"MerkleReCrypt root", &u64_to_u8(data_blocks), root).as_ref().to_vec()
+}
+
+impl<A: Cipher, B: KeyHomomorphicPrf> UpEnc for MerkleReCrypt<A, B> {
+    // Type of the key variable
+    type K = A::K;
+
+    /* Generates a new, random key  */
+    fn keygen() -> Self::K {
+        A::keygen()
+    }
+
+    /* Writes a re-keying token to a file for a pair of keys and a ciphertext */
+    /// The token has the same form as for `ReCrypt`, with the edits copied
+    /// into the new header.
+    fn rekeygen<In: Read, Out: Write>(k1: Self::K, k2: Self::K, ct_hdr: &mut In, token: &mut Out) -> Result<()> {
+        let mut hdr = Self::read_header(k1, ct_hdr)?;
+        // x' counts the update, which chi' then inherits.
+        let x_new = B::keygen_next(&hdr.chi)?;
+        let y_new = B::keygen_like(&hdr.chi);
+        hdr.chi = B::add_keys(&hdr.chi, &B::add_keys(&x_new, &y_new));
+        hdr.tau = B::update_tag(&x_new, &hdr.tau).chain_err(|| ErrorKind::InvalidHeader)?;
+
+        x_new.write_key(token)?;
+        y_new.write_key(token)?;
+        A::encrypt(k2, &mut &hdr.write()?[..], token)
+    }
+
+    fn encrypt<In: Read, Out: Write>(key: Self::K, pt: &mut In, ct_hdr: &mut Out, ct_body: &mut Out) -> Result<()> {
+        Self::encrypt_parallel(key, pt, ct_hdr, ct_body, 1)
+    }
+
+    fn reencrypt<In: Read, Out: Write>(rk: &mut In, ct1_hdr: &mut In, ct1_body: &mut In, ct2_hdr: &mut Out, ct2_body: &mut Out) -> Result<()> {
+        ReCrypt::<A, B>::reencrypt(rk, ct1_hdr, ct1_body, ct2_hdr, ct2_body)
+    }
+
+    /// Streams the body, checking the root built from the chunks once they
+    /// have all been read. As for `ReCrypt`, the plaintext is written out
+    /// before then; `decrypt_verified` withholds it until the check passes.
+    fn decrypt<In: Read, Out: Write>(key: Self::K, ct_hdr: &mut In, ct_body: &mut In, pt: &mut Out) -> Result<()> {
+        Self::decrypt_parallel(key, ct_hdr, ct_body, pt, 1)
+    }
+}
+
+impl<A: Cipher, B: KeyHomomorphicPrf> MerkleReCrypt<A, B> {
+    /// As `UpEnc::encrypt`, but the chunks are hashed on `workers` threads.
+    pub fn encrypt_parallel<In: Read, Out: Write>(key: A::K, pt: &mut In, ct_hdr: &mut Out, ct_body: &mut Out, workers: usize) -> Result<()> {
+        let x = B::keygen();
+        let y = B::keygen();
+        let chi = B::add_keys(&x, &y);
+        let ctr = B::counter(&x);
+        let nonce = B::new_nonce(&x);
+        let pt_block_size = B::PT_BLOCK_SIZE;
+
+        let mut reader = BufReader::new(pt);
+        let mut writer = BufWriter::new(ct_body);
+        y.write_key(&mut writer)?;
+        writer.write_all(&nonce)?;
+
+        // Encrypt the data, hashing each batch of chunks as it fills up.
+        let workers = max(workers, 1);
+        let hash = Arc::new(|chunk: &[u8], i: u64| -> Result<Vec<u8>> {
+            Ok(merkle_tree::leaf_hash(i, chunk).to_vec())
+        });
+        let mut leaves: Vec<Hash> = Vec::new();
+        let mut chunks = Vec::new();
+        let mut chunk = Vec::new();
+        let mut data_blocks = 0;
+        let mut eof = false;
+        while !eof {
+            let mut block = read_chunk(&mut reader, pt_block_size)?;
+
+            // Pad if it's not a full block.
+            if block.len() < pt_block_size {
+                pad(&mut block, pt_block_size);
+                eof = true;
+            }
+
+            let block_ctr = ctr.checked_add(data_blocks).ok_or(ErrorKind::CounterOverflow)?;
+            writer.write_all(&B::encrypt_block(&x, &block, &nonce, block_ctr)?)?;
+            data_blocks += 1;
+
+            chunk.extend_from_slice(&block);
+            if chunk.len() == CHUNK_BLOCKS * pt_block_size || eof {
+                chunks.push(mem::replace(&mut chunk, Vec::new()));
+            }
+            if chunks.len() == CHUNKS_PER_WORKER * workers || eof {
+                let first = leaves.len() as u64;
+                let batch = mem::replace(&mut chunks, Vec::new());
+                for leaf in parallel::map_blocks(batch, 0, first, workers, &hash)? {
+                    let mut node = [0u8; 32];
+                    node.copy_from_slice(&leaf);
+                    leaves.push(node);
+                }
+            }
+        }
+
+        // Then the tree, zero-padded to a whole number of blocks.
+        let nodes = merkle_tree::build(&leaves);
+        let mut tree = nodes[1..].concat();
+        let len = Layout::new(data_blocks, pt_block_size).tree_blocks as usize * pt_block_size;
+        tree.resize(len, 0);
+        for (block, i) in tree.chunks(pt_block_size).zip(data_blocks..) {
+            let block_ctr = ctr.checked_add(i).ok_or(ErrorKind::CounterOverflow)?;
+            writer.write_all(&B::encrypt_block(&x, block, &nonce, block_ctr)?)?;
+        }
+        writer.flush()?;
+
+        // The header contains chi || data blocks || no edits || tau, where
+        // tau encrypts the root.
+        let tau = B::encrypt_tag(&x, &root_hash(data_blocks, &nodes[1]));
+        let hdr = Header { chi: chi, data_blocks: data_blocks, edits: BTreeMap::new(), tau: tau };
+        A::encrypt(key, &mut &hdr.write()?[..], ct_hdr)
+    }
+
+    /// As `UpEnc::decrypt`, but the chunks are hashed on `workers` threads.
+    pub fn decrypt_parallel<In: Read, Out: Write>(key: A::K, ct_hdr: &mut In, ct_body: &mut In, pt: &mut Out, workers: usize) -> Result<()> {
+        let hdr = Self::read_header(key, ct_hdr)?;
+        let mut reader = BufReader::new(ct_body);
+        let ct = Self::unlock(hdr, &mut reader)?;
+        let mut writer = BufWriter::new(pt);
+        let ct_bs = B::CT_BLOCK_SIZE;
+        let n_chunks = ct.layout.n_chunks();
+
+        // Decrypt the data, hashing each batch of chunks as it is read.
+        let workers = max(workers, 1);
+        let hash = Arc::new(|chunk: &[u8], i: u64| -> Result<Vec<u8>> {
+            Ok(merkle_tree::leaf_hash(i, chunk).to_vec())
+        });
+        let mut leaves: Vec<Hash> = Vec::new();
+        while (leaves.len() as u64) < n_chunks {
+            let first = leaves.len() as u64;
+            let mut chunks = Vec::new();
+            for i in first..min(first + (CHUNKS_PER_WORKER * workers) as u64, n_chunks) {
+                let blocks = ct.layout.chunk(i);
+                let buf = read_chunk(&mut reader, (blocks.end - blocks.start) as usize * ct_bs)?;
+                chunks.push(Self::decrypt_blocks(&ct, &buf, blocks)?);
+            }
+            for leaf in parallel::map_blocks(chunks.clone(), 0, first, workers, &hash)? {
+                let mut node = [0u8; 32];
+                node.copy_from_slice(&leaf);
+                leaves.push(node);
+            }
+            for (chunk, i) in chunks.into_iter().zip(first..) {
+                // Only the final chunk carries padding.
+                let chunk = if i == n_chunks - 1 {
+                    remove_padding(chunk).ok_or(ErrorKind::InvalidBlock(ct.layout.data_blocks - 1))?
+                } else {
+                    chunk
+                };
+                writer.write_all(&chunk)?;
+            }
+        }
+        writer.flush()?;
+
+        // The tree is rebuilt from the chunks, but must still be there.
+        let tree_len = ct.layout.tree_blocks * ct_bs as u64;
+        let n = ::std::io::copy(&mut reader.take(tree_len + 1), &mut ::std::io::sink())?;
+        if n != tree_len {
+            return Err(ErrorKind::InvalidBodyLength(ct.layout.data_blocks + n / ct_bs as u64).into());
+        }
+        Self::check_root(&ct, &merkle_tree::build(&leaves)[1])
+    }
+
+    /// Decrypts only the plaintext bytes in `range`.
+    ///
+    /// Each chunk covering the range (and the final chunk, to find the length
+    /// of the padding) is checked against the root before anything is
+    /// written out. `range` is clamped to the length of the plaintext.
+    pub fn decrypt_range<In: Read, Body: Read + Seek, Out: Write>(key: A::K, ct_hdr: &mut In, ct_body: &mut Body, range: Range<u64>, pt: &mut Out) -> Result<()> {
+        let ct = Self::open(key, ct_hdr, ct_body)?;
+        let n_chunks = ct.layout.n_chunks();
+        let chunk_len = (CHUNK_BLOCKS * B::PT_BLOCK_SIZE) as u64;
+
+        let (last, _) = Self::read_verified(ct_body, &ct, n_chunks - 1)?;
+        let last_pt = remove_padding(last.clone())
+            .ok_or(ErrorKind::InvalidBlock(ct.layout.data_blocks - 1))?;
+        let pt_len = (n_chunks - 1) * chunk_len + last_pt.len() as u64;
+
+        let end = min(range.end, pt_len);
+        if range.start >= end {
+            return Ok(());
+        }
+        let first = range.start / chunk_len;
+        let last_chunk = (end - 1) / chunk_len;
+
+        let mut buf = Vec::new();
+        for i in first..last_chunk + 1 {
+            if i == n_chunks - 1 {
+                buf.extend_from_slice(&last);
+            } else {
+                buf.extend(Self::read_verified(ct_body, &ct, i)?.0);
+            }
+        }
+        let offset = first * chunk_len;
+        pt.write_all(&buf[(range.start - offset) as usize..(end - offset) as usize])?;
+        Ok(())
+    }
+
+    /// Replaces the plaintext of chunk `index` with `data` in place, writing
+    /// the new header to `ct2_hdr`.
+    ///
+    /// `data` must be the same length as the existing chunk (without padding,
+    /// for the final chunk), and the old chunk is checked against the root
+    /// first. Only the chunk, the path from it to the root and the header are
+    /// rewritten. Each block rewritten is encrypted under a fresh key, whose
+    /// `z` is added to the header.
+    pub fn write_chunk<In: Read, Body: Read + Write + Seek, Out: Write>(key: A::K, ct_hdr: &mut In, ct_body: &mut Body, index: u64, data: &[u8], ct2_hdr: &mut Out) -> Result<()> {
+        let mut ct = Self::open(key.clone(), ct_hdr, ct_body)?;
+        let n_chunks = ct.layout.n_chunks();
+        if index >= n_chunks {
+            return Err(ErrorKind::InvalidArgument("chunk index out of range").into());
+        }
+        let (old, siblings) = Self::read_verified(ct_body, &ct, index)?;
+
+        let mut new = data.to_vec();
+        let old_len = if index == n_chunks - 1 {
+            // The final chunk keeps its length, so its padding is unchanged.
+            pad(&mut new, B::PT_BLOCK_SIZE);
+            remove_padding(old).map(|b| b.len())
+                .ok_or(ErrorKind::InvalidBlock(ct.layout.data_blocks - 1))?
+        } else {
+            old.len()
+        };
+        if data.len() != old_len {
+            return Err(ErrorKind::InvalidArgument("edited chunk must keep its length").into());
+        }
+
+        let start = ct.layout.chunk(index).start;
+        Self::write_blocks(ct_body, &mut ct, start, &new)?;
+        let leaf = ct.layout.width + index;
+        let path = merkle_tree::path(leaf, merkle_tree::leaf_hash(index, &new), &siblings);
+        for &(node, ref hash) in &path {
+            Self::write_node(ct_body, &mut ct, node, hash)?;
+        }
+        ct_body.flush()?;
+
+        let root = path.last().expect("path includes the root").1;
+        ct.hdr.tau = B::encrypt_tag(&ct.x, &root_hash(ct.layout.data_blocks, &root));
+        A::encrypt(key, &mut &ct.hdr.write()?[..], ct2_hdr)
+    }
+
+    fn read_header<In: Read>(key: A::K, ct_hdr: &mut In) -> Result<Header<B::K>> {
+        let mut hdr_buf = SecretBuf::new();
+        A::decrypt(key, ct_hdr, &mut hdr_buf)?;
+        Header::read(&hdr_buf)
+    }
+
+    // Reads `y || nonce` from the start of the body, leaving `ct_body` at
+    // the first block.
+    fn unlock<Body: Read>(hdr: Header<B::K>, ct_body: &mut Body) -> Result<Opened<B::K>> {
+        let y = B::K::read_key(ct_body).chain_err(|| ErrorKind::InvalidBodyKey)?;
+        let x = B::sub_keys(&hdr.chi, &y);
+        let nonce = B::read_nonce(&x, ct_body)?;
+
+        if hdr.data_blocks == 0 {
+            return Err(ErrorKind::InvalidHeader.into());
+        }
+        let layout = Layout::new(hdr.data_blocks, B::PT_BLOCK_SIZE);
+        if B::counter(&x).checked_add(layout.n_blocks() - 1).is_none() {
+            return Err(ErrorKind::CounterOverflow.into());
+        }
+
+        let mut keys = BTreeMap::new();
+        for (&segment, z) in &hdr.edits {
+            if segment >= layout.n_segments() {
+                return Err(ErrorKind::InvalidHeader.into());
+            }
+            keys.insert(segment, B::add_keys(&x, z));
+        }
+        Ok(Opened { hdr: hdr, x: x, keys: keys, nonce: nonce, start: 0, layout: layout })
+    }
+
+    // Decrypts the header and checks the length of the body against it.
+    fn open<In: Read, Body: Read + Seek>(key: A::K, ct_hdr: &mut In, ct_body: &mut Body) -> Result<Opened<B::K>> {
+        let hdr = Self::read_header(key, ct_hdr)?;
+        let mut ct = Self::unlock(hdr, ct_body)?;
+        ct.start = ct_body.seek(SeekFrom::Current(0))?;
+        let ct_len = ct_body.seek(SeekFrom::End(0))? - ct.start;
+        let ct_bs = B::CT_BLOCK_SIZE as u64;
+        if ct_len % ct_bs != 0 {
+            return Err(ErrorKind::TruncatedBlock(ct_len / ct_bs, (ct_len % ct_bs) as usize).into());
+        }
+        if ct_len / ct_bs != ct.layout.n_blocks() {
+            return Err(ErrorKind::InvalidBodyLength(ct_len / ct_bs).into());
+        }
+        Ok(ct)
+    }
+
+    // Decrypts body blocks `blocks`, counting the tree blocks after the data.
+    fn read_blocks<Body: Read + Seek>(ct_body: &mut Body, ct: &Opened<B::K>, blocks: Range<u64>) -> Result<Vec<u8>> {
+        let ct_bs = B::CT_BLOCK_SIZE;
+        ct_body.seek(SeekFrom::Start(ct.start + blocks.start * ct_bs as u64))?;
+        let buf = read_chunk(ct_body, (blocks.end - blocks.start) as usize * ct_bs)?;
+        Self::decrypt_blocks(ct, &buf, blocks)
+    }
+
+    // Decrypts `buf`, which should hold exactly body blocks `blocks`.
+    fn decrypt_blocks(ct: &Opened<B::K>, buf: &[u8], blocks: Range<u64>) -> Result<Vec<u8>> {
+        let ct_bs = B::CT_BLOCK_SIZE;
+        if buf.len() as u64 != (blocks.end - blocks.start) * ct_bs as u64 {
+            let i = blocks.start + (buf.len() / ct_bs) as u64;
+            return Err(ErrorKind::TruncatedBlock(i, buf.len() % ct_bs).into());
+        }
+        let mut result = Vec::with_capacity(buf.len());
+        for (block, i) in buf.chunks(ct_bs).zip(blocks) {
+            let ctr = B::counter(&ct.x) + i;
+            result.extend(B::decrypt_block(ct.key(i), block, &ct.nonce, ctr).chain_err(|| ErrorKind::InvalidBlock(i))?);
+        }
+        Ok(result)
+    }
+
+    // Encrypts `pt` over the body blocks starting at `first`, which must
+    // cover whole segments. Each segment moves to a fresh key, as its
+    // counters have been used before.
+    fn write_blocks<Body: Write + Seek>(ct_body: &mut Body, ct: &mut Opened<B::K>, first: u64, pt: &[u8]) -> Result<()> {
+        let n = (pt.len() / B::PT_BLOCK_SIZE) as u64;
+        let mut segment = None;
+        for i in first..first + n {
+            let s = ct.layout.segment(i);
+            if segment != Some(s) {
+                let z = B::keygen_like(&ct.x);
+                ct.keys.insert(s, B::add_keys(&ct.x, &z));
+                ct.hdr.edits.insert(s, z);
+                segment = Some(s);
+            }
+        }
+
+        let mut buf = Vec::new();
+        for (block, i) in pt.chunks(B::PT_BLOCK_SIZE).zip(first..) {
+            let ctr = B::counter(&ct.x) + i;
+            buf.extend(B::encrypt_block(ct.key(i), block, &ct.nonce, ctr)?);
+        }
+        ct_body.seek(SeekFrom::Start(ct.start + first * B::CT_BLOCK_SIZE as u64))?;
+        ct_body.write_all(&buf)?;
+        Ok(())
+    }
+
+    // Body blocks holding node `node`, and the node's offset within them.
+    fn node_blocks(ct: &Opened<B::K>, node: u64) -> (Range<u64>, usize) {
+        let bs = B::PT_BLOCK_SIZE as u64;
+        let offset = (node - 1) * NODE_SIZE as u64;
+        let first = offset / bs;
+        let last = (offset + NODE_SIZE as u64 - 1) / bs;
+        let data = ct.layout.data_blocks;
+        (data + first..data + last + 1, (offset - first * bs) as usize)
+    }
+
+    fn read_node<Body: Read + Seek>(ct_body: &mut Body, ct: &Opened<B::K>, node: u64) -> Result<Hash> {
+        let (blocks, offset) = Self::node_blocks(ct, node);
+        let buf = Self::read_blocks(ct_body, ct, blocks)?;
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&buf[offset..offset + NODE_SIZE]);
+        Ok(hash)
+    }
+
+    fn write_node<Body: Read + Write + Seek>(ct_body: &mut Body, ct: &mut Opened<B::K>, node: u64, hash: &Hash) -> Result<()> {
+        let (blocks, offset) = Self::node_blocks(ct, node);
+        let mut buf = Self::read_blocks(ct_body, ct, blocks.clone())?;
+        buf[offset..offset + NODE_SIZE].copy_from_slice(hash);
+        Self::write_blocks(ct_body, ct, blocks.start, &buf)
+    }
+
+    // Checks a root against the header tag.
+    fn check_root(ct: &Opened<B::K>, root: &Hash) -> Result<()> {
+        let valid = B::verify_tag(&ct.x, &root_hash(ct.layout.data_blocks, root), &ct.hdr.tau)
+            .chain_err(|| ErrorKind::InvalidHeader)?;
+        if !valid {
+            return Err(ErrorKind::IntegrityCheckFailed.into());
+        }
+        Ok(())
+    }
+
+    // Decrypts chunk `index` (with its padding) and checks it against the
+    // root, returning it along with the hashes of its siblings.
+    fn read_verified<Body: Read + Seek>(ct_body: &mut Body, ct: &Opened<B::K>, index: u64) -> Result<(Vec<u8>, Vec<Hash>)> {
+        let chunk = Self::read_blocks(ct_body, ct, ct.layout.chunk(index))?;
+        let leaf = ct.layout.width + index;
+        let mut siblings = Vec::new();
+        for node in merkle_tree::siblings(leaf) {
+            siblings.push(Self::read_node(ct_body, ct, node)?);
+        }
+        let path = merkle_tree::path(leaf, merkle_tree::leaf_hash(index, &chunk), &siblings);
+        Self::check_root(ct, &path.last().expect("path includes the root").1)?;
+        Ok((chunk, siblings))
+    }
+}
@@ -1,8 +1,10 @@
 //! Generic constructions of updatable encryption from building blocks
 
 mod hybrid;
+mod merkle;
 mod naive;
 mod null;
 
 pub use self::naive::{KemDem, Naive};
 pub use self::hybrid::{Kss, ReCrypt};
+pub use self::merkle::MerkleReCrypt;
@@ -37,7 +37,7 @@ impl<C: Cipher> UpEnc for Naive<C> {
 
     /* Writes a re-keying token to a file for a pair of keys and a ciphertext */
     fn rekeygen<In: Read, Out: Write>(k1: Self::K, k2: Self::K, ct_hdr: &mut In, token: &mut Out) -> Result<()> {
-        let mut buf = Vec::new();
+        let mut buf = SecretBuf::new();
         C::decrypt(k1, ct_hdr, &mut buf)?;
         C::encrypt(k2, &mut (&buf[..]), token)
     }
@@ -53,7 +53,7 @@ impl<C: Cipher> UpEnc for Naive<C> {
 
         // Set ct2_hdr = token
         loop {
-            let chunk = read_chunk(&mut reader, 128).unwrap();
+            let chunk = read_chunk(&mut reader, 128)?;
             match chunk.len() {
                 // EOF
                 0 => break,
@@ -83,14 +83,14 @@ impl<C: Cipher> UpEnc for KemDem<C> {
 
     /* Writes a re-keying token to a file for a pair of keys and a ciphertext */
     fn rekeygen<In: Read, Out: Write>(k1: Self::K, k2: Self::K, ct_hdr: &mut In, token: &mut Out) -> Result<()> {
-        let mut buf = Vec::new();
+        let mut buf = SecretBuf::new();
         C::decrypt(k1, ct_hdr, &mut buf)?;
         C::encrypt(k2, &mut (&buf[..]), token)
     }
 
     fn encrypt<In: Read, Out: Write>(key: Self::K, pt: &mut In, ct_hdr: &mut Out, ct_body: &mut Out) -> Result<()> {
         let k_dem: C::K = C::keygen();
-        let mut buf = Vec::new();
+        let mut buf = SecretBuf::new();
         k_dem.write_key(&mut buf)?;
         C::encrypt(key, &mut (&buf[..]), ct_hdr)?;
         C::encrypt(k_dem, pt, ct_body)
@@ -104,7 +104,7 @@ impl<C: Cipher> UpEnc for KemDem<C> {
 
         // Set ct2_hdr = token
         loop {
-            let chunk = read_chunk(&mut tok_reader, 128).unwrap();
+            let chunk = read_chunk(&mut tok_reader, 128)?;
             match chunk.len() {
                 // EOF
                 0 => break,
@@ -117,7 +117,7 @@ impl<C: Cipher> UpEnc for KemDem<C> {
         }
         // Set ct2_body = ct1_body
         loop {
-            let chunk = read_chunk(&mut body_reader, 128).unwrap();
+            let chunk = read_chunk(&mut body_reader, 128)?;
             match chunk.len() {
                 // EOF
                 0 => break,
@@ -131,9 +131,9 @@ impl<C: Cipher> UpEnc for KemDem<C> {
         Ok(())
     }
     fn decrypt<In: Read ,Out: Write>(key: Self::K, ct_hdr: &mut In, ct_body: &mut In, pt: &mut Out) -> Result<()> {
-        let mut buf = Vec::new();
+        let mut buf = SecretBuf::new();
         C::decrypt(key, ct_hdr, &mut buf)?;
-        let k_dem = C::K::read_key(&mut (&buf[..]))?;
+        let k_dem = C::K::read_key(&mut (&buf[..])).chain_err(|| ErrorKind::InvalidHeader)?;
         C::decrypt(k_dem, ct_body, pt)
     }
 }
@@ -30,7 +30,7 @@ impl Cipher for NullCipher {
 
         let mut eof = false;
         while !eof {
-            let block = read_chunk(&mut reader, 128).unwrap();
+            let block = read_chunk(&mut reader, 128)?;
             if block.len() < 128 {
                 eof = true;
             }
@@ -44,7 +44,7 @@ impl Cipher for NullCipher {
         let mut writer = BufWriter::new(pt);
 
         loop {
-            let chunk = read_chunk(&mut reader, 128).unwrap();
+            let chunk = read_chunk(&mut reader, 128)?;
 
             let eof = match chunk.len() {
                 0 => true,
@@ -75,7 +75,7 @@ impl UpEncCtxtIndep for NullCipher {
         let mut writer = BufWriter::new(ct_new);
 
         loop {
-            let chunk = read_chunk(&mut reader, 128).unwrap();
+            let chunk = read_chunk(&mut reader, 128)?;
             match chunk.len() {
                 // EOF
                 0 => break,
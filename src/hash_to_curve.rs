@@ -0,0 +1,377 @@
+//! RFC 9380 hashing to edwards25519.
+//!
+//! Implements the `edwards25519_XMD:SHA-512_ELL2_RO_` suite: the message is
+//! expanded with `expand_message_xmd` (SHA-512) into two field elements, each
+//! is mapped to the curve with Elligator 2 on curve25519 followed by the
+//! rational map to edwards25519, and the sum of the two points is multiplied
+//! by the cofactor.
+//!
+//! The field arithmetic here is only used for the map itself, and for the
+//! Elligator map of the `Legacy` suite in `elligator`; the resulting points
+//! are decompressed into `dalek` points for the group operations.
+
+use dalek::edwards::{CompressedEdwardsY, EdwardsPoint};
+use ring::digest;
+
+/// Suite identifier, for building domain separation tags.
+pub const SUITE_ID: &'static str = "edwards25519_XMD:SHA-512_ELL2_RO_";
+
+// Bytes per field element in `hash_to_field`: ceil((255 + 128) / 8).
+const L: usize = 48;
+
+/// Hashes `msg` to a point on edwards25519, under the domain separation tag
+/// `dst`.
+pub fn hash_to_curve(msg: &[u8], dst: &[u8]) -> EdwardsPoint {
+    let uniform = expand_message_xmd(msg, dst, 2 * L);
+    let q0 = map_to_curve(&FieldElement::from_bytes_wide(&uniform[..L]));
+    let q1 = map_to_curve(&FieldElement::from_bytes_wide(&uniform[L..]));
+    (&q0 + &q1).mul_by_cofactor()
+}
+
+/// `expand_message_xmd` with SHA-512, from section 5.3.1 of RFC 9380.
+pub fn expand_message_xmd(msg: &[u8], dst: &[u8], len: usize) -> Vec<u8> {
+    // SHA-512 output and block sizes
+    let (b_in_bytes, s_in_bytes) = (64, 128);
+    let ell = (len + b_in_bytes - 1) / b_in_bytes;
+    assert!(ell <= 255 && len <= 65535 && dst.len() <= 255, "invalid expand_message_xmd parameters");
+
+    let mut dst_prime = dst.to_vec();
+    dst_prime.push(dst.len() as u8);
+
+    let mut ctx = digest::Context::new(&digest::SHA512);
+    ctx.update(&vec![0u8; s_in_bytes]);
+    ctx.update(msg);
+    ctx.update(&[(len >> 8) as u8, len as u8, 0]);
+    ctx.update(&dst_prime);
+    let b_0 = ctx.finish();
+
+    let mut out = Vec::with_capacity(ell * b_in_bytes);
+    let mut b_i = vec![0u8; b_in_bytes];
+    for i in 1..(ell + 1) {
+        let mut ctx = digest::Context::new(&digest::SHA512);
+        let xored: Vec<u8> = b_0.as_ref().iter().zip(&b_i).map(|(a, b)| a ^ b).collect();
+        ctx.update(&xored);
+        ctx.update(&[i as u8]);
+        ctx.update(&dst_prime);
+        b_i = ctx.finish().as_ref().to_vec();
+        out.extend_from_slice(&b_i);
+    }
+    out.truncate(len);
+    out
+}
+
+// Maps a field element to edwards25519 (appendix G.2.1 of RFC 9380).
+fn map_to_curve(u: &FieldElement) -> EdwardsPoint {
+    let (s, t) = elligator2(u);
+    montgomery_to_edwards(&s, &t)
+}
+
+// The rational map from curve25519 to edwards25519 (appendix D.1 of RFC
+// 9380).
+pub fn montgomery_to_edwards(s: &FieldElement, t: &FieldElement) -> EdwardsPoint {
+    // (x, y) = (sqrt(-486664) * s / t, (s - 1) / (s + 1)), with the
+    // exceptional cases t = 0 and s = -1 mapping to the identity.
+    let one = FieldElement::from_u64(1);
+    let (x, y) = if t.is_zero() || (s + &one).is_zero() {
+        (FieldElement::from_u64(0), one)
+    } else {
+        let x = &(&SQRT_MINUS_486664 * s) * &t.invert();
+        let y = &(s - &one) * &(s + &one).invert();
+        (x, y)
+    };
+
+    let mut bytes = y.to_bytes();
+    bytes[31] |= x.sgn0() << 7;
+    CompressedEdwardsY(bytes).decompress()
+        .expect("rational map always gives a point on edwards25519")
+}
+
+// Elligator 2 onto curve25519 (section 6.7.1 of RFC 9380), returning the
+// Montgomery coordinates `(s, t)`.
+fn elligator2(u: &FieldElement) -> (FieldElement, FieldElement) {
+    let j = FieldElement::from_u64(486662);
+    let one = FieldElement::from_u64(1);
+
+    // Z = 2
+    let mut tv1 = &FieldElement::from_u64(2) * &u.square();
+    if (&tv1 + &one).is_zero() {
+        tv1 = FieldElement::from_u64(0);
+    }
+    let x1 = -&(&j * &(&tv1 + &one).invert());
+    let gx1 = &(&(&(&x1 + &j) * &x1) + &one) * &x1;
+    let x2 = &(-&x1) - &j;
+    let gx2 = &tv1 * &gx1;
+
+    let e2 = gx1.is_square();
+    let (x, y2) = if e2 { (x1, gx1) } else { (x2, gx2) };
+    let mut y = y2.sqrt();
+    let e3 = y.sgn0() == 1;
+    if e2 != e3 {
+        y = -&y;
+    }
+    (x, y)
+}
+
+// sqrt(-486664), chosen with sgn0 equal to 0.
+pub const SQRT_MINUS_486664: FieldElement = FieldElement([
+    1693982333959686, 608509411481997, 2235573344831311, 947681270984193, 266558006233600,
+]);
+
+// sqrt(-1) = 2^((p - 1) / 4).
+const SQRT_MINUS_ONE: FieldElement = FieldElement([
+    1718705420411056, 234908883556509, 2233514472574048, 2117202627021982, 765476049583133,
+]);
+
+// Builds the little-endian exponent `lo, 0xff, ..., 0xff, hi`. Each of
+// p - 2, (p - 1) / 2, (p + 3) / 8 and (p - 1) / 4 has this form.
+fn le_exp(lo: u8, hi: u8) -> [u8; 32] {
+    let mut bytes = [0xff; 32];
+    bytes[0] = lo;
+    bytes[31] = hi;
+    bytes
+}
+
+const MASK: u64 = (1 << 51) - 1;
+
+// An element of GF(2^255 - 19), as five 51-bit limbs.
+#[derive(Clone, Copy, Debug)]
+pub struct FieldElement([u64; 5]);
+
+impl FieldElement {
+    pub fn from_u64(v: u64) -> FieldElement {
+        FieldElement([v & MASK, v >> 51, 0, 0, 0])
+    }
+
+    // Loads a little-endian encoding, ignoring the top bit.
+    pub fn from_bytes(bytes: &[u8; 32]) -> FieldElement {
+        let load = |i: usize| bytes[i..i + 8].iter().rev().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+        FieldElement([
+            load(0) & MASK,
+            (load(6) >> 3) & MASK,
+            (load(12) >> 6) & MASK,
+            (load(19) >> 1) & MASK,
+            (load(24) >> 12) & MASK,
+        ])
+    }
+
+    // Reduces a big-endian integer (`OS2IP`) modulo p.
+    fn from_bytes_wide(bytes: &[u8]) -> FieldElement {
+        let base = FieldElement::from_u64(256);
+        bytes.iter().fold(FieldElement::from_u64(0), |acc, b| &(&acc * &base) + &FieldElement::from_u64(*b as u64))
+    }
+
+    // The limbs of the canonical value, each below 2^51.
+    fn reduce(&self) -> [u64; 5] {
+        let mut h = self.carry().carry().0;
+
+        // h is now less than 2p; subtract p if h >= p.
+        let mut q = (h[0] + 19) >> 51;
+        for i in 1..5 {
+            q = (h[i] + q) >> 51;
+        }
+        h[0] += 19 * q;
+        for i in 0..4 {
+            h[i + 1] += h[i] >> 51;
+            h[i] &= MASK;
+        }
+        h[4] &= MASK;
+        h
+    }
+
+    // Canonical little-endian encoding.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        let h = self.reduce();
+        let mut bytes = [0u8; 32];
+        let (mut acc, mut bits, mut n) = (0u128, 0, 0);
+        for limb in h.iter() {
+            acc |= (*limb as u128) << bits;
+            bits += 51;
+            while bits >= 8 {
+                bytes[n] = acc as u8;
+                acc >>= 8;
+                bits -= 8;
+                n += 1;
+            }
+        }
+        bytes[n] = acc as u8;
+        bytes
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.reduce().iter().fold(0, |acc, limb| acc | limb) == 0
+    }
+
+    // Parity of the canonical value (section 4.1 of RFC 9380).
+    pub fn sgn0(&self) -> u8 {
+        (self.reduce()[0] & 1) as u8
+    }
+
+    // Whether the canonical value is above (p - 1) / 2, which is when its
+    // double wraps around to an odd value.
+    pub fn is_negative(&self) -> bool {
+        (self + self).sgn0() == 1
+    }
+
+    fn carry(&self) -> FieldElement {
+        let mut h = self.0;
+        for i in 0..4 {
+            h[i + 1] += h[i] >> 51;
+            h[i] &= MASK;
+        }
+        h[0] += 19 * (h[4] >> 51);
+        h[4] &= MASK;
+        FieldElement(h)
+    }
+
+    pub fn square(&self) -> FieldElement {
+        self * self
+    }
+
+    fn pow(&self, exp: &[u8; 32]) -> FieldElement {
+        let mut acc = FieldElement::from_u64(1);
+        for byte in exp.iter().rev() {
+            for bit in (0..8).rev() {
+                acc = acc.square();
+                if (byte >> bit) & 1 == 1 {
+                    acc = &acc * self;
+                }
+            }
+        }
+        acc
+    }
+
+    // Inverse, with 0 mapping to 0.
+    pub fn invert(&self) -> FieldElement {
+        self.pow(&le_exp(0xeb, 0x7f))
+    }
+
+    pub fn is_square(&self) -> bool {
+        let l = self.pow(&le_exp(0xf6, 0x3f));
+        l.is_zero() || (&l - &FieldElement::from_u64(1)).is_zero()
+    }
+
+    // A square root, for p = 5 mod 8. Only called on squares.
+    pub fn sqrt(&self) -> FieldElement {
+        let r = self.pow(&le_exp(0xfe, 0x0f));
+        if (&r.square() - self).is_zero() {
+            r
+        } else {
+            &r * &SQRT_MINUS_ONE
+        }
+    }
+}
+
+impl<'a> ::std::ops::Add for &'a FieldElement {
+    type Output = FieldElement;
+
+    fn add(self, other: Self) -> FieldElement {
+        let mut h = [0u64; 5];
+        for i in 0..5 {
+            h[i] = self.0[i] + other.0[i];
+        }
+        FieldElement(h).carry()
+    }
+}
+
+impl<'a> ::std::ops::Sub for &'a FieldElement {
+    type Output = FieldElement;
+
+    fn sub(self, other: Self) -> FieldElement {
+        // Add 4p first so that no limb underflows.
+        let four_p = [(MASK - 18) * 4, MASK * 4, MASK * 4, MASK * 4, MASK * 4];
+        let (a, b) = (self.carry().0, other.carry().0);
+        let mut h = [0u64; 5];
+        for i in 0..5 {
+            h[i] = a[i] + four_p[i] - b[i];
+        }
+        FieldElement(h).carry()
+    }
+}
+
+impl<'a> ::std::ops::Neg for &'a FieldElement {
+    type Output = FieldElement;
+
+    fn neg(self) -> FieldElement {
+        &FieldElement::from_u64(0) - self
+    }
+}
+
+impl<'a> ::std::ops::Mul for &'a FieldElement {
+    type Output = FieldElement;
+
+    fn mul(self, other: Self) -> FieldElement {
+        let (a, b) = (self.carry().0, other.carry().0);
+        let m = |x: u64, y: u64| (x as u128) * (y as u128);
+        let (b1, b2, b3, b4) = (19 * b[1], 19 * b[2], 19 * b[3], 19 * b[4]);
+
+        let mut r = [
+            m(a[0], b[0]) + m(a[1], b4) + m(a[2], b3) + m(a[3], b2) + m(a[4], b1),
+            m(a[0], b[1]) + m(a[1], b[0]) + m(a[2], b4) + m(a[3], b3) + m(a[4], b2),
+            m(a[0], b[2]) + m(a[1], b[1]) + m(a[2], b[0]) + m(a[3], b4) + m(a[4], b3),
+            m(a[0], b[3]) + m(a[1], b[2]) + m(a[2], b[1]) + m(a[3], b[0]) + m(a[4], b4),
+            m(a[0], b[4]) + m(a[1], b[3]) + m(a[2], b[2]) + m(a[3], b[1]) + m(a[4], b[0]),
+        ];
+        for i in 0..4 {
+            r[i + 1] += r[i] >> 51;
+            r[i] &= MASK as u128;
+        }
+        r[0] += 19 * (r[4] >> 51);
+        r[4] &= MASK as u128;
+        r[1] += r[0] >> 51;
+        r[0] &= MASK as u128;
+
+        FieldElement([r[0] as u64, r[1] as u64, r[2] as u64, r[3] as u64, r[4] as u64]).carry()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Parses big-endian affine coordinates into a compressed point.
+    fn compress(x: &str, y: &str) -> [u8; 32] {
+        let parse = |s: &str| -> Vec<u8> {
+            (0..32).map(|i| u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap()).rev().collect()
+        };
+        let (x, y) = (parse(x), parse(y));
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(&y);
+        bytes[31] |= (x[0] & 1) << 7;
+        bytes
+    }
+
+    // Test vectors from appendix J.5.1 of RFC 9380.
+    #[test]
+    fn rfc9380_edwards25519_vectors() {
+        let dst = b"QUUX-V01-CS02-with-edwards25519_XMD:SHA-512_ELL2_RO_";
+        let vectors: [(&[u8], &str, &str); 2] = [
+            (b"",
+             "3c3da6925a3c3c268448dcabb47ccde5439559d9599646a8260e47b1e4822fc6",
+             "09a6c8561a0b22bef63124c588ce4c62ea83a3c899763af26d795302e115dc21"),
+            (b"abc",
+             "608040b42285cc0d72cbb3985c6b04c935370c7361f4b7fbdb1ae7f8c1a8ecad",
+             "1a8395b88338f22e435bbd301183e7f20a5f9de643f11882fb237f88268a5531"),
+        ];
+        for &(msg, x, y) in vectors.iter() {
+            let p = hash_to_curve(msg, dst);
+            assert_eq!(p.compress().as_bytes(), &compress(x, y));
+        }
+    }
+
+    #[test]
+    fn constants() {
+        let c = &SQRT_MINUS_486664;
+        assert!((&c.square() + &FieldElement::from_u64(486664)).is_zero());
+        assert_eq!(c.sgn0(), 0);
+        assert!((&SQRT_MINUS_ONE.square() + &FieldElement::from_u64(1)).is_zero());
+        assert_eq!(SQRT_MINUS_ONE.to_bytes(), FieldElement::from_u64(2).pow(&le_exp(0xfb, 0x1f)).to_bytes());
+    }
+
+    // Test vector from appendix K.3 of RFC 9380.
+    #[test]
+    fn rfc9380_expand_message_xmd_vector() {
+        let out = expand_message_xmd(b"", b"QUUX-V01-CS02-with-expander-SHA512-256", 32);
+        let hex: String = out.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(hex, "6b9a7312411d92f921c6f68ca0b6380730a1a4d982c507211a90964c394179ba");
+    }
+}
@@ -1,12 +1,27 @@
-use common::remove_padding;
-use super::Error;
+use common::{pad, remove_padding};
+use kh_prf::{u64_to_u8, u8_to_u64};
+use super::errors::{Error, ErrorKind, ResultExt};
 
 use std;
-use std::io::{Write, Read};
+use std::cmp::{max, min};
+use std::io::{Write, Read, Seek, SeekFrom, BufWriter};
+use std::ops::{Deref, DerefMut, Range};
 use std::fs::{File,OpenOptions};
 use std::path::Path;
 
 use ring::digest;
+use zeroize::Zeroizing;
+
+// As `read_exact`, but reports running out of input as a truncated ciphertext
+// at the offset where it ended, for a field starting at byte `offset`.
+pub fn read_field(reader: &mut Read, buf: &mut [u8], offset: u64) -> Result<(), Error> {
+    let bytes = read_chunk(reader, buf.len())?;
+    if bytes.len() < buf.len() {
+        return Err(ErrorKind::TruncatedCiphertext(offset + bytes.len() as u64).into());
+    }
+    buf.copy_from_slice(&bytes);
+    Ok(())
+}
 
 // Reads a chunk of up to len bytes. Any return value smaller than
 // len indicates EOF has been reached.
@@ -38,6 +53,103 @@ pub fn read_chunk(reader: &mut Read, len: usize) -> Result<Vec<u8>, Error> {
     Ok(result)
 }
 
+// Bytes read at a time by `SecretBuf::read_from` once the buffer is full.
+const READ_SIZE: usize = 8192;
+
+// A buffer for secret bytes, such as decrypted headers and serialised keys.
+// The contents are wiped when it is dropped, and whenever it grows the old
+// allocation is wiped too, which a `Zeroizing<Vec<u8>>` grown by
+// `read_to_end` or `write_all` would leave behind.
+#[derive(Default)]
+pub struct SecretBuf(Zeroizing<Vec<u8>>);
+
+impl SecretBuf {
+    pub fn new() -> SecretBuf {
+        SecretBuf::with_capacity(0)
+    }
+
+    pub fn with_capacity(capacity: usize) -> SecretBuf {
+        SecretBuf(Zeroizing::new(Vec::with_capacity(capacity)))
+    }
+
+    // Makes room for `additional` more bytes, moving the contents to a new
+    // allocation if there is not enough.
+    fn reserve(&mut self, additional: usize) {
+        let len = self.0.len();
+        if self.0.capacity() - len < additional {
+            let mut bigger = Zeroizing::new(Vec::with_capacity(max(2 * self.0.capacity(), len + additional)));
+            bigger.extend_from_slice(&self.0);
+            // Dropping the old buffer wipes it.
+            self.0 = bigger;
+        }
+    }
+
+    // Pads with zeros, or truncates, to `len` bytes.
+    pub fn resize(&mut self, len: usize) {
+        let cur = self.0.len();
+        if len > cur {
+            self.reserve(len - cur);
+        }
+        self.0.resize(len, 0);
+    }
+
+    // As `Read::read_to_end`, appending all of `reader` to the buffer.
+    pub fn read_from<R: Read + ?Sized>(&mut self, reader: &mut R) -> std::io::Result<usize> {
+        let start = self.0.len();
+        loop {
+            let len = self.0.len();
+            if len == self.0.capacity() {
+                self.reserve(READ_SIZE);
+            }
+            let capacity = self.0.capacity();
+            self.0.resize(capacity, 0);
+            let res = reader.read(&mut self.0[len..]);
+            match res {
+                Ok(0) => {
+                    self.0.truncate(len);
+                    return Ok(len - start);
+                },
+                Ok(n) => self.0.truncate(len + n),
+                Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => self.0.truncate(len),
+                Err(e) => {
+                    self.0.truncate(len);
+                    return Err(e);
+                },
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> Zeroizing<Vec<u8>> {
+        self.0
+    }
+}
+
+impl Deref for SecretBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl DerefMut for SecretBuf {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+impl Write for SecretBuf {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.reserve(buf.len());
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
 // Writes bytes to a writer (unless bytes is empty). If padded = true,
 // and bytes is non-empty, padding is first removed from bytes.
 pub fn write_pt(block: Vec<u8>, writer: &mut Write, padded: bool) 
@@ -62,6 +174,357 @@ pub fn write_pt(block: Vec<u8>, writer: &mut Write, padded: bool)
     Ok(())
 }
 
+// Decrypts the plaintext bytes in `range` from a counter-mode ciphertext which
+// starts at the current position of `ct`, where `f(block, ctr)` decrypts a
+// single block. Only the blocks covering `range` (and the final block, to find
+// the length of the padding) are read. `range` is clamped to the plaintext.
+pub fn decrypt_range<In, Out, F>(ct: &mut In, pt: &mut Out, range: Range<u64>,
+        pt_block_size: usize, ct_block_size: usize, ctr: u64, f: F) -> Result<(), Error>
+    where In: Read + Seek, Out: Write, F: Fn(&[u8], u64) -> Result<Vec<u8>, Error>
+{
+    let (pt_bs, ct_bs) = (pt_block_size as u64, ct_block_size as u64);
+    let start = ct.seek(SeekFrom::Current(0))?;
+    let ct_len = ct.seek(SeekFrom::End(0))? - start;
+    let n_blocks = ct_len / ct_bs;
+    if ct_len % ct_bs != 0 {
+        return Err(ErrorKind::TruncatedBlock(n_blocks, (ct_len % ct_bs) as usize).into());
+    }
+    if n_blocks == 0 {
+        return Err(ErrorKind::TruncatedBlock(0, 0).into());
+    }
+    if ctr.checked_add(n_blocks - 1).is_none() {
+        return Err(ErrorKind::CounterOverflow.into());
+    }
+
+    // The last block determines the length of the plaintext.
+    ct.seek(SeekFrom::Start(start + (n_blocks - 1) * ct_bs))?;
+    let last_block = read_chunk(ct, ct_block_size)?;
+    let last_pt = f(&last_block, ctr + n_blocks - 1)
+        .chain_err(|| ErrorKind::InvalidBlock(n_blocks - 1))?;
+    let last_pt = remove_padding(last_pt).ok_or(ErrorKind::InvalidBlock(n_blocks - 1))?;
+    let pt_len = (n_blocks - 1) * pt_bs + last_pt.len() as u64;
+
+    let end = min(range.end, pt_len);
+    if range.start >= end {
+        return Ok(());
+    }
+    let first = range.start / pt_bs;
+    let last = (end - 1) / pt_bs;
+
+    ct.seek(SeekFrom::Start(start + first * ct_bs))?;
+    for i in first..last + 1 {
+        let block = if i == n_blocks - 1 {
+            last_pt.clone()
+        } else {
+            let chunk = read_chunk(ct, ct_block_size)?;
+            if chunk.len() != ct_block_size {
+                return Err(ErrorKind::TruncatedBlock(i, chunk.len()).into());
+            }
+            f(&chunk, ctr + i).chain_err(|| ErrorKind::InvalidBlock(i))?
+        };
+
+        // Write out the part of this block which lies in the range.
+        let block_start = i * pt_bs;
+        let lo = (max(range.start, block_start) - block_start) as usize;
+        let hi = (min(end, block_start + block.len() as u64) - block_start) as usize;
+        pt.write_all(&block[lo..hi])?;
+    }
+    Ok(())
+}
+
+// Decrypts a counter-mode ciphertext, where `f(block, i)` decrypts block `i`,
+// and removes the padding from the final block.
+pub fn decrypt_blocks<In, Out, F>(ct: &mut In, pt: &mut Out, ct_block_size: usize, f: F) -> Result<(), Error>
+    where In: Read, Out: Write, F: Fn(&[u8], u64) -> Result<Vec<u8>, Error>
+{
+    let mut prev_pt_block = Vec::new();
+    let mut i = 0;
+    loop {
+        let chunk = read_chunk(ct, ct_block_size)?;
+        let eof = match chunk.len() {
+            0 => true,
+            n if n != ct_block_size => {
+                return Err(ErrorKind::TruncatedBlock(i, n).into());
+            },
+            _ => false,
+        };
+
+        // Only the final block carries padding.
+        write_pt(prev_pt_block, pt, eof)?;
+
+        if eof {
+            break;
+        }
+
+        prev_pt_block = f(&chunk, i).chain_err(|| ErrorKind::InvalidBlock(i))?;
+        i += 1;
+    }
+    Ok(())
+}
+
+// Appends `pt` to a counter-mode ciphertext which starts at the current
+// position of `ct`, where `f(block, ctr)` decrypts and `g(block, ctr)`
+// encrypts a single block. The padded final block is decrypted and encrypted
+// again along with the start of `pt`, and later blocks continue the counter.
+pub fn append<F, In, D, E>(ct: &mut F, pt: &mut In, pt_block_size: usize, ct_block_size: usize,
+        ctr: u64, f: D, g: E) -> Result<(), Error>
+    where F: Read + Write + Seek, In: Read,
+          D: Fn(&[u8], u64) -> Result<Vec<u8>, Error>, E: Fn(&[u8], u64) -> Result<Vec<u8>, Error>
+{
+    let ct_bs = ct_block_size as u64;
+    let start = ct.seek(SeekFrom::Current(0))?;
+    let ct_len = ct.seek(SeekFrom::End(0))? - start;
+    let n_blocks = ct_len / ct_bs;
+    if ct_len % ct_bs != 0 {
+        return Err(ErrorKind::TruncatedBlock(n_blocks, (ct_len % ct_bs) as usize).into());
+    }
+    if n_blocks == 0 {
+        return Err(ErrorKind::TruncatedBlock(0, 0).into());
+    }
+    let mut ctr = ctr.checked_add(n_blocks - 1).ok_or(ErrorKind::CounterOverflow)?;
+
+    // Strip the padding from the final block, and start writing over it.
+    ct.seek(SeekFrom::Start(start + (n_blocks - 1) * ct_bs))?;
+    let last_block = read_chunk(ct, ct_block_size)?;
+    let tail = f(&last_block, ctr).chain_err(|| ErrorKind::InvalidBlock(n_blocks - 1))?;
+    let tail = remove_padding(tail).ok_or(ErrorKind::InvalidBlock(n_blocks - 1))?;
+    ct.seek(SeekFrom::Start(start + (n_blocks - 1) * ct_bs))?;
+
+    let mut reader = (&tail[..]).chain(pt);
+    let mut writer = BufWriter::new(ct);
+    let mut eof = false;
+    while !eof {
+        let mut block = read_chunk(&mut reader, pt_block_size)?;
+
+        // Pad if it's not a full block.
+        if block.len() < pt_block_size {
+            pad(&mut block, pt_block_size);
+            eof = true;
+        }
+
+        writer.write_all(&g(&block, ctr)?)?;
+        if !eof {
+            ctr = ctr.checked_add(1).ok_or(ErrorKind::CounterOverflow)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// Number of blocks covered by each journal entry when updating a
+// ciphertext in place.
+pub const BLOCKS_PER_ENTRY: usize = 1024;
+
+// States of a journal entry.
+const IN_PROGRESS: u8 = 0;
+const COMPLETE: u8 = 1;
+
+// Size of the fixed fields of a journal entry: index, state, offset and
+// length, plus the trailing checksum.
+const ENTRY_OVERHEAD: usize = 8 + 1 + 8 + 8 + 32;
+
+struct Entry {
+    id: Vec<u8>,
+    index: u64,
+    state: u8,
+    offset: u64,
+    data: Vec<u8>,
+}
+
+// A journal for overwriting a file one region at a time, so that an
+// interrupted update can be resumed.
+//
+// Before region `index` is overwritten, its old contents are recorded as
+// `id || index || state || offset || len || data || SHA-256(..)`, where `id`
+// identifies the update (e.g. a hash of the token). Entries alternate between
+// two fixed-size slots, so a torn write to one slot leaves the previous entry
+// intact. Note that only `flush` is called on the file and the journal, so
+// surviving a power loss relies on the writers making flushes durable.
+pub struct Journal<'a, J: 'a> {
+    journal: &'a mut J,
+    id: Vec<u8>,
+    data_len: usize,
+}
+
+impl<'a, J: Read + Write + Seek> Journal<'a, J> {
+    // Creates a journal for regions of up to `data_len` bytes.
+    pub fn new(journal: &'a mut J, id: &[u8], data_len: usize) -> Journal<'a, J> {
+        Journal { journal: journal, id: id.to_vec(), data_len: data_len }
+    }
+
+    fn slot_len(&self) -> usize {
+        self.id.len() + ENTRY_OVERHEAD + self.data_len
+    }
+
+    fn write_entry(&mut self, slot: u64, index: u64, state: u8, offset: u64, data: &[u8]) -> Result<(), Error> {
+        let mut entry = self.id.clone();
+        entry.extend_from_slice(&u64_to_u8(index));
+        entry.push(state);
+        entry.extend_from_slice(&u64_to_u8(offset));
+        entry.extend_from_slice(&u64_to_u8(data.len() as u64));
+        entry.extend_from_slice(data);
+        let len = self.slot_len() - 32;
+        entry.resize(len, 0);
+        let checksum = h!(&entry);
+        entry.extend_from_slice(checksum.as_ref());
+
+        self.journal.seek(SeekFrom::Start(slot * self.slot_len() as u64))?;
+        self.journal.write_all(&entry)?;
+        self.journal.flush()?;
+        Ok(())
+    }
+
+    // Parses a slot, if it holds a whole entry with a valid checksum.
+    fn parse_entry(&self, slot: &[u8]) -> Option<Entry> {
+        if slot.len() != self.slot_len() {
+            return None;
+        }
+        let (entry, checksum) = slot.split_at(slot.len() - 32);
+        if h!(entry).as_ref() != checksum {
+            return None;
+        }
+        let (id, rest) = entry.split_at(self.id.len());
+        let field = |i: usize| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&rest[i..i + 8]);
+            u8_to_u64(bytes)
+        };
+        let len = field(17);
+        if len > self.data_len as u64 {
+            return None;
+        }
+        Some(Entry {
+            id: id.to_vec(),
+            index: field(0),
+            state: rest[8],
+            offset: field(9),
+            data: rest[25..25 + len as usize].to_vec(),
+        })
+    }
+
+    // Restores the region which was being overwritten when a previous run
+    // stopped, and returns the index to carry on from. Returns `None` if the
+    // journal records that this update already finished.
+    pub fn recover<F: Write + Seek>(&mut self, file: &mut F) -> Result<Option<u64>, Error> {
+        let mut buf = Vec::new();
+        self.journal.seek(SeekFrom::Start(0))?;
+        self.journal.read_to_end(&mut buf)?;
+
+        let mut latest: Option<Entry> = None;
+        for slot in buf.chunks(self.slot_len()).take(2) {
+            let entry = match self.parse_entry(slot) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            if entry.id != self.id {
+                // Finished updates write a complete entry to both slots.
+                if entry.state != COMPLETE {
+                    return Err(ErrorKind::InvalidJournal("belongs to an unfinished update with a different token").into());
+                }
+                continue;
+            }
+            if latest.as_ref().map_or(true, |l| entry.index > l.index) {
+                latest = Some(entry);
+            }
+        }
+
+        match latest {
+            None => Ok(Some(0)),
+            Some(ref entry) if entry.state == COMPLETE => Ok(None),
+            Some(entry) => {
+                file.seek(SeekFrom::Start(entry.offset))?;
+                file.write_all(&entry.data)?;
+                file.flush()?;
+                Ok(Some(entry.index))
+            }
+        }
+    }
+
+    // Overwrites the `len` bytes at `offset` with `f(old)`, after recording
+    // the old contents as entry `index`.
+    pub fn rewrite<F, G>(&mut self, file: &mut F, index: u64, offset: u64, len: usize, f: G) -> Result<(), Error>
+        where F: Read + Write + Seek, G: FnOnce(&[u8]) -> Result<Vec<u8>, Error>
+    {
+        file.seek(SeekFrom::Start(offset))?;
+        let old = read_chunk(file, len)?;
+        if old.len() != len {
+            return Err(ErrorKind::TruncatedCiphertext(offset + old.len() as u64).into());
+        }
+        let new = f(&old)?;
+        if new.len() != len {
+            return Err(ErrorKind::InvalidJournal("update changed the length of a region").into());
+        }
+
+        self.write_entry(index % 2, index, IN_PROGRESS, offset, &old)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(&new)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    // Records that all `n` regions have been overwritten.
+    pub fn finish(&mut self, n: u64) -> Result<(), Error> {
+        self.write_entry(n % 2, n, COMPLETE, 0, &[])?;
+        self.write_entry((n + 1) % 2, n, COMPLETE, 0, &[])
+    }
+}
+
+// Updates the counter-mode blocks from `body` to the end of `file` in place,
+// where `f(block, ctr)` updates a single block. Each run of
+// `BLOCKS_PER_ENTRY` blocks is one journal entry, starting from entry `from`.
+// Returns the number of entries covering the body.
+pub fn update_in_place<F, J, G>(file: &mut F, journal: &mut Journal<J>, body: u64,
+        ct_block_size: usize, ctr: u64, from: u64, f: G) -> Result<u64, Error>
+    where F: Read + Write + Seek, J: Read + Write + Seek, G: Fn(&[u8], u64) -> Result<Vec<u8>, Error>
+{
+    let n_blocks = body_blocks(file, body, ct_block_size)?;
+    update_range(file, journal, body, ct_block_size, ctr, 0..n_blocks, from, f)
+}
+
+// As `update_in_place`, but only for the blocks in `blocks`. The entries
+// start from block `blocks.start`.
+pub fn update_range<F, J, G>(file: &mut F, journal: &mut Journal<J>, body: u64,
+        ct_block_size: usize, ctr: u64, blocks: Range<u64>, from: u64, f: G) -> Result<u64, Error>
+    where F: Read + Write + Seek, J: Read + Write + Seek, G: Fn(&[u8], u64) -> Result<Vec<u8>, Error>
+{
+    let ct_bs = ct_block_size as u64;
+    let n_blocks = body_blocks(file, body, ct_block_size)?;
+    if blocks.end > n_blocks {
+        return Err(ErrorKind::InvalidArgument("block range extends past the end of the ciphertext").into());
+    }
+    if n_blocks > 0 && ctr.checked_add(n_blocks - 1).is_none() {
+        return Err(ErrorKind::CounterOverflow.into());
+    }
+
+    let per_entry = BLOCKS_PER_ENTRY as u64;
+    let n_entries = (blocks.end.saturating_sub(blocks.start) + per_entry - 1) / per_entry;
+    for i in from..n_entries {
+        let first = blocks.start + i * per_entry;
+        let count = min(per_entry, blocks.end - first);
+        journal.rewrite(file, i, body + first * ct_bs, (count * ct_bs) as usize, |old| {
+            let mut new = Vec::with_capacity(old.len());
+            for (j, block) in old.chunks(ct_block_size).enumerate() {
+                let idx = first + j as u64;
+                new.extend(f(block, ctr + idx).chain_err(|| ErrorKind::InvalidBlock(idx))?);
+            }
+            Ok(new)
+        })?;
+    }
+    Ok(n_entries)
+}
+
+// Number of blocks from `body` to the end of `file`.
+fn body_blocks<F: Seek>(file: &mut F, body: u64, ct_block_size: usize) -> Result<u64, Error> {
+    let ct_bs = ct_block_size as u64;
+    let end = file.seek(SeekFrom::End(0))?;
+    let ct_len = end.checked_sub(body).ok_or(ErrorKind::TruncatedCiphertext(end))?;
+    let n_blocks = ct_len / ct_bs;
+    if ct_len % ct_bs != 0 {
+        return Err(ErrorKind::TruncatedBlock(n_blocks, (ct_len % ct_bs) as usize).into());
+    }
+    Ok(n_blocks)
+}
+
 pub fn open_file<P: AsRef<Path>>(path: P) -> File {
     OpenOptions::new().read(true).write(true).create(true).open(path).unwrap()
 }
@@ -82,6 +545,19 @@ impl<T> RwAndHash<T> {
     pub fn finish(self) -> digest::Digest {
         self.hash.finish()
     }
+
+    // Returns the hash so far, without finishing.
+    pub fn digest(&self) -> digest::Digest {
+        self.hash.clone().finish()
+    }
+
+    // Continues the same hash over a new reader or writer.
+    pub fn chain<U>(self, inner: U) -> RwAndHash<U> {
+        RwAndHash {
+            hash: self.hash,
+            rw: inner,
+        }
+    }
 }
 
 impl<T: Read> Read for RwAndHash<T> {
@@ -120,6 +596,17 @@ mod test {
         assert_eq!(buf, &input[..]);
     }
 
+    #[test]
+    fn test_secret_buf_grows() {
+        let input: Vec<u8> = (0..20000).map(|i| i as u8).collect();
+        let mut buf = SecretBuf::with_capacity(3);
+        buf.write_all(&input[..5]).unwrap();
+        buf.read_from(&mut &input[5..]).unwrap();
+        assert_eq!(&buf[..], &input[..]);
+        buf.resize(4);
+        assert_eq!(&buf[..], &input[..4]);
+    }
+
     #[test]
     fn test_hash_and_write() {
         let mut buf = Vec::new();
@@ -0,0 +1,194 @@
+//! Master keys derived from a passphrase.
+//!
+//! `KdfParams` holds a key-derivation function, its cost parameters and a
+//! salt. They are stored in the container alongside the ciphertext (see
+//! `Container::encrypt_container_with_password`), so the same passphrase
+//! derives the same key again on decryption. Only keys which are uniform
+//! random bytes, as for the AEAD header ciphers, can be derived.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use ring::rand::SecureRandom;
+use ring::{digest, pbkdf2, rand};
+
+use super::errors::*;
+use super::Key;
+use io::read_field;
+use kh_prf::{u64_to_u8, u8_to_u64};
+use ring_ae::{AesKey128, AesKey256, ChaChaKey256};
+use rustcrypto_ae::{AesGcmSivKey256, XChaChaKey256};
+
+use std::io::{Read, Write};
+
+use zeroize::Zeroizing;
+
+/// Length of the salt chosen by `KdfParams::new`, and the shortest salt
+/// accepted when reading parameters or deriving a key.
+pub const SALT_SIZE: usize = 16;
+
+/// Largest PBKDF2 iteration count accepted.
+pub const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+/// Largest Argon2 memory cost accepted, in KiB: 2 GiB, as in the first
+/// recommended option of RFC 9106.
+pub const MAX_ARGON2_MEMORY_KIB: u32 = 2 * 1024 * 1024;
+/// Largest number of Argon2 passes accepted.
+pub const MAX_ARGON2_ITERATIONS: u32 = 16;
+/// Largest Argon2 parallelism accepted.
+pub const MAX_ARGON2_PARALLELISM: u32 = 16;
+
+// Identifiers for each KDF in the encoded parameters.
+const PBKDF2_SHA256: u8 = 1;
+const ARGON2ID: u8 = 2;
+
+/// Keys made of uniformly random bytes, which can be the output of a KDF.
+pub trait DerivableKey: Key {
+    /// Length of the key in bytes.
+    fn key_len() -> usize;
+}
+
+macro_rules! derivable_key {
+    ($keyname:ident, $len:expr) => (
+        impl DerivableKey for $keyname {
+            fn key_len() -> usize {
+                $len
+            }
+        }
+    )
+}
+
+derivable_key!(AesKey128, 16);
+derivable_key!(AesKey256, 32);
+derivable_key!(ChaChaKey256, 32);
+derivable_key!(AesGcmSivKey256, 32);
+derivable_key!(XChaChaKey256, 32);
+
+/// A password-based key-derivation function and its cost parameters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kdf {
+    /// PBKDF2-HMAC-SHA256 from *ring*.
+    Pbkdf2Sha256 { iterations: u32 },
+    /// Argon2id (RFC 9106), with memory cost in KiB.
+    Argon2id { memory_kib: u32, iterations: u32, parallelism: u32 },
+}
+
+impl Default for Kdf {
+    /// Argon2id with 19 MiB of memory and 2 passes, following the OWASP
+    /// recommendation.
+    fn default() -> Kdf {
+        Kdf::Argon2id { memory_kib: 19 * 1024, iterations: 2, parallelism: 1 }
+    }
+}
+
+/// A KDF with the salt for one key.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KdfParams {
+    pub kdf: Kdf,
+    pub salt: Vec<u8>,
+}
+
+impl KdfParams {
+    /// Parameters for a new key, with a random salt.
+    pub fn new(kdf: Kdf) -> KdfParams {
+        let mut salt = vec![0u8; SALT_SIZE];
+        let rng = rand::SystemRandom::new();
+        rng.fill(&mut salt).expect("could not generate random bytes for salt");
+        KdfParams { kdf: kdf, salt: salt }
+    }
+
+    /// Checks that the salt is at least `SALT_SIZE` bytes and the costs are
+    /// within the `MAX_` limits, since parameters read from a container
+    /// could otherwise make deriving the key arbitrarily expensive.
+    pub fn check(&self) -> Result<()> {
+        if self.salt.len() < SALT_SIZE {
+            return Err(ErrorKind::InvalidKdf("salt too short").into());
+        }
+        match self.kdf {
+            Kdf::Pbkdf2Sha256 { iterations } => {
+                if iterations == 0 {
+                    return Err(ErrorKind::InvalidKdf("PBKDF2 needs at least one iteration").into());
+                }
+                if iterations > MAX_PBKDF2_ITERATIONS {
+                    return Err(ErrorKind::InvalidKdf("too many PBKDF2 iterations").into());
+                }
+            },
+            Kdf::Argon2id { memory_kib, iterations, parallelism } => {
+                if memory_kib > MAX_ARGON2_MEMORY_KIB || iterations > MAX_ARGON2_ITERATIONS
+                    || parallelism > MAX_ARGON2_PARALLELISM {
+                    return Err(ErrorKind::InvalidKdf("Argon2 cost parameters too large").into());
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Derives a key from `password`, after `check`ing the parameters.
+    pub fn derive_key<K: DerivableKey>(&self, password: &[u8]) -> Result<K> {
+        self.check()?;
+        let mut bytes = Zeroizing::new(vec![0u8; K::key_len()]);
+        match self.kdf {
+            Kdf::Pbkdf2Sha256 { iterations } => {
+                pbkdf2::derive(&digest::SHA256, iterations, &self.salt, password, &mut bytes);
+            },
+            Kdf::Argon2id { memory_kib, iterations, parallelism } => {
+                let params = Params::new(memory_kib, iterations, parallelism, Some(bytes.len()))
+                    .map_err(|_| ErrorKind::InvalidKdf("Argon2 cost parameters out of range"))?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(password, &self.salt, &mut bytes)
+                    .map_err(|_| ErrorKind::InvalidKdf("rejected by Argon2"))?;
+            },
+        }
+        K::read_key_file(&mut &bytes[..])
+    }
+
+    /// Writes the parameters as `kdf id || costs || salt length (1 byte) ||
+    /// salt`, where each cost is a big-endian `u32`. Parameters which fail
+    /// `check` are refused, as they could not be read back.
+    pub fn write<Out: Write>(&self, out: &mut Out) -> Result<()> {
+        self.check()?;
+        if self.salt.len() > 255 {
+            return Err(ErrorKind::InvalidKdf("salt too long").into());
+        }
+        let (id, costs) = match self.kdf {
+            Kdf::Pbkdf2Sha256 { iterations } => (PBKDF2_SHA256, vec![iterations]),
+            Kdf::Argon2id { memory_kib, iterations, parallelism } => {
+                (ARGON2ID, vec![memory_kib, iterations, parallelism])
+            },
+        };
+        out.write_all(&[id])?;
+        for cost in costs {
+            out.write_all(&u64_to_u8(cost as u64)[4..])?;
+        }
+        out.write_all(&[self.salt.len() as u8])?;
+        out.write_all(&self.salt)?;
+        Ok(())
+    }
+
+    /// Reads parameters written by `write`, rejecting any which fail
+    /// `check`.
+    pub fn read<In: Read>(input: &mut In) -> Result<KdfParams> {
+        let mut id = [0u8; 1];
+        read_field(input, &mut id, 0)?;
+        let n_costs = match id[0] {
+            PBKDF2_SHA256 => 1,
+            ARGON2ID => 3,
+            _ => return Err(ErrorKind::InvalidKdf("unknown key-derivation function").into()),
+        };
+        let mut costs = Vec::with_capacity(n_costs);
+        for i in 0..n_costs {
+            let mut cost = [0u8; 8];
+            read_field(input, &mut cost[4..], 1 + 4 * i as u64)?;
+            costs.push(u8_to_u64(cost) as u32);
+        }
+        let mut salt_len = [0u8; 1];
+        read_field(input, &mut salt_len, 1 + 4 * n_costs as u64)?;
+        let mut salt = vec![0u8; salt_len[0] as usize];
+        read_field(input, &mut salt, 2 + 4 * n_costs as u64)?;
+
+        let kdf = match id[0] {
+            PBKDF2_SHA256 => Kdf::Pbkdf2Sha256 { iterations: costs[0] },
+            _ => Kdf::Argon2id { memory_kib: costs[0], iterations: costs[1], parallelism: costs[2] },
+        };
+        let params = KdfParams { kdf: kdf, salt: salt };
+        params.check()?;
+        Ok(params)
+    }
+}
@@ -1,35 +1,133 @@
-use curve25519_dalek;
-use curve25519_dalek::curve::ExtendedPoint;
-use curve25519_dalek::scalar::Scalar;
+use dalek::edwards::{CompressedEdwardsY, EdwardsPoint};
+use dalek::scalar::Scalar;
 
+use rand::Rng;
 use rand::os::OsRng;
 
 use std::io::{Read,Write,BufReader,BufWriter};
+use std::fmt;
 use std::ops::{Add, Sub};
 
+use zeroize::{Zeroize, Zeroizing};
+
 use super::*;
 use common::pad;
+use elligator;
+use hash_to_curve;
 use io::*;
 
 /// Encryption using a key-homomorphic PRF.
 ///
 /// This is the generic "counter-mode" encryption: `E(k, m) = (m_1 + F(k, 1), ...)`. 
+///
+/// Each ciphertext starts with a random nonce, which is hashed along with the
+/// counter, so that files encrypted under the same key do not share a
+/// keystream. The nonce is copied unchanged on `reencrypt`, so a token works
+/// for every ciphertext under a key pair. Keys using `HashSuite::Legacy` keep
+/// the original format, which has no nonce.
 pub struct KhPrf;
 
-#[derive(Clone, Debug, PartialEq)]
-pub struct KhKey(pub Scalar, pub u64);
+/// Size of the random nonce at the start of each ciphertext.
+pub const NONCE_SIZE: usize = 16;
+
+/// Construction used to hash counters and tags to the curve.
+///
+/// The suite is recorded in the key version, so keys (and ciphertexts) from
+/// before the RFC 9380 suite was added keep using the original construction.
+/// Keys with different suites cannot be combined.
+///
+/// The suite also fixes the ciphertext format: `Legacy` ciphertexts have no
+/// nonce, and hash the counter alone, exactly as before either was added.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HashSuite {
+    /// Elligator on a SHA-256 hash, plus a multiple of the base point from a
+    /// second hash, with no nonce.
+    Legacy,
+    /// RFC 9380 `edwards25519_XMD:SHA-512_ELL2_RO_`.
+    Rfc9380,
+}
+
+impl HashSuite {
+    fn key_version(&self) -> u8 {
+        match *self {
+            HashSuite::Legacy => 1,
+            HashSuite::Rfc9380 => 2,
+        }
+    }
+}
+
+// Domain separation tags for the RFC 9380 suite.
+const COUNTER_DST: &'static [u8] = b"RECRYPT-V01-CS01-KhPrf-counter-with-edwards25519_XMD:SHA-512_ELL2_RO_";
+const TAG_DST: &'static [u8] = b"RECRYPT-V01-CS01-KhPrf-tag-with-edwards25519_XMD:SHA-512_ELL2_RO_";
+
+/// Key for `KhPrf`: a random scalar, the starting counter, and the hash suite.
+///
+/// Keys (and hence tokens) were originally written as the 32-byte scalar
+/// followed by a single counter byte. Since a reduced scalar is less than
+/// 2^253, the top bit of its last byte is never set in that encoding. The
+/// current encoding sets this bit to mark itself, then follows the scalar
+/// with a version byte and the full counter as a big-endian `u64`. Version 1
+/// (and the original encoding) use `HashSuite::Legacy`, and version 2 uses
+/// `HashSuite::Rfc9380`.
+///
+/// Counter 0 is reserved for the `ReCrypt` tag, so keys starting there are
+/// rejected.
+#[derive(Clone, PartialEq)]
+pub struct KhKey(pub Scalar, pub u64, pub HashSuite);
+
+impl Drop for KhKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for KhKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "KhKey(<redacted>, {}, {:?})", self.1, self.2)
+    }
+}
+
+// Marks the versioned key encoding, in the last byte of the scalar.
+const VERSIONED_FLAG: u8 = 0x80;
 
 impl Key for KhKey {
     fn read_key<In: Read>(key_in: &mut In) -> Result<Self> {
-        let mut bytes = [0u8; 32];
-        let mut ctr = [0u8; 1];
-        key_in.read_exact(&mut bytes)?;
-        key_in.read_exact(&mut ctr)?;
-        Ok(KhKey(Scalar(bytes), ctr[0] as u64))
+        let mut bytes = Zeroizing::new([0u8; 32]);
+        key_in.read_exact(&mut *bytes)?;
+
+        let (ctr, suite) = if bytes[31] & VERSIONED_FLAG == 0 {
+            // Legacy 33-byte encoding with a single counter byte
+            let mut ctr = [0u8; 1];
+            key_in.read_exact(&mut ctr)?;
+            (ctr[0] as u64, HashSuite::Legacy)
+        } else {
+            bytes[31] &= !VERSIONED_FLAG;
+            let mut version = [0u8; 1];
+            key_in.read_exact(&mut version)?;
+            let suite = match version[0] {
+                1 => HashSuite::Legacy,
+                2 => HashSuite::Rfc9380,
+                v => return Err(ErrorKind::UnsupportedKeyVersion(v).into()),
+            };
+            let mut ctr = [0u8; 8];
+            key_in.read_exact(&mut ctr)?;
+            (u8_to_u64(ctr), suite)
+        };
+
+        if ctr == 0 {
+            return Err(ErrorKind::InvalidKey("counter overlaps the reserved tag counter").into());
+        }
+        Ok(KhKey(Scalar::from_bytes_mod_order(*bytes), ctr, suite))
     }
     fn write_key<Out: Write>(&self, key_out: &mut Out) -> Result<()> {
-        key_out.write_all(&(&self.0).0).chain_err(|| "failed writing to file")?;
-        key_out.write_all(&[self.1 as u8]).chain_err(|| "failed writing to file")
+        let mut bytes = self.0.to_bytes();
+        bytes[31] |= VERSIONED_FLAG;
+        let res = key_out.write_all(&bytes);
+        bytes.zeroize();
+        res?;
+        key_out.write_all(&[self.2.key_version()])?;
+        key_out.write_all(&u64_to_u8(self.1))?;
+        Ok(())
     }
 }
 
@@ -37,15 +135,21 @@ impl<'a> Add for &'a KhKey {
     type Output = KhKey;
 
     fn add(self, other: Self) -> Self::Output {
-        KhKey(&self.0 + &other.0, self.1)
+        // `Scalar` is `Copy`, so wipe the copy left behind by the sum.
+        let mut sum = &self.0 + &other.0;
+        let key = KhKey(sum, self.1, self.2);
+        sum.zeroize();
+        key
     }
 }
 
+// Advances the starting counter, failing rather than wrapping around.
 impl Add<u64> for KhKey {
-    type Output = KhKey;
+    type Output = Result<KhKey>;
 
-    fn add(self, other: u64) -> KhKey {
-        KhKey(self.0, self.1 + other)
+    fn add(mut self, other: u64) -> Result<KhKey> {
+        self.1 = self.1.checked_add(other).ok_or(ErrorKind::CounterOverflow)?;
+        Ok(self)
     }
 }
 
@@ -53,7 +157,10 @@ impl<'a> Sub for &'a KhKey {
     type Output = KhKey;
 
     fn sub(self, other: Self) -> Self::Output {
-        KhKey(&self.0 - &other.0, self.1)
+        let mut diff = &self.0 - &other.0;
+        let key = KhKey(diff, self.1, self.2);
+        diff.zeroize();
+        key
     }
 }
 
@@ -63,20 +170,22 @@ impl Cipher for KhPrf {
 
      // Generate a random encryption key
     fn keygen() -> Self::K {
-        let mut rng = OsRng::new().unwrap();
-        KhKey(Scalar::random(&mut rng), 1)
+        KhPrf::keygen_with_suite(HashSuite::Rfc9380)
     }
 
     fn encrypt<In: Read, Out: Write>(key: Self::K, pt: &mut In, ct: &mut Out) -> Result<()> {
-        let mut ctr = key.1;
+        let mut i = 0;
         let pt_block_size = 31;
      
         let mut reader = BufReader::new(pt);
         let mut writer = BufWriter::new(ct);
 
+        let nonce = new_nonce(Self::nonce_size(&key));
+        writer.write_all(&nonce)?;
+
         let mut eof = false;
         while !eof {
-            let mut block = read_chunk(&mut reader, pt_block_size).unwrap();
+            let mut block = read_chunk(&mut reader, pt_block_size)?;
 
             // Pad if it's not a full block.
             if block.len() < pt_block_size {
@@ -84,11 +193,9 @@ impl Cipher for KhPrf {
                 eof = true;
             }
 
-            let ct_block = encrypt_block(key.0, &block, ctr);
+            let ct_block = encrypt_block(&key, &block, &nonce, block_ctr(key.1, i)?);
             writer.write_all(&ct_block)?;
-
-            // Increment ctr for each block
-            ctr += 1;
+            i += 1;
         }
         Ok(())
     }
@@ -97,17 +204,20 @@ impl Cipher for KhPrf {
         let mut reader = BufReader::new(ct);
         let mut writer = BufWriter::new(pt);
         let ct_block_size = 32;
-        let mut ctr = key.1;
+        let mut i = 0;
+        let nonce = read_nonce(&mut reader, Self::nonce_size(&key))?;
 
         let mut prev_pt_block = Vec::new();
         loop {
-            let chunk = read_chunk(&mut reader, ct_block_size).unwrap();
+            let chunk = read_chunk(&mut reader, ct_block_size)?;
 
             // Ciphertext files are expected to be exact multiples of CT_BLOCK_SIZE.
             // Check for EOF or other unexpected block sizes.
             let eof = match chunk.len() {
                 0 => true,
-                n if n != ct_block_size => { return Err("incorrect block size".into()); },
+                n if n != ct_block_size => {
+                    return Err(ErrorKind::TruncatedBlock(i, n).into());
+                },
                 _ => false,
             };
 
@@ -123,10 +233,9 @@ impl Cipher for KhPrf {
 
             // Decode the EcPoint and decrypt.
             // let point = EcPoint::from_bytes(&chunk).unwrap();
-            prev_pt_block = decrypt_block(key.0, &chunk, ctr)?;
-
-            // Increment ctr for each block
-            ctr += 1;
+            prev_pt_block = decrypt_block(&key, &chunk, &nonce, block_ctr(key.1, i)?)
+                .chain_err(|| ErrorKind::InvalidBlock(i))?;
+            i += 1;
         }
         Ok(())
     }
@@ -140,7 +249,7 @@ impl UpEncCtxtIndep for KhPrf {
         let ct_block_size = 32;
 
         // Read the re-keying token from the file.
-        let rk_token = Self::K::read_key(rk_file)?;
+        let rk_token = Self::K::read_key(rk_file).chain_err(|| ErrorKind::InvalidToken)?;
 
         // // Read the counter from the file.
         // let mut ctr_bytes = [0u8;8];
@@ -153,17 +262,24 @@ impl UpEncCtxtIndep for KhPrf {
         // updates.
         // try_or_panic!(writer.write(&ctr_bytes));
         // let mut ctr = u8_to_u64(ctr_bytes);
-        let mut ctr = rk_token.1;
-        
+        let mut i = 0;
+
+        // The nonce is carried over as-is. The token uses the same hash
+        // suite as the keys, so it also says whether there is one.
+        let nonce = read_nonce(&mut reader, Self::nonce_size(&rk_token))?;
+        writer.write_all(&nonce)?;
+
         // Read and update each ciphertext block of the file.
         loop {
-            let chunk = read_chunk(&mut reader, ct_block_size).unwrap();
+            let chunk = read_chunk(&mut reader, ct_block_size)?;
             match chunk.len() {
                 // EOF
                 0 => break,
 
                 // Woah, buddy.
-                n if n != ct_block_size => return Err("incorrect block size".into()),
+                n if n != ct_block_size => {
+                    return Err(ErrorKind::TruncatedBlock(i, n).into());
+                },
 
                 // Expected block size
                 _ => ()
@@ -172,13 +288,13 @@ impl UpEncCtxtIndep for KhPrf {
             // Decode the EcPoint and update.
             // let point = EcPoint::from_bytes(&chunk).unwrap();
 
-            let bytes = update_block(rk_token.0, &chunk, ctr);
+            let bytes = update_block(&rk_token, &chunk, &nonce, block_ctr(rk_token.1, i)?)
+                .chain_err(|| ErrorKind::InvalidBlock(i))?;
 
             // Write the newpoint to the output file.
             // let bytes = newpoint.serialize();
             writer.write_all(&bytes[..])?;
-
-            ctr += 1;
+            i += 1;
         }
         Ok(())
     }
@@ -187,17 +303,87 @@ impl UpEncCtxtIndep for KhPrf {
     // Generate an update rk_token that coverts ciphertexts from k1 to k2
     fn rekeygen<Out: Write>(k1: Self::K, k2: Self::K, rk_out: &mut Out) -> Result<()> {
         // let rk = rekey_token(k1, k2);
+        if k1.2 != k2.2 {
+            return Err(ErrorKind::InvalidKey("keys use different hash suites").into());
+        }
         let rk = &k2 - &k1;
         rk.write_key(rk_out)
     }
 }
 
+impl KhPrf {
+    /// Generates a random key which uses the given hash suite.
+    pub fn keygen_with_suite(suite: HashSuite) -> KhKey {
+        let mut rng = OsRng::new().unwrap();
+        let mut bytes = Zeroizing::new([0u8; 64]);
+        rng.fill_bytes(&mut *bytes);
+        KhKey(Scalar::from_bytes_mod_order_wide(&bytes), 1, suite)
+    }
+}
+
+impl KeyHomomorphicPrf for KhPrf {
+    type Output = EdwardsPoint;
+
+    const PT_BLOCK_SIZE: usize = 31;
+    const CT_BLOCK_SIZE: usize = 32;
+
+    fn add_keys(k1: &KhKey, k2: &KhKey) -> KhKey {
+        k1 + k2
+    }
+
+    fn sub_keys(k1: &KhKey, k2: &KhKey) -> KhKey {
+        k1 - k2
+    }
+
+    fn counter(key: &KhKey) -> u64 {
+        key.1
+    }
+
+    fn keygen_like(key: &KhKey) -> KhKey {
+        KhPrf::keygen_with_suite(key.2)
+    }
+
+    fn nonce_size(key: &KhKey) -> usize {
+        match key.2 {
+            HashSuite::Legacy => 0,
+            HashSuite::Rfc9380 => NONCE_SIZE,
+        }
+    }
+
+    fn prf(key: &KhKey, nonce: &[u8], ctr: u64) -> EdwardsPoint {
+        prf(key, nonce, ctr)
+    }
+
+    fn encrypt_block(key: &KhKey, block: &[u8], nonce: &[u8], ctr: u64) -> Result<Vec<u8>> {
+        Ok(encrypt_block(key, block, nonce, ctr))
+    }
+
+    fn decrypt_block(key: &KhKey, block: &[u8], nonce: &[u8], ctr: u64) -> Result<Vec<u8>> {
+        decrypt_block(key, block, nonce, ctr)
+    }
+
+    fn update_block(rk: &KhKey, block: &[u8], nonce: &[u8], ctr: u64) -> Result<Vec<u8>> {
+        update_block(rk, block, nonce, ctr)
+    }
+
+    fn encrypt_tag(key: &KhKey, hm: &[u8]) -> Vec<u8> {
+        encrypt_tag(key, hm)
+    }
+
+    fn update_tag(rk: &KhKey, tau: &[u8]) -> Result<Vec<u8>> {
+        update_block(rk, tau, &[], 0)
+    }
+
+    fn verify_tag(key: &KhKey, hm: &[u8], tau: &[u8]) -> Result<bool> {
+        verify_tag(key, hm, tau)
+    }
+}
 
 // Encrypt a single block of raw plaintext
-pub fn encrypt_block(key: Scalar, msg: &[u8], ctr: u64) -> Vec<u8> {
+pub fn encrypt_block(key: &KhKey, msg: &[u8], nonce: &[u8], ctr: u64) -> Vec<u8> {
     let m = encode_point(&msg);
 
-    let c = encrypt_point(key, m, ctr);
+    let c = encrypt_point(key, m, nonce, ctr);
 
     // c.serialize()
     serialize_point(c)
@@ -218,82 +404,274 @@ pub fn u64_to_u8(v: u64) -> [u8; 8] {
     ]
 }
 
-fn prf(key: Scalar, ctr: u64) -> ExtendedPoint {
-    (&key * &hash_ctr_to_group(ctr)).mult_by_cofactor()
+// The counter for block `i` of a ciphertext whose first block uses `ctr`.
+// Counters must never wrap around, since counter 0 is reserved for the
+// `ReCrypt` tag, but only the counters of blocks which exist need to fit.
+#[inline]
+pub fn block_ctr(ctr: u64, i: u64) -> Result<u64> {
+    ctr.checked_add(i).ok_or(ErrorKind::CounterOverflow.into())
+}
+
+// Generates a fresh nonce of `len` bytes for a new ciphertext.
+pub fn new_nonce(len: usize) -> Vec<u8> {
+    let mut rng = OsRng::new().unwrap();
+    let mut nonce = vec![0u8; len];
+    rng.fill_bytes(&mut nonce);
+    nonce
+}
+
+// Reads the `len`-byte nonce from the start of a ciphertext.
+pub fn read_nonce<In: Read>(ct: &mut In, len: usize) -> Result<Vec<u8>> {
+    let mut nonce = vec![0u8; len];
+    read_field(ct, &mut nonce, 0)?;
+    Ok(nonce)
+}
+
+// u8 array to big endian u64
+#[inline]
+pub fn u8_to_u64(v: [u8; 8]) -> u64 {
+    v.iter().fold(0, |acc, &b| (acc << 8) | b as u64)
+}
+
+pub fn prf(key: &KhKey, nonce: &[u8], ctr: u64) -> EdwardsPoint {
+    (&key.0 * &hash_ctr_to_group(key.2, nonce, ctr)).mul_by_cofactor()
 }
 
 // Encrypt a single EcPoint.
-pub fn encrypt_point(key: Scalar, msg: ExtendedPoint, ctr: u64) -> ExtendedPoint {
+pub fn encrypt_point(key: &KhKey, msg: EdwardsPoint, nonce: &[u8], ctr: u64) -> EdwardsPoint {
     // C::prf(key, &u64_to_u8(ctr)) + msg
-    &prf(key, ctr) + &msg
+    &prf(key, nonce, ctr) + &msg
 }
 
-pub fn update_block(rk: Scalar, ct_block: &[u8], ctr: u64) -> Vec<u8> {
-    let newpoint = update_point(rk, deserialize_point(ct_block), ctr);
-    serialize_point(newpoint)
+pub fn update_block(rk: &KhKey, ct_block: &[u8], nonce: &[u8], ctr: u64) -> Result<Vec<u8>> {
+    let newpoint = update_point(rk, deserialize_point(ct_block)?, nonce, ctr);
+    Ok(serialize_point(newpoint))
 }
 
 // Updates a single ciphertext block/point
-pub fn update_point(rk: Scalar, block: ExtendedPoint, ctr: u64) -> ExtendedPoint {
-    &prf(rk, ctr) + &block
+pub fn update_point(rk: &KhKey, block: EdwardsPoint, nonce: &[u8], ctr: u64) -> EdwardsPoint {
+    &prf(rk, nonce, ctr) + &block
 }
 
 // Decrypts a single block of ciphertext
-pub fn decrypt_block(key: Scalar, ct_block: &[u8], ctr: u64) -> Result<Vec<u8>> {
-    decode_point(decrypt_point(key, deserialize_point(ct_block), ctr))
+pub fn decrypt_block(key: &KhKey, ct_block: &[u8], nonce: &[u8], ctr: u64) -> Result<Vec<u8>> {
+    decode_point(decrypt_point(key, deserialize_point(ct_block)?, nonce, ctr))
 }
 
 // Decrypts a single EcPoint
-pub fn decrypt_point(key: Scalar, ct: ExtendedPoint, ctr: u64) -> ExtendedPoint {
-    &ct - &prf(key, ctr)
+pub fn decrypt_point(key: &KhKey, ct: EdwardsPoint, nonce: &[u8], ctr: u64) -> EdwardsPoint {
+    &ct - &prf(key, nonce, ctr)
+}
+
+// Encrypts the tag `H(m) + F(x, 0)` used by `ReCrypt`. The tag has no nonce,
+// since `ReCrypt` uses a fresh `x` for every ciphertext.
+pub fn encrypt_tag(key: &KhKey, hm: &[u8]) -> Vec<u8> {
+    serialize_point(encrypt_point(key, hash_tag_to_group(key.2, hm), &[], 0))
+}
+
+// Checks a tag produced by `encrypt_tag` against the hash of the plaintext.
+pub fn verify_tag(key: &KhKey, hm: &[u8], tau: &[u8]) -> Result<bool> {
+    let y = decrypt_point(key, deserialize_point(tau)?, &[], 0);
+    Ok(y.compress() == hash_tag_to_group(key.2, hm).compress())
 }
 
-pub fn serialize_point(point: ExtendedPoint) -> Vec<u8> {
-    point.compress_edwards().as_bytes().to_vec()
+pub fn serialize_point(point: EdwardsPoint) -> Vec<u8> {
+    point.compress().as_bytes().to_vec()
 }
 
-pub fn deserialize_point(bytes: &[u8]) -> ExtendedPoint {
-    // let point = deserialize(&ct_block).unwrap();
-    debug_assert_eq!(bytes.len(), 32);
+pub fn deserialize_point(bytes: &[u8]) -> Result<EdwardsPoint> {
+    if bytes.len() != 32 {
+        return Err(ErrorKind::InvalidBlockSize(bytes.len()).into());
+    }
     let mut point_bytes = [0u8; 32];
     point_bytes.copy_from_slice(&bytes);
-    let point = curve25519_dalek::curve::CompressedEdwardsY(point_bytes);
-    point.decompress().unwrap()
+    let point = CompressedEdwardsY(point_bytes);
+    point.decompress().ok_or(ErrorKind::InvalidPoint.into())
 }
 
-pub fn encode_point(bytes: &[u8]) -> ExtendedPoint {
+pub fn encode_point(bytes: &[u8]) -> EdwardsPoint {
     debug_assert_eq!(bytes.len(), 31);
     let mut point_bytes = [0u8; 32];
     point_bytes[..31].copy_from_slice(bytes);
-    let enc  = ExtendedPoint::from_uniform_representative(&point_bytes);
+    let enc  = elligator::from_uniform_representative(&point_bytes);
     enc
 }
-pub fn decode_point(point: ExtendedPoint) -> Result<Vec<u8>> {
+pub fn decode_point(point: EdwardsPoint) -> Result<Vec<u8>> {
     // println!("Point to decode:{:?}", point); 
-    let decoded = point.to_uniform_representative().unwrap();
+    let decoded = elligator::to_uniform_representative(&point).ok_or(ErrorKind::InvalidPoint)?;
     if decoded[31] != 0 {
-        return Err("invalid point decoding".into());
+        return Err(ErrorKind::InvalidPoint.into());
     }
     Ok(decoded[..31].to_vec())
 }
 
-pub fn hash_ctr_to_group(ctr: u64) -> ExtendedPoint {
-    let bytes = u64_to_u8(ctr);
-    hash_to_group(&bytes, "counter")
+// Hashes `nonce || ctr`. The nonce is a fixed size in ciphertexts, and empty
+// for the tag and for `Legacy` ciphertexts, whose counters start at 1, so
+// these never collide.
+pub fn hash_ctr_to_group(suite: HashSuite, nonce: &[u8], ctr: u64) -> EdwardsPoint {
+    let mut bytes = nonce.to_vec();
+    bytes.extend_from_slice(&u64_to_u8(ctr));
+    match suite {
+        HashSuite::Legacy => hash_to_group(&bytes, "counter"),
+        HashSuite::Rfc9380 => hash_to_curve::hash_to_curve(&bytes, COUNTER_DST),
+    }
 }
 
-pub fn hash_tag_to_group(bytes: &[u8]) -> ExtendedPoint {
-    hash_to_group(&bytes, "tag")
+pub fn hash_tag_to_group(suite: HashSuite, bytes: &[u8]) -> EdwardsPoint {
+    match suite {
+        HashSuite::Legacy => hash_to_group(&bytes, "tag"),
+        HashSuite::Rfc9380 => hash_to_curve::hash_to_curve(&bytes, TAG_DST),
+    }
 }
 
-pub fn hash_to_group(bytes: &[u8], label: &str) -> ExtendedPoint {
+pub fn hash_to_group(bytes: &[u8], label: &str) -> EdwardsPoint {
     let h1 = h!(&label.as_bytes(), b"h1", &bytes);
     let p1 = encode_point(&h1.as_ref()[1..]);
     let h2 = h!(&label.as_bytes(), b"h2", &bytes);
     let mut scalar_bytes = [0u8; 32];
     scalar_bytes.copy_from_slice(&h2.as_ref());
     scalar_bytes[31] &= 0x7f;
-    let p2 = Scalar(scalar_bytes);
-    &p1 + &(&p2 * &curve25519_dalek::constants::ED25519_BASEPOINT_TABLE)
+    let p2 = Scalar::from_bytes_mod_order(scalar_bytes);
+    &p1 + &EdwardsPoint::mul_base(&p2)
     // // This already maps the full 32-bytes to group points.
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn key_rt_full_counter() {
+        for &ctr in [1, 255, 256, 1 << 40, u64::max_value()].iter() {
+            for &suite in [HashSuite::Legacy, HashSuite::Rfc9380].iter() {
+                let key = KhKey(KhPrf::keygen().0, ctr, suite);
+                let mut buf = Vec::new();
+                key.write_key(&mut buf).unwrap();
+                assert_eq!(buf.len(), 41);
+                assert_eq!(KhKey::read_key(&mut &buf[..]).unwrap(), key);
+            }
+        }
+    }
+
+    #[test]
+    fn read_legacy_key() {
+        let key = KhPrf::keygen();
+        let mut buf = key.0.to_bytes().to_vec();
+        buf.push(7);
+        assert_eq!(KhKey::read_key(&mut &buf[..]).unwrap(), KhKey(key.0, 7, HashSuite::Legacy));
+    }
+
+    #[test]
+    fn reject_tag_counter() {
+        let key = KhPrf::keygen();
+        let mut legacy = key.0.to_bytes().to_vec();
+        legacy.push(0);
+        assert!(KhKey::read_key(&mut &legacy[..]).is_err());
+
+        let mut buf = Vec::new();
+        KhKey(key.0, 0, key.2).write_key(&mut buf).unwrap();
+        assert!(KhKey::read_key(&mut &buf[..]).is_err());
+    }
+
+    #[test]
+    fn reject_unknown_version() {
+        let mut buf = Vec::new();
+        KhPrf::keygen().write_key(&mut buf).unwrap();
+        buf[32] = 0xff;
+        assert!(KhKey::read_key(&mut &buf[..]).is_err());
+    }
+
+    #[test]
+    fn legacy_suite_rt() {
+        let k1 = KhPrf::keygen_with_suite(HashSuite::Legacy);
+        let k2 = KhPrf::keygen_with_suite(HashSuite::Legacy);
+        let pt = [0x5a; 100];
+        let mut ct = Vec::new();
+        KhPrf::encrypt(k1.clone(), &mut &pt[..], &mut ct).unwrap();
+
+        let mut rk = Vec::new();
+        KhPrf::rekeygen(k1, k2.clone(), &mut rk).unwrap();
+        let mut ct2 = Vec::new();
+        KhPrf::reencrypt(&mut &rk[..], &mut &ct[..], &mut ct2).unwrap();
+        let mut rec = Vec::new();
+        KhPrf::decrypt(k2, &mut &ct2[..], &mut rec).unwrap();
+        assert_eq!(&pt[..], &rec[..]);
+    }
+
+    // Builds a ciphertext as the original `KhPrf` wrote it, with no nonce
+    // and the counter hashed alone, under a key in the original encoding.
+    fn baseline_ct(key: &[u8], pt: &[u8]) -> Vec<u8> {
+        let mut scalar = [0u8; 32];
+        scalar.copy_from_slice(&key[..32]);
+        let mut block = pt.to_vec();
+        pad(&mut block, 31);
+        block.chunks(31).enumerate().flat_map(|(i, m)| {
+            let ctr = key[32] as u64 + i as u64;
+            let f = (&Scalar::from_bytes_mod_order(scalar) * &hash_to_group(&u64_to_u8(ctr), "counter")).mul_by_cofactor();
+            serialize_point(&f + &encode_point(m))
+        }).collect()
+    }
+
+    #[test]
+    fn baseline_ct_decrypts() {
+        let mut key_bytes = [0u8; 33];
+        key_bytes[0] = 7;
+        key_bytes[32] = 1;
+        let key = KhKey::read_key(&mut &key_bytes[..]).unwrap();
+        assert_eq!(KhPrf::nonce_size(&key), 0);
+
+        let pt = b"written before ciphertexts had a nonce";
+        let ct = baseline_ct(&key_bytes, pt);
+        assert_eq!(ct.len(), 64);
+        let mut rec = Vec::new();
+        KhPrf::decrypt(key.clone(), &mut &ct[..], &mut rec).unwrap();
+        assert_eq!(&pt[..], &rec[..]);
+
+        // New ciphertexts under the key are in the same format.
+        let mut ct2 = Vec::new();
+        KhPrf::encrypt(key, &mut &pt[..], &mut ct2).unwrap();
+        assert_eq!(ct, ct2);
+    }
+
+    // As the original `ReCrypt<A, KhPrf>` wrote it: the header encrypts
+    // `chi || tau`, where `tau = H(m) + F(x, 0)`, and the body is `y || C`.
+    #[test]
+    fn baseline_recrypt_decrypts() {
+        use generic::ReCrypt;
+
+        // Keys in the original encoding, with chi = x + y.
+        let (mut x, mut y, mut chi) = ([0u8; 33], [0u8; 33], vec![0u8; 33]);
+        x[0] = 7;
+        y[0] = 9;
+        chi[0] = 16;
+        x[32] = 1;
+        y[32] = 1;
+        chi[32] = 1;
+
+        let pt = [0xa5; 100];
+        let key_x = KhKey::read_key(&mut &x[..]).unwrap();
+        let f0 = (&key_x.0 * &hash_to_group(&u64_to_u8(0), "counter")).mul_by_cofactor();
+        let tau = &f0 + &hash_to_group(h!(&pt).as_ref(), "tag");
+        chi.extend_from_slice(&serialize_point(tau));
+
+        let key = RingAes::keygen();
+        let mut ct_hdr = Vec::new();
+        RingAes::encrypt(key.clone(), &mut &chi[..], &mut ct_hdr).unwrap();
+        let mut ct_body = y.to_vec();
+        ct_body.extend(baseline_ct(&x, &pt));
+
+        let mut rec = Vec::new();
+        ReCrypt::<RingAes, KhPrf>::decrypt(key, &mut &ct_hdr[..], &mut &ct_body[..], &mut rec).unwrap();
+        assert_eq!(&pt[..], &rec[..]);
+    }
+
+    #[test]
+    fn suites_differ() {
+        let key = KhPrf::keygen();
+        assert!(hash_ctr_to_group(HashSuite::Legacy, &[], 1).compress()
+            != hash_ctr_to_group(HashSuite::Rfc9380, &[], 1).compress());
+
+        let legacy = KhPrf::keygen_with_suite(HashSuite::Legacy);
+        assert!(KhPrf::rekeygen(legacy, key, &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,425 @@
+use rand::Rng;
+use rand::os::OsRng;
+
+use std::cmp::max;
+use std::io::{Read,Write,BufReader,BufWriter};
+use std::fmt;
+use std::ops::{Add, Sub};
+
+use zeroize::{Zeroize, Zeroizing};
+
+use super::*;
+use common::pad;
+use io::*;
+use kh_prf::{block_ctr, new_nonce, read_nonce, u64_to_u8, u8_to_u64, NONCE_SIZE};
+
+/// Dimension of the ring `Z_q[X]/(X^N + 1)`.
+pub const LWR_N: usize = 1024;
+/// Plaintext bytes carried by each block, one per output coefficient.
+pub const PT_BLOCK_SIZE: usize = 32;
+/// Each block is `PT_BLOCK_SIZE` coefficients mod `p = 2^16`.
+pub const CT_BLOCK_SIZE: usize = 2 * PT_BLOCK_SIZE;
+/// Number of updates a ciphertext can take before decryption fails.
+pub const MAX_UPDATES: usize = 128;
+
+/// Encryption using an *almost* key-homomorphic PRF from Ring Learning With
+/// Rounding, following Boneh, Lewi, Montgomery and Raghunathan (CRYPTO 2013).
+///
+/// The PRF is `F(k, x) = round_p(a_x * k)`, for a public ring element `a_x`
+/// expanded from the nonce and counter `x`, with `q = 2^32` and `p = 2^16`.
+/// Only the first `PT_BLOCK_SIZE` coefficients are used for each block.
+///
+/// This is experimental. The parameters are illustrative and have not been
+/// chosen to meet any particular security level, classical or quantum, so it
+/// should not be relied on as a post-quantum alternative to `KhPrf`.
+///
+/// Rounding means that `F(k1 + k2, x) = F(k1, x) + F(k2, x) + e` for some
+/// `e` in `{0, 1}`, so each update can subtract 1 from every coefficient.
+/// Each plaintext byte is therefore stored in the top 8 bits of a
+/// coefficient, with the bottom 8 bits as headroom: a ciphertext can be
+/// updated `MAX_UPDATES` times before it no longer decrypts. The accumulated
+/// noise also reveals roughly how many times a ciphertext has been updated.
+/// Keys count the updates, so rotating a ciphertext which has already taken
+/// `MAX_UPDATES` fails with `TooManyUpdates` rather than corrupting it.
+pub struct LwrKhPrf;
+
+/// Key for `LwrKhPrf`: a uniformly random ring element, the starting
+/// counter, and the number of updates taken by ciphertexts under the key.
+///
+/// Serialised as `LWR_N` big-endian `u32` coefficients followed by a
+/// big-endian `u64` counter and a big-endian `u32` update count. As for
+/// `KhKey`, counter 0 is reserved for the `ReCrypt` tag.
+#[derive(Clone, PartialEq)]
+pub struct LwrKhKey(pub Vec<u32>, pub u64, pub u32);
+
+impl Drop for LwrKhKey {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for LwrKhKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LwrKhKey(<redacted>, {}, {})", self.1, self.2)
+    }
+}
+
+impl Key for LwrKhKey {
+    fn read_key<In: Read>(key_in: &mut In) -> Result<Self> {
+        let mut bytes = Zeroizing::new(vec![0u8; 4 * LWR_N]);
+        let mut ctr = [0u8; 8];
+        let mut updates = [0u8; 8];
+        key_in.read_exact(&mut bytes)?;
+        key_in.read_exact(&mut ctr)?;
+        key_in.read_exact(&mut updates[4..])?;
+        let coeffs = bytes.chunks(4)
+            .map(|c| (c[0] as u32) << 24 | (c[1] as u32) << 16 | (c[2] as u32) << 8 | c[3] as u32)
+            .collect();
+        let ctr = u8_to_u64(ctr);
+        if ctr == 0 {
+            return Err(ErrorKind::InvalidKey("counter overlaps the reserved tag counter").into());
+        }
+        let updates = u8_to_u64(updates);
+        if updates > MAX_UPDATES as u64 {
+            return Err(ErrorKind::InvalidKey("update count beyond MAX_UPDATES").into());
+        }
+        Ok(LwrKhKey(coeffs, ctr, updates as u32))
+    }
+    fn write_key<Out: Write>(&self, key_out: &mut Out) -> Result<()> {
+        let mut bytes = Zeroizing::new(Vec::with_capacity(4 * LWR_N));
+        for c in &self.0 {
+            bytes.extend_from_slice(&[(c >> 24) as u8, (c >> 16) as u8, (c >> 8) as u8, *c as u8]);
+        }
+        key_out.write_all(&bytes)?;
+        key_out.write_all(&u64_to_u8(self.1))?;
+        key_out.write_all(&u64_to_u8(self.2 as u64)[4..])?;
+        Ok(())
+    }
+}
+
+impl<'a> Add for &'a LwrKhKey {
+    type Output = LwrKhKey;
+
+    fn add(self, other: Self) -> Self::Output {
+        let coeffs = self.0.iter().zip(&other.0).map(|(a, b)| a.wrapping_add(*b)).collect();
+        LwrKhKey(coeffs, self.1, max(self.2, other.2))
+    }
+}
+
+// Advances the starting counter, failing rather than wrapping around.
+impl Add<u64> for LwrKhKey {
+    type Output = Result<LwrKhKey>;
+
+    fn add(mut self, other: u64) -> Result<LwrKhKey> {
+        self.1 = self.1.checked_add(other).ok_or(ErrorKind::CounterOverflow)?;
+        Ok(self)
+    }
+}
+
+impl<'a> Sub for &'a LwrKhKey {
+    type Output = LwrKhKey;
+
+    fn sub(self, other: Self) -> Self::Output {
+        let coeffs = self.0.iter().zip(&other.0).map(|(a, b)| a.wrapping_sub(*b)).collect();
+        LwrKhKey(coeffs, self.1, self.2)
+    }
+}
+
+impl Cipher for LwrKhPrf {
+    // The key is a random ring element, and the starting counter
+    type K = LwrKhKey;
+
+    // Generate a random encryption key
+    fn keygen() -> Self::K {
+        let mut rng = OsRng::new().unwrap();
+        LwrKhKey((0..LWR_N).map(|_| rng.next_u32()).collect(), 1, 0)
+    }
+
+    fn encrypt<In: Read, Out: Write>(key: Self::K, pt: &mut In, ct: &mut Out) -> Result<()> {
+        let mut i = 0;
+
+        let mut reader = BufReader::new(pt);
+        let mut writer = BufWriter::new(ct);
+
+        let nonce = new_nonce(NONCE_SIZE);
+        writer.write_all(&nonce)?;
+
+        let mut eof = false;
+        while !eof {
+            let mut block = read_chunk(&mut reader, PT_BLOCK_SIZE)?;
+
+            // Pad if it's not a full block.
+            if block.len() < PT_BLOCK_SIZE {
+                pad(&mut block, PT_BLOCK_SIZE);
+                eof = true;
+            }
+
+            let ct_block = encrypt_block(&key.0, &block, &nonce, block_ctr(key.1, i)?);
+            writer.write_all(&ct_block)?;
+            i += 1;
+        }
+        Ok(())
+    }
+
+    fn decrypt<In: Read, Out: Write>(key: Self::K, ct: &mut In, pt: &mut Out) -> Result<()> {
+        let mut reader = BufReader::new(ct);
+        let mut writer = BufWriter::new(pt);
+        let mut i = 0;
+        let nonce = read_nonce(&mut reader, NONCE_SIZE)?;
+
+        let mut prev_pt_block = Vec::new();
+        loop {
+            let chunk = read_chunk(&mut reader, CT_BLOCK_SIZE)?;
+
+            // Ciphertext files are expected to be exact multiples of CT_BLOCK_SIZE.
+            let eof = match chunk.len() {
+                0 => true,
+                n if n != CT_BLOCK_SIZE => {
+                    return Err(ErrorKind::TruncatedBlock(i, n).into());
+                },
+                _ => false,
+            };
+
+            // Only the final block carries padding.
+            write_pt(prev_pt_block, &mut writer, eof)?;
+
+            if eof {
+                break;
+            }
+
+            prev_pt_block = decrypt_block(&key.0, &chunk, &nonce, block_ctr(key.1, i)?)
+                .chain_err(|| ErrorKind::InvalidBlock(i))?;
+            i += 1;
+        }
+        Ok(())
+    }
+}
+
+impl UpEncCtxtIndep for LwrKhPrf {
+    fn reencrypt<In1: Read, In2: Read, Out: Write>(rk_file: &mut In1, ct1: &mut In2, ct2: &mut Out)
+            -> Result<()> {
+        let mut reader = BufReader::new(ct1);
+        let mut writer = BufWriter::new(ct2);
+
+        let rk_token = Self::K::read_key(rk_file).chain_err(|| ErrorKind::InvalidToken)?;
+        let mut i = 0;
+
+        // The nonce is carried over as-is.
+        let nonce = read_nonce(&mut reader, NONCE_SIZE)?;
+        writer.write_all(&nonce)?;
+
+        loop {
+            let chunk = read_chunk(&mut reader, CT_BLOCK_SIZE)?;
+            match chunk.len() {
+                // EOF
+                0 => break,
+                n if n != CT_BLOCK_SIZE => {
+                    return Err(ErrorKind::TruncatedBlock(i, n).into());
+                },
+                _ => ()
+            }
+
+            let bytes = update_block(&rk_token.0, &chunk, &nonce, block_ctr(rk_token.1, i)?)
+                .chain_err(|| ErrorKind::InvalidBlock(i))?;
+            writer.write_all(&bytes[..])?;
+            i += 1;
+        }
+        Ok(())
+    }
+
+    // Generate an update rk_token that coverts ciphertexts from k1 to k2,
+    // which must come from `keygen_next(k1)` so that the update is counted.
+    fn rekeygen<Out: Write>(k1: Self::K, k2: Self::K, rk_out: &mut Out) -> Result<()> {
+        if k2.2 as usize > MAX_UPDATES {
+            return Err(ErrorKind::TooManyUpdates(MAX_UPDATES).into());
+        }
+        if k2.2 != k1.2 + 1 {
+            return Err(ErrorKind::InvalidKey("new key does not count the update; use keygen_next").into());
+        }
+        let rk = &k2 - &k1;
+        rk.write_key(rk_out)
+    }
+}
+
+impl KeyHomomorphicPrf for LwrKhPrf {
+    type Output = Vec<u16>;
+
+    const PT_BLOCK_SIZE: usize = PT_BLOCK_SIZE;
+    const CT_BLOCK_SIZE: usize = CT_BLOCK_SIZE;
+
+    fn add_keys(k1: &LwrKhKey, k2: &LwrKhKey) -> LwrKhKey {
+        k1 + k2
+    }
+
+    fn sub_keys(k1: &LwrKhKey, k2: &LwrKhKey) -> LwrKhKey {
+        k1 - k2
+    }
+
+    fn counter(key: &LwrKhKey) -> u64 {
+        key.1
+    }
+
+    fn keygen_next(key: &LwrKhKey) -> Result<LwrKhKey> {
+        if key.2 as usize >= MAX_UPDATES {
+            return Err(ErrorKind::TooManyUpdates(MAX_UPDATES).into());
+        }
+        let mut next = LwrKhPrf::keygen();
+        next.2 = key.2 + 1;
+        Ok(next)
+    }
+
+    fn prf(key: &LwrKhKey, nonce: &[u8], ctr: u64) -> Vec<u16> {
+        prf(&key.0, nonce, ctr)
+    }
+
+    fn encrypt_block(key: &LwrKhKey, block: &[u8], nonce: &[u8], ctr: u64) -> Result<Vec<u8>> {
+        Ok(encrypt_block(&key.0, block, nonce, ctr))
+    }
+
+    fn decrypt_block(key: &LwrKhKey, block: &[u8], nonce: &[u8], ctr: u64) -> Result<Vec<u8>> {
+        decrypt_block(&key.0, block, nonce, ctr)
+    }
+
+    fn update_block(rk: &LwrKhKey, block: &[u8], nonce: &[u8], ctr: u64) -> Result<Vec<u8>> {
+        update_block(&rk.0, block, nonce, ctr)
+    }
+
+    fn encrypt_tag(key: &LwrKhKey, hm: &[u8]) -> Vec<u8> {
+        encrypt_tag(&key.0, hm)
+    }
+
+    fn update_tag(rk: &LwrKhKey, tau: &[u8]) -> Result<Vec<u8>> {
+        update_block(&rk.0, tau, &[], 0)
+    }
+
+    fn verify_tag(key: &LwrKhKey, hm: &[u8], tau: &[u8]) -> Result<bool> {
+        verify_tag(&key.0, hm, tau)
+    }
+}
+
+// Encrypt a single block of raw plaintext. Each byte is placed in the top
+// half of a coefficient, offset by half the headroom so that decryption can
+// just truncate.
+pub fn encrypt_block(key: &[u32], msg: &[u8], nonce: &[u8], ctr: u64) -> Vec<u8> {
+    debug_assert_eq!(msg.len(), PT_BLOCK_SIZE);
+    let coeffs = msg.iter().zip(prf(key, nonce, ctr))
+        .map(|(m, f)| ((*m as u16) << 8 | 0x80).wrapping_add(f))
+        .collect::<Vec<_>>();
+    serialize_coeffs(&coeffs)
+}
+
+// Evaluates the first `PT_BLOCK_SIZE` coefficients of `round_p(a_ctr * key)`.
+pub fn prf(key: &[u32], nonce: &[u8], ctr: u64) -> Vec<u16> {
+    let a = hash_ctr_to_ring(nonce, ctr);
+    // Coefficient i of a * key mod X^N + 1, where X^N wraps around to -1.
+    (0..PT_BLOCK_SIZE).map(|i| {
+        let mut sum = 0u32;
+        for (j, k) in key.iter().enumerate() {
+            if j <= i {
+                sum = sum.wrapping_add(a[i - j].wrapping_mul(*k));
+            } else {
+                sum = sum.wrapping_sub(a[LWR_N + i - j].wrapping_mul(*k));
+            }
+        }
+        (sum >> 16) as u16
+    }).collect()
+}
+
+// Updates a single ciphertext block, adding at most one unit of noise.
+pub fn update_block(rk: &[u32], ct_block: &[u8], nonce: &[u8], ctr: u64) -> Result<Vec<u8>> {
+    let coeffs = deserialize_coeffs(ct_block)?.iter().zip(prf(rk, nonce, ctr))
+        .map(|(c, f)| c.wrapping_add(f))
+        .collect::<Vec<_>>();
+    Ok(serialize_coeffs(&coeffs))
+}
+
+// Decrypts a single block of ciphertext
+pub fn decrypt_block(key: &[u32], ct_block: &[u8], nonce: &[u8], ctr: u64) -> Result<Vec<u8>> {
+    Ok(deserialize_coeffs(ct_block)?.iter().zip(prf(key, nonce, ctr))
+        .map(|(c, f)| (c.wrapping_sub(f) >> 8) as u8)
+        .collect())
+}
+
+// Encrypts the tag `h(m) + F(x, 0)` used by `ReCrypt`, with an empty nonce.
+// The hash is exactly one block, so it is encrypted in the same way as the body.
+pub fn encrypt_tag(key: &[u32], hm: &[u8]) -> Vec<u8> {
+    encrypt_block(key, hm, &[], 0)
+}
+
+// Checks a tag produced by `encrypt_tag` against the hash of the plaintext.
+// This tolerates the same noise as decryption.
+pub fn verify_tag(key: &[u32], hm: &[u8], tau: &[u8]) -> Result<bool> {
+    Ok(decrypt_block(key, tau, &[], 0)? == hm)
+}
+
+pub fn serialize_coeffs(coeffs: &[u16]) -> Vec<u8> {
+    coeffs.iter().flat_map(|c| vec![(c >> 8) as u8, *c as u8]).collect()
+}
+
+pub fn deserialize_coeffs(bytes: &[u8]) -> Result<Vec<u16>> {
+    if bytes.len() != CT_BLOCK_SIZE {
+        return Err(ErrorKind::InvalidBlockSize(bytes.len()).into());
+    }
+    Ok(bytes.chunks(2).map(|c| (c[0] as u16) << 8 | c[1] as u16).collect())
+}
+
+// Expands `nonce || ctr` into the public ring element `a_ctr`.
+//
+// The seed is `SHA-256("lwr counter" || nonce || ctr)`, with `ctr` as a
+// big-endian `u64`, and is expanded with MGF1 (RFC 8017) over SHA-256: block
+// `j` is `SHA-256(seed || j)` for a big-endian `u32` `j`, read as eight
+// big-endian coefficients.
+pub fn hash_ctr_to_ring(nonce: &[u8], ctr: u64) -> Vec<u32> {
+    let seed = h!(b"lwr counter", nonce, &u64_to_u8(ctr));
+    let mut a = Vec::with_capacity(LWR_N);
+    for j in 0..(LWR_N / 8) as u32 {
+        let j_bytes = [(j >> 24) as u8, (j >> 16) as u8, (j >> 8) as u8, j as u8];
+        let block = h!(seed.as_ref(), &j_bytes);
+        a.extend(block.as_ref().chunks(4)
+            .map(|c| (c[0] as u32) << 24 | (c[1] as u32) << 16 | (c[2] as u32) << 8 | c[3] as u32));
+    }
+    a
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hash_ctr_to_ring_kat() {
+        let a = hash_ctr_to_ring(&[], 0);
+        assert_eq!(a.len(), LWR_N);
+        assert_eq!(&a[..4], &[0xe2a10d51, 0x7bc78fc1, 0x02162218, 0xa069522f]);
+        assert_eq!(a[LWR_N - 1], 0xbe07b6ca);
+
+        let nonce = (0..16).collect::<Vec<u8>>();
+        let a = hash_ctr_to_ring(&nonce, 7);
+        assert_eq!(&a[..4], &[0xee458014, 0x7c7a47dd, 0x22107e22, 0xdc259037]);
+        assert_eq!(a[LWR_N - 1], 0xf2a4cf6d);
+    }
+
+    #[test]
+    fn prf_almost_key_homomorphic() {
+        let k1 = LwrKhPrf::keygen();
+        let k2 = LwrKhPrf::keygen();
+        let sum = prf(&(&k1 + &k2).0, &[], 7);
+        let parts = prf(&k1.0, &[], 7).iter().zip(prf(&k2.0, &[], 7)).map(|(a, b)| a.wrapping_add(b)).collect::<Vec<_>>();
+        for (s, p) in sum.iter().zip(parts) {
+            assert!(s.wrapping_sub(p) <= 1);
+        }
+    }
+
+    #[test]
+    fn block_rt_max_updates() {
+        let msg = (0..PT_BLOCK_SIZE as u8).map(|b| b.wrapping_mul(37)).collect::<Vec<_>>();
+        let mut key = LwrKhPrf::keygen();
+        let nonce = new_nonce(NONCE_SIZE);
+        let mut ct = encrypt_block(&key.0, &msg, &nonce, 3);
+        for _ in 0..MAX_UPDATES {
+            let new_key = LwrKhPrf::keygen();
+            ct = update_block(&(&new_key - &key).0, &ct, &nonce, 3).unwrap();
+            key = new_key;
+        }
+        assert_eq!(decrypt_block(&key.0, &ct, &nonce, 3).unwrap(), msg);
+    }
+}
@@ -1,3 +1,5 @@
+#![recursion_limit = "256"]
+
 /// ReCrypt - Key Rotation for Authenticated Encryption
 ///
 /// This is a research prototype and should never be used for important data
@@ -7,8 +9,15 @@
 /// schemes. See https://eprint.iacr.org/2017/527 for more details.
 ///
 /// There are 2 main building blocks:
-///   - Authenticated encryption schemes (AES and ChaCha from *ring*).
-///   - Key-homomorphic PRF (using `curve25519_dalek`)
+///   - Authenticated encryption schemes (AES-128, AES-256 and ChaCha from
+///     *ring*), and
+///     online versions of them (`RingAesStream`, `RingChaChaStream`) for
+///     large bodies, and AES-GCM-SIV (`AesGcmSiv`) and XChaCha20-Poly1305
+///     (`XChaCha`) for headers
+///   - Key-homomorphic PRF (using `curve25519_dalek`), over either the
+///     Edwards curve (`KhPrf`) or Ristretto255 (`RistrettoKhPrf`)
+///   - Experimental almost key-homomorphic PRF from Ring-LWR (`LwrKhPrf`),
+///     with illustrative parameters
 ///
 /// These can be composed to construct updatable encryption.
 ///
@@ -16,19 +25,29 @@
 /// definitions and are included for reference.
 ///
 /// The updatable schemes are `Kss` and `ReCrypt`. These can be found in the
-/// [generic](generic/) module.
+/// [generic](generic/) module. `ReCrypt` can be instantiated with any
+/// `KeyHomomorphicPrf`.
 ///
 /// We also define the `UpEnc` and `UpEncCtxtIndep` traits, which match the definitions
 /// given in our text.
 
-extern crate curve25519_dalek;
+extern crate aead;
+extern crate aes_gcm_siv;
+extern crate argon2;
+extern crate chacha20poly1305;
+extern crate dalek;
 #[macro_use]
 extern crate error_chain;
 extern crate rand;
 extern crate ring;
+extern crate subtle;
+extern crate zeroize;
+#[cfg(feature = "async")]
+extern crate tokio;
 
 use std::fmt::Debug;
-use std::io::{Read, Write};
+use std::io::{Read, Write, Seek, SeekFrom, BufReader, BufWriter};
+use std::ops::Range;
 
 /// ReCrypt errors.
 pub mod errors {
@@ -36,6 +55,152 @@ pub mod errors {
         foreign_links {
             Io(::std::io::Error);
         }
+
+        errors {
+            /// A ciphertext block could not be decoded, by position in the body.
+            InvalidBlock(index: u64) {
+                description("invalid ciphertext block")
+                display("invalid ciphertext block {}", index)
+            }
+            /// The ciphertext ended part-way through a block.
+            TruncatedBlock(index: u64, len: usize) {
+                description("truncated ciphertext block")
+                display("ciphertext block {} truncated to {} bytes", index, len)
+            }
+            /// The decrypted ciphertext header could not be parsed.
+            InvalidHeader {
+                description("malformed ciphertext header")
+                display("malformed ciphertext header")
+            }
+            /// The re-keying token could not be parsed.
+            InvalidToken {
+                description("malformed re-keying token")
+                display("malformed re-keying token")
+            }
+            /// The ciphertext ended part-way through a fixed-size field, at
+            /// this byte offset into the part being read.
+            TruncatedCiphertext(offset: u64) {
+                description("truncated ciphertext")
+                display("ciphertext truncated at byte {}", offset)
+            }
+            /// A ciphertext block had the wrong number of bytes.
+            InvalidBlockSize(len: usize) {
+                description("incorrect block size")
+                display("incorrect block size of {} bytes", len)
+            }
+            /// A group element could not be decoded.
+            InvalidPoint {
+                description("invalid point encoding")
+                display("invalid point encoding")
+            }
+            /// The key at the start of a ciphertext body could not be parsed.
+            InvalidBodyKey {
+                description("malformed ciphertext body key")
+                display("malformed ciphertext body key")
+            }
+            /// The ciphertext body has a length, in blocks, that no plaintext
+            /// encrypts to.
+            InvalidBodyLength(blocks: u64) {
+                description("malformed ciphertext body length")
+                display("malformed ciphertext body length of {} blocks", blocks)
+            }
+            /// A key could not be parsed or used.
+            InvalidKey(reason: &'static str) {
+                description("invalid key")
+                display("invalid key: {}", reason)
+            }
+            /// A key was written with a version this library does not know.
+            UnsupportedKeyVersion(version: u8) {
+                description("unsupported key version")
+                display("unsupported key version {}", version)
+            }
+            /// Authenticated encryption failed.
+            EncryptionFailed {
+                description("encryption failed")
+                display("encryption failed")
+            }
+            /// An AEAD ciphertext did not authenticate: the key or associated
+            /// data is wrong, or the ciphertext has been modified.
+            DecryptionFailed {
+                description("decryption failed")
+                display("decryption failed")
+            }
+            /// The ciphertext decrypted, but does not match its integrity tag,
+            /// so it has been modified.
+            IntegrityCheckFailed {
+                description("integrity check failed")
+                display("integrity check failed")
+            }
+            /// The token was generated for a different ciphertext.
+            AssociatedDataMismatch {
+                description("token does not match ciphertext")
+                display("token does not match ciphertext")
+            }
+            /// The operation is not supported by this cipher or scheme.
+            Unsupported(operation: &'static str) {
+                description("unsupported operation")
+                display("{} is not supported", operation)
+            }
+            /// A block counter or key epoch would wrap around.
+            CounterOverflow {
+                description("counter overflow")
+                display("counter overflow")
+            }
+            /// A ciphertext under an almost key-homomorphic PRF has taken as
+            /// many updates as it can while still decrypting.
+            TooManyUpdates(max: usize) {
+                description("too many updates")
+                display("ciphertext has already taken the maximum of {} updates", max)
+            }
+            /// The key epochs do not allow this operation, or could not be
+            /// parsed.
+            InvalidEpochs(reason: &'static str) {
+                description("invalid key epochs")
+                display("invalid key epochs: {}", reason)
+            }
+            /// A worker thread of a parallel operation panicked.
+            WorkerPanicked {
+                description("worker thread panicked")
+                display("worker thread panicked")
+            }
+            /// The in-place update journal does not match the file.
+            InvalidJournal(reason: &'static str) {
+                description("invalid journal")
+                display("invalid journal: {}", reason)
+            }
+            /// An argument is out of range for the ciphertext.
+            InvalidArgument(reason: &'static str) {
+                description("invalid argument")
+                display("invalid argument: {}", reason)
+            }
+            /// The input is not a container this library can read.
+            InvalidContainer(reason: &'static str) {
+                description("invalid container")
+                display("invalid container: {}", reason)
+            }
+            /// A container was written with a version of the format this
+            /// library does not know.
+            UnsupportedContainerVersion(version: u8) {
+                description("unsupported container version")
+                display("unsupported container version {}", version)
+            }
+            /// A container was written by a different scheme or ciphers,
+            /// identified by `suite`.
+            SuiteMismatch(suite: Vec<u8>) {
+                description("container suite does not match")
+                display("container was written with suite {:02x?}", suite)
+            }
+            /// No registered scheme has this suite ID.
+            UnknownSuite(suite: Vec<u8>) {
+                description("unknown suite")
+                display("no scheme registered for suite {:02x?}", suite)
+            }
+            /// Key-derivation parameters are invalid or unsupported.
+            InvalidKdf(reason: &'static str) {
+                description("invalid key-derivation parameters")
+                display("invalid key-derivation parameters: {}", reason)
+            }
+        }
     }
 }
 
@@ -43,14 +208,36 @@ use errors::*;
 
 #[macro_use]
 pub mod common;
+#[cfg(feature = "async")]
+mod async_io;
+pub mod container;
+mod elligator;
+mod epochs;
 pub mod generic;
+mod hash_to_curve;
 mod io;
+pub mod kdf;
 mod kh_prf;
+mod kh_prf_lwr;
+mod kh_prf_ristretto;
+mod merkle_tree;
+mod parallel;
 pub mod profile;
+pub mod registry;
 mod ring_ae;
+mod rustcrypto_ae;
 
-pub use kh_prf::KhPrf;
-pub use ring_ae::{RingAes, RingChaCha};
+#[cfg(feature = "async")]
+pub use async_io::{AsyncCipher, AsyncUpEnc, AsyncUpEncCtxtIndep, Task};
+pub use container::{Container, SuiteId};
+pub use epochs::KeyEpochs;
+pub use kdf::{Kdf, KdfParams};
+pub use kh_prf::{HashSuite, KhPrf};
+pub use kh_prf_lwr::LwrKhPrf;
+pub use kh_prf_ristretto::RistrettoKhPrf;
+pub use registry::Registry;
+pub use ring_ae::{RingAes, RingAes256, RingAes256Stream, RingAesStream, RingChaCha, RingChaChaStream};
+pub use rustcrypto_ae::{AesGcmSiv, XChaCha};
 // pub use recrypt::ReCrypt;
 
 /// A generic cipher trait.
@@ -64,6 +251,25 @@ pub trait Cipher {
 
     fn encrypt<In: Read, Out: Write>(key: Self::K, pt: &mut In, ct: &mut Out) -> Result<()>;
     fn decrypt<In: Read, Out: Write>(key: Self::K, ct: &mut In, pt: &mut Out) -> Result<()>;    
+
+    /// As `encrypt`, binding the ciphertext to the associated data `aad`,
+    /// which must be given again to decrypt it.
+    ///
+    /// Only AEAD ciphers support this; by default, `aad` must be empty.
+    fn encrypt_aad<In: Read, Out: Write>(key: Self::K, aad: &[u8], pt: &mut In, ct: &mut Out) -> Result<()> {
+        if !aad.is_empty() {
+            return Err(ErrorKind::Unsupported("associated data").into());
+        }
+        Self::encrypt(key, pt, ct)
+    }
+
+    /// As `decrypt`, for a ciphertext written by `encrypt_aad`.
+    fn decrypt_aad<In: Read, Out: Write>(key: Self::K, aad: &[u8], ct: &mut In, pt: &mut Out) -> Result<()> {
+        if !aad.is_empty() {
+            return Err(ErrorKind::Unsupported("associated data").into());
+        }
+        Self::decrypt(key, ct, pt)
+    }
 }
 
 /// Trait for a ciphertext-independent updatable encryption scheme.
@@ -73,6 +279,241 @@ pub trait UpEncCtxtIndep: Cipher {
     fn reencrypt<In1: Read, In2: Read, Out: Write>(rk: &mut In1, ct_old: &mut In2, ct_new: &mut Out) -> Result<()>;
 }
 
+/// Trait for a key-homomorphic PRF used in "counter-mode", as the body cipher
+/// of `ReCrypt`.
+///
+/// Block `i` of a ciphertext under key `k` is encrypted with
+/// `F(k, nonce || ctr + i)`, where `ctr` is the starting counter of the key and
+/// `nonce` is a random value written at the start of the ciphertext. Keys satisfy
+/// `F(k1 + k2, x) = F(k1, x) + F(k2, x)`, up to some small error for an
+/// *almost* key-homomorphic PRF, so a ciphertext is updated by adding
+/// `F(k2 - k1, x)` to each block. Counter 0 is reserved for `ReCrypt`'s
+/// integrity tag, which is evaluated with an empty nonce.
+pub trait KeyHomomorphicPrf: UpEncCtxtIndep {
+    /// Output of a single PRF evaluation.
+    type Output;
+
+    /// Plaintext bytes in each block.
+    const PT_BLOCK_SIZE: usize;
+    /// Ciphertext bytes in each block.
+    const CT_BLOCK_SIZE: usize;
+
+    /// Key addition. The counter is taken from `k1`, and any update count
+    /// is the larger of the two.
+    fn add_keys(k1: &Self::K, k2: &Self::K) -> Self::K;
+    /// Key subtraction, `k1 - k2`. The counter, and any update count, is
+    /// taken from `k1`.
+    fn sub_keys(k1: &Self::K, k2: &Self::K) -> Self::K;
+    /// Starting counter of the key.
+    fn counter(key: &Self::K) -> u64;
+    /// Generates a fresh key which can be combined with `key`, for example
+    /// one using the same hash suite.
+    fn keygen_like(_key: &Self::K) -> Self::K {
+        Self::keygen()
+    }
+    /// Generates a fresh key to rotate a ciphertext under `key` to. For an
+    /// almost key-homomorphic PRF, the new key counts one more update, and
+    /// this fails once the ciphertext has taken as many as it can.
+    fn keygen_next(key: &Self::K) -> Result<Self::K> {
+        Ok(Self::keygen_like(key))
+    }
+    /// Length of the nonce at the start of ciphertexts under `key`, which
+    /// may be 0 for formats from before nonces were added.
+    fn nonce_size(_key: &Self::K) -> usize {
+        kh_prf::NONCE_SIZE
+    }
+    /// Generates the nonce for a new ciphertext under `key`.
+    fn new_nonce(key: &Self::K) -> Vec<u8> {
+        kh_prf::new_nonce(Self::nonce_size(key))
+    }
+    /// Reads the nonce from the start of a ciphertext under `key`.
+    fn read_nonce<In: Read>(key: &Self::K, ct: &mut In) -> Result<Vec<u8>> {
+        kh_prf::read_nonce(ct, Self::nonce_size(key))
+    }
+
+    /// Evaluates `F(k, nonce || ctr)`, ignoring the starting counter of `key`.
+    fn prf(key: &Self::K, nonce: &[u8], ctr: u64) -> Self::Output;
+
+    /// Encrypts a single (padded) block of plaintext at counter `ctr`.
+    fn encrypt_block(key: &Self::K, block: &[u8], nonce: &[u8], ctr: u64) -> Result<Vec<u8>>;
+    /// Decrypts a single block of ciphertext at counter `ctr`.
+    fn decrypt_block(key: &Self::K, block: &[u8], nonce: &[u8], ctr: u64) -> Result<Vec<u8>>;
+    /// Updates a single block of ciphertext at counter `ctr` with a token.
+    fn update_block(rk: &Self::K, block: &[u8], nonce: &[u8], ctr: u64) -> Result<Vec<u8>>;
+
+    /// Encrypts the hash `hm` of a plaintext at counter 0.
+    fn encrypt_tag(key: &Self::K, hm: &[u8]) -> Vec<u8>;
+    /// Updates a tag produced by `encrypt_tag` with a token.
+    fn update_tag(rk: &Self::K, tau: &[u8]) -> Result<Vec<u8>>;
+    /// Checks that `tau` is an encryption of `hm` at counter 0.
+    fn verify_tag(key: &Self::K, hm: &[u8], tau: &[u8]) -> Result<bool>;
+
+    /// Multi-threaded `encrypt`, splitting the plaintext into counter ranges
+    /// across `workers` threads. The output is identical to `encrypt`, apart
+    /// from the random nonce.
+    fn encrypt_parallel<In: Read, Out: Write>(key: Self::K, pt: &mut In, ct: &mut Out, workers: usize) -> Result<()>
+        where Self: Sized + 'static, Self::K: Send + Sync + 'static
+    {
+        let mut writer = BufWriter::new(ct);
+        let ctr = Self::counter(&key);
+        let nonce = Self::new_nonce(&key);
+        writer.write_all(&nonce)?;
+        parallel::encrypt(pt, &mut writer, Self::PT_BLOCK_SIZE, ctr, workers,
+            move |block, ctr| Self::encrypt_block(&key, block, &nonce, ctr))
+    }
+
+    /// Multi-threaded `decrypt`. The output is identical to `decrypt`.
+    fn decrypt_parallel<In: Read, Out: Write>(key: Self::K, ct: &mut In, pt: &mut Out, workers: usize) -> Result<()>
+        where Self: Sized + 'static, Self::K: Send + Sync + 'static
+    {
+        let mut writer = BufWriter::new(pt);
+        let ctr = Self::counter(&key);
+        let nonce = Self::read_nonce(&key, ct)?;
+        parallel::decrypt(ct, &mut writer, Self::CT_BLOCK_SIZE, ctr, workers,
+            move |block, ctr| Self::decrypt_block(&key, block, &nonce, ctr))
+    }
+
+    /// Multi-threaded `reencrypt`. The output is identical to `reencrypt`.
+    fn reencrypt_parallel<In1: Read, In2: Read, Out: Write>(rk: &mut In1, ct_old: &mut In2, ct_new: &mut Out, workers: usize) -> Result<()>
+        where Self: Sized + 'static, Self::K: Send + Sync + 'static
+    {
+        let rk_token = Self::K::read_key(rk).chain_err(|| ErrorKind::InvalidToken)?;
+        let mut writer = BufWriter::new(ct_new);
+        let ctr = Self::counter(&rk_token);
+        let nonce = Self::read_nonce(&rk_token, ct_old)?;
+        writer.write_all(&nonce)?;
+        parallel::update(ct_old, &mut writer, Self::CT_BLOCK_SIZE, ctr, workers,
+            move |block, ctr| Self::update_block(&rk_token, block, &nonce, ctr))
+    }
+
+    /// Appends plaintext to the end of an existing ciphertext under `key`.
+    ///
+    /// Only the final, padded block is rewritten, and the new blocks carry on
+    /// from the last counter. The result is the ciphertext of the combined
+    /// plaintext under the existing nonce, so it can be updated as usual. The
+    /// ciphertext is taken to start at the current position of `ct`.
+    fn append<In: Read, F: Read + Write + Seek>(key: Self::K, pt: &mut In, ct: &mut F) -> Result<()>
+        where Self: Sized
+    {
+        let ctr = Self::counter(&key);
+        let nonce = Self::read_nonce(&key, ct)?;
+        io::append(ct, pt, Self::PT_BLOCK_SIZE, Self::CT_BLOCK_SIZE, ctr,
+            |block, ctr| Self::decrypt_block(&key, block, &nonce, ctr),
+            |block, ctr| Self::encrypt_block(&key, block, &nonce, ctr))
+    }
+
+    /// Updates a ciphertext in place with a token, so that rotating a file
+    /// does not need room for a second copy.
+    ///
+    /// The old contents of each batch of blocks are written to `journal`
+    /// before the batch is overwritten. If an update is interrupted, calling
+    /// this again with the same token and journal restores the interrupted
+    /// batch and carries on from there, and an update which has finished is
+    /// not applied twice. The ciphertext is taken to start at the current
+    /// position of `ct`, and `journal` should start out empty.
+    fn reencrypt_in_place<In: Read, F: Read + Write + Seek, J: Read + Write + Seek>(rk: &mut In, ct: &mut F, journal: &mut J) -> Result<()>
+        where Self: Sized
+    {
+        let mut token = io::SecretBuf::new();
+        token.read_from(rk)?;
+        let rk_token = Self::K::read_key(&mut &token[..]).chain_err(|| ErrorKind::InvalidToken)?;
+        let start = ct.seek(SeekFrom::Current(0))?;
+        let mut journal = io::Journal::new(journal, h!(&token).as_ref(),
+            Self::CT_BLOCK_SIZE * io::BLOCKS_PER_ENTRY);
+        let from = match journal.recover(ct)? {
+            Some(from) => from,
+            None => return Ok(()),
+        };
+
+        ct.seek(SeekFrom::Start(start))?;
+        let nonce = Self::read_nonce(&rk_token, ct)?;
+        let body = start + nonce.len() as u64;
+        let ctr = Self::counter(&rk_token);
+        let n = io::update_in_place(ct, &mut journal, body, Self::CT_BLOCK_SIZE, ctr, from,
+            |block, ctr| Self::update_block(&rk_token, block, &nonce, ctr))?;
+        journal.finish(n)
+    }
+
+    /// Updates only the blocks in `blocks` with a token, in place, moving them
+    /// to the next epoch in `epochs`.
+    ///
+    /// The blocks must all be under the same epoch `e`, and `rk` should be a
+    /// token from the key for `e` to the key for `e + 1`. The ciphertext
+    /// (starting with its nonce) is taken to start at the current position
+    /// of `ct`.
+    ///
+    /// As for `reencrypt_in_place`, the old contents of each batch of blocks
+    /// are written to `journal` first. If an update is interrupted, calling
+    /// this again with the same token, range, epochs and journal restores the
+    /// interrupted batch and carries on from there, and an update which has
+    /// finished is not applied twice. `journal` should start out empty for
+    /// each range.
+    fn reencrypt_range<In: Read, F: Read + Write + Seek, J: Read + Write + Seek>(rk: &mut In, ct: &mut F, journal: &mut J, epochs: &mut KeyEpochs, blocks: Range<u64>) -> Result<()>
+        where Self: Sized
+    {
+        if blocks.start >= blocks.end {
+            return Ok(());
+        }
+        let current = epochs.epochs_in(blocks.clone());
+        if current.len() != 1 {
+            return Err(ErrorKind::InvalidEpochs("blocks to update are not all under one key epoch").into());
+        }
+        let next = current[0].checked_add(1).ok_or(ErrorKind::CounterOverflow)?;
+
+        let mut token = io::SecretBuf::new();
+        token.read_from(rk)?;
+        let rk_token = Self::K::read_key(&mut &token[..]).chain_err(|| ErrorKind::InvalidToken)?;
+        let start = ct.seek(SeekFrom::Current(0))?;
+        // The journal is for this token on these blocks, from this epoch.
+        let id = h!(&token[..], &kh_prf::u64_to_u8(blocks.start), &kh_prf::u64_to_u8(blocks.end),
+            &kh_prf::u64_to_u8(current[0] as u64));
+        let mut journal = io::Journal::new(journal, id.as_ref(),
+            Self::CT_BLOCK_SIZE * io::BLOCKS_PER_ENTRY);
+        if let Some(from) = journal.recover(ct)? {
+            ct.seek(SeekFrom::Start(start))?;
+            let nonce = Self::read_nonce(&rk_token, ct)?;
+            let body = start + nonce.len() as u64;
+            let ctr = Self::counter(&rk_token);
+            let n = io::update_range(ct, &mut journal, body, Self::CT_BLOCK_SIZE, ctr, blocks.clone(), from,
+                |block, ctr| Self::update_block(&rk_token, block, &nonce, ctr))?;
+            journal.finish(n)?;
+        }
+        epochs.set(blocks, next);
+        Ok(())
+    }
+
+    /// Decrypts a ciphertext whose blocks are under several keys, where
+    /// `keys[e]` is the key for epoch `e` in `epochs`.
+    fn decrypt_epochs<In: Read, Out: Write>(keys: &[Self::K], epochs: &KeyEpochs, ct: &mut In, pt: &mut Out) -> Result<()>
+        where Self: Sized
+    {
+        let mut reader = BufReader::new(ct);
+        let mut writer = BufWriter::new(pt);
+        let first = keys.first().ok_or(ErrorKind::InvalidEpochs("no key for key epoch"))?;
+        let nonce = Self::read_nonce(first, &mut reader)?;
+        io::decrypt_blocks(&mut reader, &mut writer, Self::CT_BLOCK_SIZE, |block, i| {
+            let key = keys.get(epochs.epoch(i) as usize).ok_or(ErrorKind::InvalidEpochs("no key for key epoch"))?;
+            let ctr = Self::counter(key).checked_add(i).ok_or(ErrorKind::CounterOverflow)?;
+            Self::decrypt_block(key, block, &nonce, ctr)
+        })
+    }
+
+    /// Decrypts only the plaintext bytes in `range`.
+    ///
+    /// Since each block is decrypted with `F(k, ctr)` alone, only the blocks
+    /// covering the range are read, plus the final block to find the length
+    /// of the padding. The ciphertext (starting with its nonce) is taken to
+    /// start at the current position of `ct`, and `range` is clamped to the
+    /// length of the plaintext.
+    fn decrypt_range<In: Read + Seek, Out: Write>(key: Self::K, ct: &mut In, range: Range<u64>, pt: &mut Out) -> Result<()>
+        where Self: Sized
+    {
+        let ctr = Self::counter(&key);
+        let nonce = Self::read_nonce(&key, ct)?;
+        io::decrypt_range(ct, pt, range, Self::PT_BLOCK_SIZE, Self::CT_BLOCK_SIZE, ctr,
+            |block, ctr| Self::decrypt_block(&key, block, &nonce, ctr))
+    }
+}
 
 /// Trait for an updatable encryption scheme.
 pub trait UpEnc {
@@ -88,10 +529,77 @@ pub trait UpEnc {
     fn encrypt<In: Read, Out: Write>(key: Self::K, pt: &mut In, ct_hdr: &mut Out, ct_body: &mut Out) -> Result<()>;
     fn reencrypt<In: Read, Out: Write>(rk: &mut In, ct1_hdr: &mut In, ct1_body: &mut In, ct2_hdr: &mut Out, ct2_body: &mut Out) -> Result<()>;
     fn decrypt<In: Read, Out: Write>(key: Self::K, ct_hdr: &mut In, ct_body: &mut In, pt: &mut Out) -> Result<()>;
+
+    /// As `decrypt`, but no plaintext reaches `pt` until the integrity check
+    /// has passed.
+    ///
+    /// The plaintext is first decrypted into `spool` (e.g. a new temporary
+    /// file), starting at its current position, and copied to `pt` once the
+    /// ciphertext has been verified. On failure `spool` may hold unverified
+    /// plaintext, and should be discarded.
+    fn decrypt_verified<In: Read, Spool: Read + Write + Seek, Out: Write>(key: Self::K, ct_hdr: &mut In, ct_body: &mut In, spool: &mut Spool, pt: &mut Out) -> Result<()>
+        where Self: Sized
+    {
+        let start = spool.seek(SeekFrom::Current(0))?;
+        Self::decrypt(key, ct_hdr, ct_body, spool)?;
+        spool.flush()?;
+        let end = spool.seek(SeekFrom::Current(0))?;
+        spool.seek(SeekFrom::Start(start))?;
+        std::io::copy(&mut spool.take(end - start), pt)?;
+        Ok(())
+    }
+
+    /// As `encrypt`, binding the ciphertext to the associated data `aad`
+    /// (e.g. an object ID and path), which must be given again to rotate or
+    /// decrypt it.
+    ///
+    /// The header authenticates `aad`, so neither it nor the body can be
+    /// moved to an object with different associated data. Headers written
+    /// this way start with a random value binding them to their tokens, and
+    /// can only be used with the `_aad` methods.
+    fn encrypt_aad<In: Read, Out: Write>(_key: Self::K, _aad: &[u8], _pt: &mut In, _ct_hdr: &mut Out, _ct_body: &mut Out) -> Result<()> {
+        Err(ErrorKind::Unsupported("associated data").into())
+    }
+
+    /// As `rekeygen`, for a ciphertext written by `encrypt_aad`. The new
+    /// header in the token is bound to the same `aad`.
+    fn rekeygen_aad<In: Read, Out: Write>(_k1: Self::K, _k2: Self::K, _aad: &[u8], _ct_hdr: &mut In, _token: &mut Out) -> Result<()> {
+        Err(ErrorKind::Unsupported("associated data").into())
+    }
+
+    /// As `reencrypt`, checking that the token was generated for the
+    /// ciphertext header `ct1_hdr`.
+    fn reencrypt_aad<In: Read, Out: Write>(_rk: &mut In, _aad: &[u8], _ct1_hdr: &mut In, _ct1_body: &mut In, _ct2_hdr: &mut Out, _ct2_body: &mut Out) -> Result<()> {
+        Err(ErrorKind::Unsupported("associated data").into())
+    }
+
+    /// As `decrypt`, for a ciphertext written by `encrypt_aad`.
+    fn decrypt_aad<In: Read, Out: Write>(_key: Self::K, _aad: &[u8], _ct_hdr: &mut In, _ct_body: &mut In, _pt: &mut Out) -> Result<()> {
+        Err(ErrorKind::Unsupported("associated data").into())
+    }
 }
 
 /// Trait encapsulating some common functionality needed for the keys.
 pub trait Key: PartialEq + Clone + Debug + Sized {
     fn read_key<In: Read>(key_in: &mut In) -> Result<Self>;
     fn write_key<Out: Write>(&self, key_out: &mut Out) -> Result<()>;
+
+    /// Reads a key which makes up the whole of `key_in`, such as a key file.
+    /// Input which is shorter or longer than one key is rejected.
+    fn read_key_file<In: Read>(key_in: &mut In) -> Result<Self> {
+        let mut bytes = io::SecretBuf::new();
+        bytes.read_from(key_in)?;
+        let mut reader = &bytes[..];
+        let key = Self::read_key(&mut reader).map_err(|e| {
+            let short = match *e.kind() {
+                ErrorKind::Io(ref err) => err.kind() == std::io::ErrorKind::UnexpectedEof,
+                _ => false,
+            };
+            if short { ErrorKind::InvalidKey("wrong length for this key type").into() } else { e }
+        })?;
+        if !reader.is_empty() {
+            return Err(ErrorKind::InvalidKey("wrong length for this key type").into());
+        }
+        Ok(key)
+    }
 }
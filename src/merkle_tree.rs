@@ -0,0 +1,88 @@
+//! Hash tree over the plaintext chunks of a `MerkleReCrypt` ciphertext.
+//!
+//! Nodes are numbered as a binary heap: the root is node 1, the children of
+//! node `j` are `2j` and `2j + 1`, and leaf `i` of a tree of width `w` is node
+//! `w + i`. Leaves past the last chunk are all zeroes.
+
+use kh_prf::u64_to_u8;
+
+use ring::digest;
+
+pub type Hash = [u8; 32];
+
+/// Bytes in each serialised node.
+pub const NODE_SIZE: usize = 32;
+
+fn to_hash(digest: digest::Digest) -> Hash {
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(digest.as_ref());
+    hash
+}
+
+pub fn leaf_hash(index: u64, chunk: &[u8]) -> Hash {
+    to_hash(h!(&[0u8], &u64_to_u8(index), chunk))
+}
+
+pub fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    to_hash(h!(&[1u8], left, right))
+}
+
+// Number of leaves in a tree over `n_leaves` chunks.
+pub fn width(n_leaves: u64) -> u64 {
+    n_leaves.next_power_of_two()
+}
+
+// Builds the whole tree, returning the nodes indexed by number (node 0 is
+// unused).
+pub fn build(leaves: &[Hash]) -> Vec<Hash> {
+    let w = width(leaves.len() as u64) as usize;
+    let mut nodes = vec![[0u8; 32]; 2 * w];
+    nodes[w..w + leaves.len()].copy_from_slice(leaves);
+    for j in (1..w).rev() {
+        nodes[j] = node_hash(&nodes[2 * j], &nodes[2 * j + 1]);
+    }
+    nodes
+}
+
+// Numbers of the siblings of each node on the path from `node` to the root.
+pub fn siblings(node: u64) -> Vec<u64> {
+    let mut result = Vec::new();
+    let mut j = node;
+    while j > 1 {
+        result.push(j ^ 1);
+        j /= 2;
+    }
+    result
+}
+
+// Recomputes the path from `node` to the root when `node` has hash `hash`,
+// given the hashes of `siblings(node)`. The root is the last entry.
+pub fn path(node: u64, hash: Hash, siblings: &[Hash]) -> Vec<(u64, Hash)> {
+    let mut result = vec![(node, hash)];
+    let (mut j, mut h) = (node, hash);
+    for sibling in siblings {
+        h = if j % 2 == 0 { node_hash(&h, sibling) } else { node_hash(sibling, &h) };
+        j /= 2;
+        result.push((j, h));
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn path_matches_tree() {
+        for n in 1..10 {
+            let leaves: Vec<Hash> = (0..n).map(|i| leaf_hash(i, &[i as u8; 7])).collect();
+            let nodes = build(&leaves);
+            let w = width(n);
+            for i in 0..n {
+                let sibs: Vec<Hash> = siblings(w + i).iter().map(|&j| nodes[j as usize]).collect();
+                let root = path(w + i, leaves[i as usize], &sibs).pop().unwrap();
+                assert_eq!(root, (1, nodes[1]));
+            }
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! Multi-threaded processing of counter-mode ciphertext blocks.
+//!
+//! Each block of a `KhPrf` ciphertext depends only on its counter, so the
+//! body is read in batches, each batch is split into contiguous counter
+//! ranges (one per worker), and the results are written back in order.
+//! The output is identical to the sequential implementations.
+
+use super::errors::{Error, ErrorKind, ResultExt};
+use common::pad;
+use io::{read_chunk, write_pt};
+
+use std::cmp::max;
+use std::io::{Read, Write};
+use std::sync::Arc;
+use std::thread;
+
+// Number of blocks handed to each worker per batch.
+const BLOCKS_PER_WORKER: usize = 1024;
+
+// Encrypts `pt` block-by-block with `f(block, ctr)`, padding the final block.
+pub fn encrypt<In: Read, Out: Write, F>(pt: &mut In, ct: &mut Out, pt_block_size: usize,
+        ctr: u64, workers: usize, f: F) -> Result<(), Error>
+    where F: Fn(&[u8], u64) -> Result<Vec<u8>, Error> + Send + Sync + 'static
+{
+    let workers = max(workers, 1);
+    let batch_len = pt_block_size * BLOCKS_PER_WORKER * workers;
+    let f = Arc::new(f);
+    let mut first = 0;
+
+    loop {
+        let chunk = read_chunk(pt, batch_len)?;
+        let eof = chunk.len() < batch_len;
+        let mut blocks: Vec<Vec<u8>> = chunk.chunks(pt_block_size).map(|b| b.to_vec()).collect();
+
+        if eof {
+            // As in the sequential version, a full final block is followed by
+            // an extra block of padding.
+            if blocks.last().map_or(true, |b| b.len() == pt_block_size) {
+                blocks.push(Vec::new());
+            }
+            pad(blocks.last_mut().unwrap(), pt_block_size);
+        }
+
+        let n = blocks.len() as u64;
+        for ct_block in map_blocks(blocks, ctr, first, workers, &f)? {
+            ct.write_all(&ct_block)?;
+        }
+        first += n;
+
+        if eof {
+            return Ok(());
+        }
+    }
+}
+
+// Decrypts `ct` block-by-block with `f(block, ctr)`, removing the padding
+// from the final block.
+pub fn decrypt<In: Read, Out: Write, F>(ct: &mut In, pt: &mut Out, ct_block_size: usize,
+        ctr: u64, workers: usize, f: F) -> Result<(), Error>
+    where F: Fn(&[u8], u64) -> Result<Vec<u8>, Error> + Send + Sync + 'static
+{
+    let workers = max(workers, 1);
+    let batch_len = ct_block_size * BLOCKS_PER_WORKER * workers;
+    let f = Arc::new(f);
+    let mut first = 0;
+
+    let mut prev_pt_block = Vec::new();
+    loop {
+        let chunk = read_chunk(ct, batch_len)?;
+        if chunk.len() % ct_block_size != 0 {
+            let index = first + (chunk.len() / ct_block_size) as u64;
+            return Err(ErrorKind::TruncatedBlock(index, chunk.len() % ct_block_size).into());
+        }
+        let eof = chunk.len() < batch_len;
+        let blocks: Vec<Vec<u8>> = chunk.chunks(ct_block_size).map(|b| b.to_vec()).collect();
+
+        let n = blocks.len() as u64;
+        for pt_block in map_blocks(blocks, ctr, first, workers, &f)? {
+            write_pt(prev_pt_block, pt, false)?;
+            prev_pt_block = pt_block;
+        }
+        first += n;
+
+        if eof {
+            // Only the last block carries padding.
+            return write_pt(prev_pt_block, pt, true);
+        }
+    }
+}
+
+// Updates each block of `ct1` with `f(block, ctr)`, writing the result to `ct2`.
+pub fn update<In: Read, Out: Write, F>(ct1: &mut In, ct2: &mut Out, ct_block_size: usize,
+        ctr: u64, workers: usize, f: F) -> Result<(), Error>
+    where F: Fn(&[u8], u64) -> Result<Vec<u8>, Error> + Send + Sync + 'static
+{
+    let workers = max(workers, 1);
+    let batch_len = ct_block_size * BLOCKS_PER_WORKER * workers;
+    let f = Arc::new(f);
+    let mut first = 0;
+
+    loop {
+        let chunk = read_chunk(ct1, batch_len)?;
+        if chunk.len() % ct_block_size != 0 {
+            let index = first + (chunk.len() / ct_block_size) as u64;
+            return Err(ErrorKind::TruncatedBlock(index, chunk.len() % ct_block_size).into());
+        }
+        let eof = chunk.len() < batch_len;
+        let blocks: Vec<Vec<u8>> = chunk.chunks(ct_block_size).map(|b| b.to_vec()).collect();
+
+        let n = blocks.len() as u64;
+        for ct_block in map_blocks(blocks, ctr, first, workers, &f)? {
+            ct2.write_all(&ct_block)?;
+        }
+        first += n;
+
+        if eof {
+            return Ok(());
+        }
+    }
+}
+
+// Applies `f` to the blocks with indices starting at `first`, where block
+// `i` uses counter `ctr + i`. The blocks are split into one contiguous
+// counter range per worker, and the results are returned in the original
+// order. Errors report the index of the failing block.
+pub fn map_blocks<F>(blocks: Vec<Vec<u8>>, ctr: u64, first: u64, workers: usize, f: &Arc<F>)
+        -> Result<Vec<Vec<u8>>, Error>
+    where F: Fn(&[u8], u64) -> Result<Vec<u8>, Error> + Send + Sync + 'static
+{
+    // Only the counters which are used need to fit, so a key may end at
+    // counter `u64::MAX`.
+    if !blocks.is_empty() && ctr.checked_add(first + blocks.len() as u64 - 1).is_none() {
+        return Err(ErrorKind::CounterOverflow.into());
+    }
+    let per_worker = (blocks.len() + workers - 1) / workers;
+    let mut blocks = blocks.into_iter();
+    let mut handles = Vec::with_capacity(workers);
+    let mut start = first;
+
+    loop {
+        let range: Vec<Vec<u8>> = blocks.by_ref().take(per_worker).collect();
+        if range.is_empty() {
+            break;
+        }
+        let f = f.clone();
+        let lo = start;
+        start += range.len() as u64;
+        handles.push(thread::spawn(move || {
+            range.iter().enumerate()
+                .map(|(j, block)| {
+                    let i = lo + j as u64;
+                    (*f)(block, ctr + i).chain_err(|| ErrorKind::InvalidBlock(i))
+                })
+                .collect::<Result<Vec<_>, Error>>()
+        }));
+    }
+
+    let mut result = Vec::new();
+    for handle in handles {
+        let out = handle.join().map_err(|_| Error::from(ErrorKind::WorkerPanicked))?;
+        result.extend(out?);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn worker_panic() {
+        let f = Arc::new(|_: &[u8], ctr: u64| -> Result<Vec<u8>, Error> {
+            if ctr == 3 {
+                panic!("worker panic");
+            }
+            Ok(vec![])
+        });
+        let blocks = vec![vec![0u8; 4]; 8];
+        match map_blocks(blocks, 1, 0, 2, &f) {
+            Err(Error(ErrorKind::WorkerPanicked, _)) => {},
+            other => panic!("expected WorkerPanicked, got {:?}", other),
+        }
+    }
+}
@@ -15,6 +15,9 @@ use std::env;
 // type ProfileCipher = Kss<RingAes, RingAes>;
 type ProfileCipher = ReCrypt<RingAes, KhPrf>;
 
+// Number of threads used to profile the parallel re-encryption.
+const PROFILE_WORKERS: usize = 4;
+
 pub fn run_all() {
     profile_init();
 
@@ -26,8 +29,12 @@ pub fn run_all() {
     for (iterations,size) in params {
         profile_init();
         profile_upenc::<ProfileCipher>(iterations, size);
+        profile_reencrypt_parallel(iterations, size, PROFILE_WORKERS);
         profile_clean();
     }
+
+    println!("\nKhPrf blocks");
+    profile_kh_prf_blocks(1000);
 }
 
 
@@ -142,6 +149,54 @@ fn profile_upenc<Scheme: UpEnc>(iterations:usize, bytes:usize) {
     run_profile(&dec_text, iterations, bytes, prep_final_ct, dec);
 }
 
+// Profiles `ReCrypt::reencrypt_parallel` for the profile cipher.
+fn profile_reencrypt_parallel(iterations: usize, bytes: usize, workers: usize) {
+    let prep = &|l|{
+        let k1 = ProfileCipher::keygen();
+        let k2 = ProfileCipher::keygen();
+        let pt_path = get_tmp_fname("upenc-profile");
+        create_test_file(&pt_path, &random_vec(l));
+        let ct_path = get_tmp_fname("upenc-profile");
+        let token_path = get_tmp_fname("upenc-profile");
+        {
+            let mut pt_file = open_file(&pt_path);
+            let mut ct_h = File::create(extend_path(&ct_path, "_h")).unwrap();
+            let mut ct_b = File::create(extend_path(&ct_path, "_b")).unwrap();
+            ProfileCipher::encrypt(k1.clone(), &mut pt_file, &mut ct_h, &mut ct_b).unwrap();
+        }
+        let mut ct_h = File::open(extend_path(&ct_path, "_h")).unwrap();
+        let mut token_file = open_file(&token_path);
+        ProfileCipher::rekeygen(k1, k2, &mut ct_h, &mut token_file).unwrap();
+        (token_path, ct_path)
+    };
+    let reenc = |(token_path, ct_path): (PathBuf, PathBuf)|{
+        let ct2_path = get_tmp_fname("upenc-profile");
+        let mut token_file = open_file(&token_path);
+        let mut ct1_h = File::open(extend_path(&ct_path, "_h")).unwrap();
+        let mut ct1_b = File::open(extend_path(&ct_path, "_b")).unwrap();
+
+        let mut ct2_h = File::create(extend_path(&ct2_path, "_h")).unwrap();
+        let mut ct2_b = File::create(extend_path(&ct2_path, "_b")).unwrap();
+        ProfileCipher::reencrypt_parallel(&mut token_file, None, &mut ct1_h, &mut ct1_b, &mut ct2_h, &mut ct2_b, workers).unwrap();
+    };
+
+    let text = format!("ReEnc x{:<3}  {}", workers, get_display_size(bytes));
+    run_profile(&text, iterations, bytes, prep, reenc);
+}
+
+// Times encrypting a single block under each hash suite, which is dominated
+// by hashing the counter to the curve.
+fn profile_kh_prf_blocks(iterations: usize) {
+    let suites = [("Block Legacy", HashSuite::Legacy), ("Block RFC 9380", HashSuite::Rfc9380)];
+    for &(name, suite) in suites.iter() {
+        let key = KhPrf::keygen_with_suite(suite);
+        let nonce = KhPrf::new_nonce(&key);
+        let prep = &|l| random_vec(l);
+        let enc = |block: Vec<u8>| KhPrf::encrypt_block(&key, &block, &nonce, 1).unwrap();
+        run_profile(name, iterations, 31, prep, enc);
+    }
+}
+
 // Converts byte count into a human-readable strings. Examples:
 // 100 B
 // 50 KB
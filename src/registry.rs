@@ -0,0 +1,186 @@
+//! Choosing a scheme at runtime.
+//!
+//! Each scheme is a separate type, so code using `Container` must know which
+//! one wrote a ciphertext at compile time. A `Registry` instead maps suite
+//! IDs to schemes, and reads the suite of a container to decide which one
+//! decrypts, re-keys or re-encrypts it. Keys are passed in their serialised
+//! form, as written by `Key::write_key`.
+
+use super::errors::*;
+use super::Key;
+use container::{read_suite, Container};
+use generic::{KemDem, Kss, MerkleReCrypt, Naive, ReCrypt};
+use {AesGcmSiv, KhPrf, LwrKhPrf, RingAes, RingAes256, RingAes256Stream, RingAesStream, RingChaCha, RingChaChaStream, RistrettoKhPrf, XChaCha};
+
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+
+/// A reader which can also seek, so that it can be used as a trait object.
+pub trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// The `Container` algorithms of a scheme, over serialised keys.
+pub trait Scheme: Send + Sync {
+    fn suite_id(&self) -> Vec<u8>;
+    /// Writes a new, random key.
+    fn keygen(&self, key_out: &mut Write) -> Result<()>;
+    fn encrypt(&self, key: &[u8], pt: &mut Read, ct: &mut Write) -> Result<()>;
+    fn rekeygen(&self, k1: &[u8], k2: &[u8], ct: &mut ReadSeek, token: &mut Write) -> Result<()>;
+    fn reencrypt(&self, rk: &mut Read, ct1: &mut ReadSeek, ct2: &mut Write) -> Result<()>;
+    fn decrypt(&self, key: &[u8], ct: &mut ReadSeek, pt: &mut Write) -> Result<()>;
+}
+
+struct Entry<S>(PhantomData<fn() -> S>);
+
+// Parses a serialised key, which must be exactly one key long.
+fn parse_key<K: Key>(key: &[u8]) -> Result<K> {
+    K::read_key_file(&mut &key[..])
+}
+
+impl<S: Container> Scheme for Entry<S> {
+    fn suite_id(&self) -> Vec<u8> {
+        S::suite_id()
+    }
+
+    fn keygen(&self, key_out: &mut Write) -> Result<()> {
+        S::keygen().write_key(&mut &mut *key_out)
+    }
+
+    fn encrypt(&self, key: &[u8], pt: &mut Read, ct: &mut Write) -> Result<()> {
+        S::encrypt_container(parse_key(key)?, &mut &mut *pt, &mut &mut *ct)
+    }
+
+    fn rekeygen(&self, k1: &[u8], k2: &[u8], ct: &mut ReadSeek, token: &mut Write) -> Result<()> {
+        S::rekeygen_container(parse_key(k1)?, parse_key(k2)?, &mut &mut *ct, &mut &mut *token)
+    }
+
+    fn reencrypt(&self, rk: &mut Read, ct1: &mut ReadSeek, ct2: &mut Write) -> Result<()> {
+        S::reencrypt_container(&mut &mut *rk, &mut &mut *ct1, &mut &mut *ct2)
+    }
+
+    fn decrypt(&self, key: &[u8], ct: &mut ReadSeek, pt: &mut Write) -> Result<()> {
+        S::decrypt_container(parse_key(key)?, &mut &mut *ct, &mut &mut *pt)
+    }
+}
+
+/// Schemes by suite ID.
+pub struct Registry {
+    schemes: HashMap<Vec<u8>, Box<Scheme>>,
+}
+
+impl Default for Registry {
+    /// A registry of every scheme in this crate, over the *ring* ciphers
+    /// and, for `ReCrypt` and `MerkleReCrypt`, each key-homomorphic PRF.
+    /// The online ciphers are included as the body cipher of `KemDem` and
+    /// `Kss`, and `AesGcmSiv` and `XChaCha` as the header cipher of the hybrid
+    /// schemes.
+    fn default() -> Registry {
+        let mut registry = Registry::new();
+        registry.register::<Naive<RingAes>>();
+        registry.register::<Naive<RingChaCha>>();
+        registry.register::<KemDem<RingAes>>();
+        registry.register::<KemDem<RingAes256>>();
+        registry.register::<KemDem<RingChaCha>>();
+        registry.register::<KemDem<RingAesStream>>();
+        registry.register::<KemDem<RingAes256Stream>>();
+        registry.register::<KemDem<RingChaChaStream>>();
+        registry.register::<Kss<RingAes, RingAes>>();
+        registry.register::<Kss<RingAes, RingChaCha>>();
+        registry.register::<Kss<RingChaCha, RingAes>>();
+        registry.register::<Kss<RingChaCha, RingChaCha>>();
+        registry.register::<Kss<RingAes256, RingAes256>>();
+        registry.register::<Kss<RingAes, RingAesStream>>();
+        registry.register::<Kss<RingChaCha, RingChaChaStream>>();
+        registry.register::<Kss<RingAes256, RingAes256Stream>>();
+        registry.register::<Kss<AesGcmSiv, RingAes>>();
+        registry.register::<Kss<AesGcmSiv, RingAesStream>>();
+        registry.register::<Kss<XChaCha, RingChaCha>>();
+        registry.register::<Kss<XChaCha, RingChaChaStream>>();
+        registry.register::<ReCrypt<RingAes, KhPrf>>();
+        registry.register::<ReCrypt<RingAes, RistrettoKhPrf>>();
+        registry.register::<ReCrypt<RingAes, LwrKhPrf>>();
+        registry.register::<ReCrypt<RingAes256, KhPrf>>();
+        registry.register::<ReCrypt<RingAes256, RistrettoKhPrf>>();
+        registry.register::<ReCrypt<RingAes256, LwrKhPrf>>();
+        registry.register::<ReCrypt<RingChaCha, KhPrf>>();
+        registry.register::<ReCrypt<RingChaCha, RistrettoKhPrf>>();
+        registry.register::<ReCrypt<RingChaCha, LwrKhPrf>>();
+        registry.register::<ReCrypt<AesGcmSiv, KhPrf>>();
+        registry.register::<ReCrypt<AesGcmSiv, RistrettoKhPrf>>();
+        registry.register::<ReCrypt<AesGcmSiv, LwrKhPrf>>();
+        registry.register::<ReCrypt<XChaCha, KhPrf>>();
+        registry.register::<ReCrypt<XChaCha, RistrettoKhPrf>>();
+        registry.register::<ReCrypt<XChaCha, LwrKhPrf>>();
+        registry.register::<MerkleReCrypt<RingAes, KhPrf>>();
+        registry.register::<MerkleReCrypt<RingAes, RistrettoKhPrf>>();
+        registry.register::<MerkleReCrypt<RingAes, LwrKhPrf>>();
+        registry.register::<MerkleReCrypt<RingAes256, KhPrf>>();
+        registry.register::<MerkleReCrypt<RingAes256, RistrettoKhPrf>>();
+        registry.register::<MerkleReCrypt<RingAes256, LwrKhPrf>>();
+        registry.register::<MerkleReCrypt<RingChaCha, KhPrf>>();
+        registry.register::<MerkleReCrypt<RingChaCha, RistrettoKhPrf>>();
+        registry.register::<MerkleReCrypt<RingChaCha, LwrKhPrf>>();
+        registry.register::<MerkleReCrypt<AesGcmSiv, KhPrf>>();
+        registry.register::<MerkleReCrypt<AesGcmSiv, RistrettoKhPrf>>();
+        registry.register::<MerkleReCrypt<AesGcmSiv, LwrKhPrf>>();
+        registry.register::<MerkleReCrypt<XChaCha, KhPrf>>();
+        registry.register::<MerkleReCrypt<XChaCha, RistrettoKhPrf>>();
+        registry.register::<MerkleReCrypt<XChaCha, LwrKhPrf>>();
+        registry
+    }
+}
+
+impl Registry {
+    /// An empty registry.
+    pub fn new() -> Registry {
+        Registry { schemes: HashMap::new() }
+    }
+
+    /// Adds the scheme `S`, replacing any scheme with the same suite ID.
+    pub fn register<S: Container + 'static>(&mut self) {
+        self.schemes.insert(S::suite_id(), Box::new(Entry::<S>(PhantomData)));
+    }
+
+    /// The suite IDs of the registered schemes.
+    pub fn suites(&self) -> Vec<Vec<u8>> {
+        self.schemes.keys().cloned().collect()
+    }
+
+    pub fn get(&self, suite: &[u8]) -> Result<&Scheme> {
+        match self.schemes.get(suite) {
+            Some(scheme) => Ok(&**scheme),
+            None => Err(ErrorKind::UnknownSuite(suite.to_vec()).into()),
+        }
+    }
+
+    /// Finds the scheme which wrote the container `ct`, leaving `ct` where
+    /// it was.
+    pub fn detect(&self, ct: &mut ReadSeek) -> Result<&Scheme> {
+        let start = ct.seek(SeekFrom::Current(0))?;
+        let suite = read_suite(&mut &mut *ct)?;
+        ct.seek(SeekFrom::Start(start))?;
+        self.get(&suite)
+    }
+
+    pub fn keygen(&self, suite: &[u8], key_out: &mut Write) -> Result<()> {
+        self.get(suite)?.keygen(key_out)
+    }
+
+    pub fn encrypt(&self, suite: &[u8], key: &[u8], pt: &mut Read, ct: &mut Write) -> Result<()> {
+        self.get(suite)?.encrypt(key, pt, ct)
+    }
+
+    pub fn rekeygen(&self, k1: &[u8], k2: &[u8], ct: &mut ReadSeek, token: &mut Write) -> Result<()> {
+        self.detect(ct)?.rekeygen(k1, k2, ct, token)
+    }
+
+    pub fn reencrypt(&self, rk: &mut Read, ct1: &mut ReadSeek, ct2: &mut Write) -> Result<()> {
+        self.detect(ct1)?.reencrypt(rk, ct1, ct2)
+    }
+
+    pub fn decrypt(&self, key: &[u8], ct: &mut ReadSeek, pt: &mut Write) -> Result<()> {
+        self.detect(ct)?.decrypt(key, ct, pt)
+    }
+}
@@ -3,8 +3,15 @@ use ring::rand::SecureRandom;
 
 use super::*;
 
+use std::fmt;
+use std::mem;
 use std::ops::{Add, Sub};
 
+use zeroize::{Zeroize, Zeroizing};
+
+use io::SecretBuf;
+use kh_prf::u64_to_u8;
+
 
 use std::io::{BufReader, BufWriter};
 
@@ -14,17 +21,29 @@ macro_rules! make_ring_ae {
         /// AE scheme wrapping the *ring* implementation
         pub struct $name;
 
-        #[derive(Clone, Debug, PartialEq)]
+        #[derive(Clone, PartialEq)]
         pub struct $keyname(Vec<u8>);
 
+        impl Drop for $keyname {
+            fn drop(&mut self) {
+                self.0.zeroize();
+            }
+        }
+
+        impl fmt::Debug for $keyname {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}(<redacted>)", stringify!($keyname))
+            }
+        }
+
         impl Key for $keyname {
             fn read_key<In: Read>(key_in: &mut In) -> Result<Self> {
                 let mut key = vec![0; $alg.key_len()];
-                key_in.read_exact(&mut key).chain_err(|| "unable to read from file")?;
+                key_in.read_exact(&mut key)?;
                 Ok($keyname(key))
             }
             fn write_key<Out: Write>(&self, key_out: &mut Out) -> Result<()> {
-                key_out.write_all(&self.0).chain_err(|| "unable to write to file")
+                Ok(key_out.write_all(&self.0)?)
             }
         }
 
@@ -55,37 +74,43 @@ macro_rules! make_ring_ae {
             }
 
             fn encrypt<In: Read, Out: Write>(key: Self::K, pt: &mut In, ct: &mut Out) -> Result<()> {
+                Self::encrypt_aad(key, &[], pt, ct)
+            }
+            fn decrypt<In: Read, Out: Write>(key: Self::K, ct: &mut In, pt: &mut Out) -> Result<()> {
+                Self::decrypt_aad(key, &[], ct, pt)
+            }
+
+            fn encrypt_aad<In: Read, Out: Write>(key: Self::K, aad: &[u8], pt: &mut In, ct: &mut Out) -> Result<()> {
                 let mut ct = BufWriter::new(ct);
-                let mut in_out = Vec::new();
-                pt.read_to_end(&mut in_out)?;
-                for _ in 0..$alg.tag_len() {
-                    in_out.push(0);
-                }
+                let mut in_out = SecretBuf::new();
+                in_out.read_from(pt)?;
+                let len = in_out.len();
+                in_out.resize(len + $alg.tag_len());
 
                 let mut iv = [0u8; 12];
                 let rng = rand::SystemRandom::new();
                 rng.fill(&mut iv).expect("could not generate random bytes for IV");
 
-                let key = aead::SealingKey::new(&$alg, &key.0).chain_err(|| "key invalid")?;
-                let out_len = aead::seal_in_place(&key, &iv, &mut [], &mut in_out, $alg.tag_len()).chain_err(|| "encryption failed")?;
+                let key = aead::SealingKey::new(&$alg, &key.0).chain_err(|| ErrorKind::InvalidKey("rejected by the AEAD"))?;
+                let out_len = aead::seal_in_place(&key, &iv, aad, &mut in_out, $alg.tag_len()).chain_err(|| ErrorKind::EncryptionFailed)?;
 
-                ct.write_all(&iv).map_err(Error::from).chain_err(|| "unable to write to file")?;
-                ct.write_all(&in_out[..out_len]).chain_err(|| "unable to write to file")?;
+                ct.write_all(&iv)?;
+                ct.write_all(&in_out[..out_len])?;
 
                 Ok(())
             }
-            fn decrypt<In: Read, Out: Write>(key: Self::K, ct: &mut In, pt: &mut Out) -> Result<()> {
+            fn decrypt_aad<In: Read, Out: Write>(key: Self::K, aad: &[u8], ct: &mut In, pt: &mut Out) -> Result<()> {
                 let mut ct = BufReader::new(ct);
                 let mut iv = [0u8; 12];
-                ct.read_exact(&mut iv).chain_err(|| "unable to read from file")?;
+                ::io::read_field(&mut ct, &mut iv, 0)?;
 
-                let mut in_out = Vec::new();
-                ct.read_to_end(&mut in_out).chain_err(|| "unable to read from file")?;
+                let mut in_out = SecretBuf::new();
+                in_out.read_from(&mut ct)?;
 
-                let key = aead::OpeningKey::new(&$alg, &key.0).chain_err(|| "incorrect key")?;
-                let out = aead::open_in_place(&key, &iv, &[], 0, &mut in_out).chain_err(|| "decryption failed")?;
+                let key = aead::OpeningKey::new(&$alg, &key.0).chain_err(|| ErrorKind::InvalidKey("rejected by the AEAD"))?;
+                let out = aead::open_in_place(&key, &iv, aad, 0, &mut in_out).chain_err(|| ErrorKind::DecryptionFailed)?;
 
-                pt.write_all(&out).chain_err(|| "unable to write to file")
+                Ok(pt.write_all(&out)?)
             }
         }
 
@@ -94,5 +119,128 @@ macro_rules! make_ring_ae {
 }
 
 
+// Plaintext bytes in each segment of a STREAM ciphertext.
+const SEGMENT_SIZE: usize = 64 * 1024;
+
+// Length of the random nonce prefix of a STREAM ciphertext. The rest of each
+// segment's nonce is a 4-byte segment counter and a last-segment flag.
+const NONCE_PREFIX_SIZE: usize = 7;
+
+fn segment_nonce(prefix: &[u8], i: u64, last: bool) -> Result<[u8; 12]> {
+    if i > u32::max_value() as u64 {
+        return Err(ErrorKind::CounterOverflow.into());
+    }
+    let mut nonce = [0u8; 12];
+    nonce[..NONCE_PREFIX_SIZE].copy_from_slice(prefix);
+    nonce[7..11].copy_from_slice(&u64_to_u8(i)[4..]);
+    nonce[11] = last as u8;
+    Ok(nonce)
+}
+
+// Reads up to a segment of plaintext, into a buffer with room for the tag.
+fn read_segment<In: Read>(pt: &mut In, tag_len: usize) -> Result<SecretBuf> {
+    let mut segment = SecretBuf::with_capacity(SEGMENT_SIZE + tag_len);
+    segment.read_from(&mut pt.take(SEGMENT_SIZE as u64))?;
+    Ok(segment)
+}
+
+// Online AEAD, using the STREAM construction of Hoang, Reyhanitabar, Rogaway
+// and Vizár over the AEAD of an existing `make_ring_ae!` cipher.
+macro_rules! make_ring_stream {
+    ($name:ident, $base:ident, $alg:expr) => (
+        /// Online AE scheme, encrypting in 64 KiB segments so that memory use
+        /// does not grow with the plaintext.
+        ///
+        /// The ciphertext is a random nonce prefix followed by the sealed
+        /// segments, where each segment's nonce includes its index and
+        /// whether it is the last, so reordered, dropped or truncated
+        /// segments fail to decrypt. Segments are written out as they are
+        /// authenticated, so a failed decryption may already have written
+        /// the plaintext of earlier segments.
+        pub struct $name;
+
+        impl Cipher for $name {
+            type K = <$base as Cipher>::K;
+
+            fn keygen() -> Self::K {
+                $base::keygen()
+            }
+
+            fn encrypt<In: Read, Out: Write>(key: Self::K, pt: &mut In, ct: &mut Out) -> Result<()> {
+                Self::encrypt_aad(key, &[], pt, ct)
+            }
+            fn decrypt<In: Read, Out: Write>(key: Self::K, ct: &mut In, pt: &mut Out) -> Result<()> {
+                Self::decrypt_aad(key, &[], ct, pt)
+            }
+
+            fn encrypt_aad<In: Read, Out: Write>(key: Self::K, aad: &[u8], pt: &mut In, ct: &mut Out) -> Result<()> {
+                let mut ct = BufWriter::new(ct);
+                let key = aead::SealingKey::new(&$alg, &key.0).chain_err(|| ErrorKind::InvalidKey("rejected by the AEAD"))?;
+
+                let mut prefix = [0u8; NONCE_PREFIX_SIZE];
+                let rng = rand::SystemRandom::new();
+                rng.fill(&mut prefix).expect("could not generate random bytes for nonce");
+                ct.write_all(&prefix)?;
+
+                // Read one segment ahead, to know which segment is the last.
+                let mut next = read_segment(pt, $alg.tag_len())?;
+                let mut i = 0;
+                loop {
+                    let mut in_out = mem::replace(&mut next, SecretBuf::new());
+                    let last = in_out.len() < SEGMENT_SIZE || {
+                        next = read_segment(pt, $alg.tag_len())?;
+                        next.is_empty()
+                    };
+                    let nonce = segment_nonce(&prefix, i, last)?;
+                    let len = in_out.len();
+                    in_out.resize(len + $alg.tag_len());
+                    let out_len = aead::seal_in_place(&key, &nonce, aad, &mut in_out, $alg.tag_len()).chain_err(|| ErrorKind::EncryptionFailed)?;
+                    ct.write_all(&in_out[..out_len])?;
+                    if last {
+                        break;
+                    }
+                    i += 1;
+                }
+                Ok(ct.flush()?)
+            }
+            fn decrypt_aad<In: Read, Out: Write>(key: Self::K, aad: &[u8], ct: &mut In, pt: &mut Out) -> Result<()> {
+                let mut ct = BufReader::new(ct);
+                let key = aead::OpeningKey::new(&$alg, &key.0).chain_err(|| ErrorKind::InvalidKey("rejected by the AEAD"))?;
+                let mut prefix = [0u8; NONCE_PREFIX_SIZE];
+                ::io::read_field(&mut ct, &mut prefix, 0)?;
+
+                let segment_len = SEGMENT_SIZE + $alg.tag_len();
+                let mut next = ::io::read_chunk(&mut ct, segment_len)?;
+                let mut i = 0;
+                loop {
+                    let mut in_out = Zeroizing::new(mem::replace(&mut next, Vec::new()));
+                    let last = in_out.len() < segment_len || {
+                        next = ::io::read_chunk(&mut ct, segment_len)?;
+                        next.is_empty()
+                    };
+                    if in_out.len() < $alg.tag_len() {
+                        let offset = (NONCE_PREFIX_SIZE + i as usize * segment_len + in_out.len()) as u64;
+                        return Err(ErrorKind::TruncatedCiphertext(offset).into());
+                    }
+                    let nonce = segment_nonce(&prefix, i, last)?;
+                    {
+                        let out = aead::open_in_place(&key, &nonce, aad, 0, &mut in_out).chain_err(|| ErrorKind::DecryptionFailed)?;
+                        pt.write_all(out)?;
+                    }
+                    if last {
+                        break;
+                    }
+                    i += 1;
+                }
+                Ok(())
+            }
+        }
+    )
+}
+
 make_ring_ae!(RingAes, aead::AES_128_GCM, AesKey128);
-make_ring_ae!(RingChaCha, aead::CHACHA20_POLY1305, ChaChaKey128);
+make_ring_ae!(RingAes256, aead::AES_256_GCM, AesKey256);
+make_ring_ae!(RingChaCha, aead::CHACHA20_POLY1305, ChaChaKey256);
+make_ring_stream!(RingAesStream, RingAes, aead::AES_128_GCM);
+make_ring_stream!(RingAes256Stream, RingAes256, aead::AES_256_GCM);
+make_ring_stream!(RingChaChaStream, RingChaCha, aead::CHACHA20_POLY1305);
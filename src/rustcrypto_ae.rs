@@ -0,0 +1,128 @@
+use aead::{Aead, KeyInit, Nonce, Payload};
+use aes_gcm_siv::Aes256GcmSiv;
+use chacha20poly1305::XChaCha20Poly1305;
+use ring::rand;
+use ring::rand::SecureRandom;
+
+use super::*;
+
+use std::fmt;
+use std::ops::{Add, Sub};
+
+use io::SecretBuf;
+use zeroize::{Zeroize, Zeroizing};
+
+macro_rules! make_rustcrypto_ae {
+    ($(#[$attr:meta])* $name:ident, $alg:ty, $keyname:ident, $key_size:expr, $nonce_size:expr) => (
+        $(#[$attr])*
+        pub struct $name;
+
+        /// Key for the cipher of the same name: random bytes.
+        #[derive(Clone, PartialEq)]
+        pub struct $keyname(Vec<u8>);
+
+        impl Drop for $keyname {
+            fn drop(&mut self) {
+                self.0.zeroize();
+            }
+        }
+
+        impl fmt::Debug for $keyname {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}(<redacted>)", stringify!($keyname))
+            }
+        }
+
+        impl Key for $keyname {
+            fn read_key<In: Read>(key_in: &mut In) -> Result<Self> {
+                let mut key = vec![0; $key_size];
+                key_in.read_exact(&mut key)?;
+                Ok($keyname(key))
+            }
+            fn write_key<Out: Write>(&self, key_out: &mut Out) -> Result<()> {
+                Ok(key_out.write_all(&self.0)?)
+            }
+        }
+
+        impl<'a> Add for &'a $keyname {
+            type Output = $keyname;
+
+            fn add(self, other: &'a $keyname) -> $keyname {
+                $keyname(self.0.iter().zip(other.0.iter()).map(|(a, b)| a^b).collect())
+            }
+        }
+
+        impl<'a> Sub for &'a $keyname {
+            type Output = $keyname;
+
+            fn sub(self, other: &'a $keyname) -> $keyname {
+                self + other
+            }
+        }
+
+        impl Cipher for $name {
+            type K = $keyname;
+
+            fn keygen() -> Self::K {
+                let mut rand_bytes = vec![0u8; $key_size];
+                let rng = rand::SystemRandom::new();
+                rng.fill(&mut rand_bytes).expect("could not generate random bytes for keygen");
+                $keyname(rand_bytes)
+            }
+
+            fn encrypt<In: Read, Out: Write>(key: Self::K, pt: &mut In, ct: &mut Out) -> Result<()> {
+                Self::encrypt_aad(key, &[], pt, ct)
+            }
+            fn decrypt<In: Read, Out: Write>(key: Self::K, ct: &mut In, pt: &mut Out) -> Result<()> {
+                Self::decrypt_aad(key, &[], ct, pt)
+            }
+
+            fn encrypt_aad<In: Read, Out: Write>(key: Self::K, aad: &[u8], pt: &mut In, ct: &mut Out) -> Result<()> {
+                let mut msg = SecretBuf::new();
+                msg.read_from(pt)?;
+
+                let mut nonce = [0u8; $nonce_size];
+                let rng = rand::SystemRandom::new();
+                rng.fill(&mut nonce).expect("could not generate random bytes for nonce");
+
+                let cipher = <$alg>::new_from_slice(&key.0).map_err(|_| ErrorKind::InvalidKey("rejected by the AEAD"))?;
+                let out = cipher.encrypt(Nonce::<$alg>::from_slice(&nonce), Payload { msg: &msg, aad: aad })
+                    .map_err(|_| ErrorKind::EncryptionFailed)?;
+
+                ct.write_all(&nonce)?;
+                ct.write_all(&out)?;
+                Ok(())
+            }
+            fn decrypt_aad<In: Read, Out: Write>(key: Self::K, aad: &[u8], ct: &mut In, pt: &mut Out) -> Result<()> {
+                let mut nonce = [0u8; $nonce_size];
+                ::io::read_field(ct, &mut nonce, 0)?;
+                let mut msg = Vec::new();
+                ct.read_to_end(&mut msg)?;
+
+                let cipher = <$alg>::new_from_slice(&key.0).map_err(|_| ErrorKind::InvalidKey("rejected by the AEAD"))?;
+                let out = Zeroizing::new(cipher.decrypt(Nonce::<$alg>::from_slice(&nonce), Payload { msg: &msg, aad: aad })
+                    .map_err(|_| ErrorKind::DecryptionFailed)?);
+
+                Ok(pt.write_all(&out)?)
+            }
+        }
+    )
+}
+
+make_rustcrypto_ae!(
+    /// AES-256-GCM-SIV (RFC 8452), using the RustCrypto implementation.
+    ///
+    /// Like `RingAes`, each ciphertext starts with a random 96-bit nonce.
+    /// Unlike AES-GCM, a repeated nonce only reveals whether the same
+    /// plaintext was encrypted twice, so this is the safer choice of header
+    /// cipher for long-lived keys which encrypt a very large number of
+    /// headers.
+    AesGcmSiv, Aes256GcmSiv, AesGcmSivKey256, 32, 12);
+
+make_rustcrypto_ae!(
+    /// XChaCha20-Poly1305, using the RustCrypto implementation.
+    ///
+    /// Each ciphertext starts with a random 192-bit nonce, which is long
+    /// enough that nonces can be drawn at random for any practical number of
+    /// messages under one key.
+    XChaCha, XChaCha20Poly1305, XChaChaKey256, 32, 24);
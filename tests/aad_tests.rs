@@ -0,0 +1,198 @@
+extern crate recrypt;
+
+use recrypt::generic::{Kss, MerkleReCrypt, ReCrypt};
+use recrypt::{Cipher, Container, KeyEpochs, UpEnc};
+use recrypt::{KhPrf, LwrKhPrf, RingAes, RingChaCha};
+
+use std::io::Cursor;
+
+mod helpers;
+
+use helpers::random_vec;
+
+fn cipher_aad<C: Cipher>() {
+    let pt = random_vec(1000);
+    let key = C::keygen();
+    let mut ct = Vec::new();
+    C::encrypt_aad(key.clone(), b"object 1", &mut &pt[..], &mut ct).unwrap();
+
+    let mut out = Vec::new();
+    C::decrypt_aad(key.clone(), b"object 1", &mut &ct[..], &mut out).unwrap();
+    assert_eq!(pt, out);
+    assert!(C::decrypt_aad(key.clone(), b"object 2", &mut &ct[..], &mut Vec::new()).is_err());
+    assert!(C::decrypt(key.clone(), &mut &ct[..], &mut Vec::new()).is_err());
+
+    // Empty associated data is the same as none.
+    let mut ct = Vec::new();
+    C::encrypt(key.clone(), &mut &pt[..], &mut ct).unwrap();
+    let mut out = Vec::new();
+    C::decrypt_aad(key, b"", &mut &ct[..], &mut out).unwrap();
+    assert_eq!(pt, out);
+}
+
+#[test]
+fn ring_aad() {
+    cipher_aad::<RingAes>();
+    cipher_aad::<RingChaCha>();
+}
+
+#[test]
+fn kh_prf_rejects_aad() {
+    let mut ct = Vec::new();
+    assert!(KhPrf::encrypt_aad(KhPrf::keygen(), b"object 1", &mut &random_vec(100)[..], &mut ct).is_err());
+    KhPrf::encrypt_aad(KhPrf::keygen(), b"", &mut &random_vec(100)[..], &mut ct).unwrap();
+}
+
+fn upenc_aad<S: UpEnc>() {
+    let pt = random_vec(1000);
+    let k1 = S::keygen();
+    let k2 = S::keygen();
+    let (mut hdr1, mut body1) = (Vec::new(), Vec::new());
+    let (mut hdr2, mut body2) = (Vec::new(), Vec::new());
+    S::encrypt_aad(k1.clone(), b"tenant/a.txt", &mut &pt[..], &mut hdr1, &mut body1).unwrap();
+    S::encrypt_aad(k1.clone(), b"tenant/b.txt", &mut &pt[..], &mut hdr2, &mut body2).unwrap();
+
+    let mut out = Vec::new();
+    S::decrypt_aad(k1.clone(), b"tenant/a.txt", &mut &hdr1[..], &mut &body1[..], &mut out).unwrap();
+    assert_eq!(pt, out);
+
+    // Wrong name, or a header or body moved from the other object.
+    assert!(S::decrypt_aad(k1.clone(), b"tenant/b.txt", &mut &hdr1[..], &mut &body1[..], &mut Vec::new()).is_err());
+    assert!(S::decrypt_aad(k1.clone(), b"tenant/a.txt", &mut &hdr2[..], &mut &body1[..], &mut Vec::new()).is_err());
+    assert!(S::decrypt_aad(k1.clone(), b"tenant/a.txt", &mut &hdr1[..], &mut &body2[..], &mut Vec::new()).is_err());
+    assert!(S::decrypt(k1.clone(), &mut &hdr1[..], &mut &body1[..], &mut Vec::new()).is_err());
+
+    // Nothing in the clear depends on the associated data.
+    let mut hdr4 = Vec::new();
+    S::encrypt_aad(k1.clone(), b"tenant/a.txt", &mut &pt[..], &mut hdr4, &mut Vec::new()).unwrap();
+    assert!(hdr1[..16] != hdr4[..16]);
+
+    assert!(S::rekeygen_aad(k1.clone(), k2.clone(), b"tenant/b.txt", &mut &hdr1[..], &mut Vec::new()).is_err());
+    let mut rk = Vec::new();
+    S::rekeygen_aad(k1.clone(), k2.clone(), b"tenant/a.txt", &mut &hdr1[..], &mut rk).unwrap();
+
+    // The token is for a.txt only, and for this copy of it.
+    let res = S::reencrypt_aad(&mut &rk[..], b"tenant/b.txt", &mut &hdr2[..], &mut &body2[..], &mut Vec::new(), &mut Vec::new());
+    assert!(res.is_err());
+    let res = S::reencrypt_aad(&mut &rk[..], b"tenant/a.txt", &mut &hdr4[..], &mut &body1[..], &mut Vec::new(), &mut Vec::new());
+    assert!(res.is_err());
+
+    let (mut hdr3, mut body3) = (Vec::new(), Vec::new());
+    S::reencrypt_aad(&mut &rk[..], b"tenant/a.txt", &mut &hdr1[..], &mut &body1[..], &mut hdr3, &mut body3).unwrap();
+    let mut out = Vec::new();
+    S::decrypt_aad(k2.clone(), b"tenant/a.txt", &mut &hdr3[..], &mut &body3[..], &mut out).unwrap();
+    assert_eq!(pt, out);
+    assert!(S::decrypt_aad(k2, b"tenant/b.txt", &mut &hdr3[..], &mut &body3[..], &mut Vec::new()).is_err());
+}
+
+#[test]
+fn kss_aad() {
+    upenc_aad::<Kss<RingAes, RingAes>>();
+}
+
+#[test]
+fn recrypt_aad() {
+    upenc_aad::<ReCrypt<RingAes, KhPrf>>();
+    upenc_aad::<ReCrypt<RingChaCha, LwrKhPrf>>();
+}
+
+#[test]
+fn recrypt_aad_extras() {
+    type Scheme = ReCrypt<RingAes, KhPrf>;
+    let aad = Some(&b"tenant/a.txt"[..]);
+    let other = Some(&b"tenant/b.txt"[..]);
+    let pt = random_vec(31 * 30);
+    let k1 = Scheme::keygen();
+    let k2 = Scheme::keygen();
+    let (mut hdr, mut body) = (Vec::new(), Vec::new());
+    Scheme::encrypt_aad(k1.clone(), aad.unwrap(), &mut &pt[..], &mut hdr, &mut body).unwrap();
+
+    // Only headers with associated data carry a binding value.
+    let mut plain_hdr = Vec::new();
+    Scheme::encrypt(k1.clone(), &mut &pt[..], &mut plain_hdr, &mut Vec::new()).unwrap();
+    assert_eq!(hdr.len(), plain_hdr.len() + 16);
+
+    let mut out = Vec::new();
+    Scheme::decrypt_range(k1.clone(), aad, &mut &hdr[..], &mut Cursor::new(&body), 10..100, &mut out).unwrap();
+    assert_eq!(&pt[10..100], &out[..]);
+    assert!(Scheme::decrypt_range(k1.clone(), other, &mut &hdr[..], &mut Cursor::new(&body), 10..100, &mut Vec::new()).is_err());
+    assert!(Scheme::decrypt_range(k1.clone(), None, &mut &hdr[..], &mut Cursor::new(&body), 10..100, &mut Vec::new()).is_err());
+
+    // Appending keeps the binding, so the token still matches.
+    let mut file = Cursor::new(body.clone());
+    let mut hdr2 = Vec::new();
+    assert!(Scheme::append(k1.clone(), other, &mut &hdr[..], &mut file, &mut &b"more"[..], &mut Vec::new()).is_err());
+    file.set_position(0);
+    Scheme::append(k1.clone(), aad, &mut &hdr[..], &mut file, &mut &b"more"[..], &mut hdr2).unwrap();
+    assert_eq!(hdr[..16], hdr2[..16]);
+    let mut out = Vec::new();
+    Scheme::decrypt_aad(k1.clone(), aad.unwrap(), &mut &hdr2[..], &mut &file.get_ref()[..], &mut out).unwrap();
+    assert_eq!([&pt[..], b"more"].concat(), out);
+
+    let mut rk = Vec::new();
+    Scheme::rekeygen_aad(k1.clone(), k2.clone(), aad.unwrap(), &mut &hdr[..], &mut rk).unwrap();
+
+    // In place, the token is checked against the old header.
+    let mut journal = Cursor::new(Vec::new());
+    let mut file = Cursor::new(body.clone());
+    let res = Scheme::reencrypt_in_place(&mut &rk[..], aad, &mut &plain_hdr[..], &mut file, &mut journal, &mut Vec::new());
+    assert!(res.is_err());
+    let mut hdr3 = Vec::new();
+    Scheme::reencrypt_in_place(&mut &rk[..], aad, &mut &hdr[..], &mut file, &mut journal, &mut hdr3).unwrap();
+    let mut out = Vec::new();
+    Scheme::decrypt_aad(k2.clone(), aad.unwrap(), &mut &hdr3[..], &mut &file.get_ref()[..], &mut out).unwrap();
+    assert_eq!(pt, out);
+
+    // And in parallel.
+    let (mut hdr3, mut body3) = (Vec::new(), Vec::new());
+    Scheme::reencrypt_parallel(&mut &rk[..], aad, &mut &hdr[..], &mut &body[..], &mut hdr3, &mut body3, 3).unwrap();
+    let mut out = Vec::new();
+    Scheme::decrypt_aad(k2.clone(), aad.unwrap(), &mut &hdr3[..], &mut &body3[..], &mut out).unwrap();
+    assert_eq!(pt, out);
+
+    // And a range at a time.
+    let mut file = Cursor::new(body.clone());
+    let mut epochs = KeyEpochs::new(0);
+    Scheme::reencrypt_range(&mut &rk[..], &mut file, &mut Cursor::new(Vec::new()), &mut epochs, 0..10).unwrap();
+    let mut out = Vec::new();
+    Scheme::decrypt_epochs(k1.clone(), aad, &mut &rk[..], &mut &hdr[..], &mut &file.get_ref()[..], &epochs, &mut out).unwrap();
+    assert_eq!(pt, out);
+    file.set_position(0);
+    Scheme::reencrypt_range(&mut &rk[..], &mut file, &mut Cursor::new(Vec::new()), &mut epochs, 10..31).unwrap();
+    file.set_position(0);
+    let res = Scheme::finish_reencrypt_range(&mut &rk[..], aad, &mut &plain_hdr[..], &mut file, &mut epochs, &mut Vec::new());
+    assert!(res.is_err());
+    file.set_position(0);
+    let mut hdr3 = Vec::new();
+    Scheme::finish_reencrypt_range(&mut &rk[..], aad, &mut &hdr[..], &mut file, &mut epochs, &mut hdr3).unwrap();
+    let mut out = Vec::new();
+    Scheme::decrypt_aad(k2, aad.unwrap(), &mut &hdr3[..], &mut &file.get_ref()[..], &mut out).unwrap();
+    assert_eq!(pt, out);
+}
+
+#[test]
+fn merkle_rejects_aad() {
+    type Scheme = MerkleReCrypt<RingAes, KhPrf>;
+    let res = Scheme::encrypt_aad(Scheme::keygen(), b"object 1", &mut &random_vec(100)[..], &mut Vec::new(), &mut Vec::new());
+    assert!(res.is_err());
+}
+
+#[test]
+fn container_aad() {
+    type Scheme = ReCrypt<RingAes, KhPrf>;
+    let pt = random_vec(1000);
+    let k1 = Scheme::keygen();
+    let k2 = Scheme::keygen();
+    let mut ct1 = Vec::new();
+    Scheme::encrypt_container_aad(k1.clone(), b"tenant/a.txt", &mut &pt[..], &mut ct1).unwrap();
+    assert!(Scheme::decrypt_container(k1.clone(), &mut Cursor::new(&ct1), &mut Vec::new()).is_err());
+    assert!(Scheme::decrypt_container_aad(k1.clone(), b"tenant/b.txt", &mut Cursor::new(&ct1), &mut Vec::new()).is_err());
+
+    let mut rk = Vec::new();
+    Scheme::rekeygen_container_aad(k1, k2.clone(), b"tenant/a.txt", &mut Cursor::new(&ct1), &mut rk).unwrap();
+    let mut ct2 = Vec::new();
+    Scheme::reencrypt_container_aad(&mut &rk[..], b"tenant/a.txt", &mut Cursor::new(&ct1), &mut ct2).unwrap();
+    let mut out = Vec::new();
+    Scheme::decrypt_container_aad(k2, b"tenant/a.txt", &mut Cursor::new(&ct2), &mut out).unwrap();
+    assert_eq!(pt, out);
+}
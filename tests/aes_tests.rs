@@ -1,7 +1,7 @@
 extern crate recrypt;
 
-use recrypt::generic::Naive;
-use recrypt::RingAes;
+use recrypt::generic::{Kss, Naive, ReCrypt};
+use recrypt::{KhPrf, RingAes, RingAes256};
 
 mod helpers;
 
@@ -10,3 +10,9 @@ fn encrypt_sane() {
     helpers::filecrypt_rt::<Naive<RingAes>>(16);
 }
 
+#[test]
+fn aes256_sane() {
+    helpers::filecrypt_rt::<Naive<RingAes256>>(16);
+    helpers::encrypt_update_once::<Kss<RingAes256, RingAes256>>(16);
+    helpers::encrypt_update_once::<ReCrypt<RingAes256, KhPrf>>(31);
+}
@@ -0,0 +1,122 @@
+extern crate recrypt;
+
+use recrypt::errors::ErrorKind;
+use recrypt::generic::ReCrypt;
+use recrypt::{Cipher, KeyHomomorphicPrf, UpEnc, UpEncCtxtIndep};
+use recrypt::{KhPrf, LwrKhPrf, RingAes, RistrettoKhPrf};
+
+use std::io::Cursor;
+
+mod helpers;
+
+use helpers::random_vec;
+
+// Appends to ciphertexts of various lengths, including ones ending on a block
+// boundary, and checks the result still decrypts after an update.
+fn append_then_rotate<C: KeyHomomorphicPrf>() {
+    let bs = C::PT_BLOCK_SIZE;
+    for &(len1, len2) in &[(1, 1), (bs - 1, 1), (bs, bs), (3 * bs + 5, 0), (2 * bs, 4 * bs + 3)] {
+        let pt1 = random_vec(len1);
+        let pt2 = random_vec(len2);
+        let k1 = C::keygen();
+        let k2 = C::keygen_next(&k1).unwrap();
+        let mut ct = Vec::new();
+        C::encrypt(k1.clone(), &mut &pt1[..], &mut ct).unwrap();
+
+        let mut file = Cursor::new(ct);
+        C::append(k1.clone(), &mut &pt2[..], &mut file).unwrap();
+        let mut out = Vec::new();
+        C::decrypt(k1.clone(), &mut &file.get_ref()[..], &mut out).unwrap();
+        assert_eq!([&pt1[..], &pt2[..]].concat(), out);
+
+        let mut rk = Vec::new();
+        C::rekeygen(k1, k2.clone(), &mut rk).unwrap();
+        let mut ct2 = Vec::new();
+        C::reencrypt(&mut &rk[..], &mut &file.get_ref()[..], &mut ct2).unwrap();
+        out.clear();
+        C::decrypt(k2, &mut &ct2[..], &mut out).unwrap();
+        assert_eq!([&pt1[..], &pt2[..]].concat(), out);
+    }
+}
+
+#[test]
+fn kh_prf_append() {
+    append_then_rotate::<KhPrf>();
+}
+
+#[test]
+fn ristretto_kh_prf_append() {
+    append_then_rotate::<RistrettoKhPrf>();
+}
+
+#[test]
+fn lwr_kh_prf_append() {
+    append_then_rotate::<LwrKhPrf>();
+}
+
+#[test]
+fn recrypt_append() {
+    type Scheme = ReCrypt<RingAes, KhPrf>;
+    let pt1 = random_vec(100);
+    let pt2 = random_vec(200);
+    let k1 = Scheme::keygen();
+    let k2 = Scheme::keygen();
+    let mut hdr = Vec::new();
+    let mut body = Vec::new();
+    Scheme::encrypt(k1.clone(), &mut &pt1[..], &mut hdr, &mut body).unwrap();
+
+    let mut file = Cursor::new(body);
+    let mut hdr2 = Vec::new();
+    Scheme::append(k1.clone(), None, &mut &hdr[..], &mut file, &mut &pt2[..], &mut hdr2).unwrap();
+    let mut out = Vec::new();
+    Scheme::decrypt(k1.clone(), &mut &hdr2[..], &mut &file.get_ref()[..], &mut out).unwrap();
+    assert_eq!([&pt1[..], &pt2[..]].concat(), out);
+
+    // The old header no longer matches the body.
+    assert!(Scheme::decrypt(k1.clone(), &mut &hdr[..], &mut &file.get_ref()[..], &mut Vec::new()).is_err());
+
+    let mut rk = Vec::new();
+    Scheme::rekeygen(k1, k2.clone(), &mut &hdr2[..], &mut rk).unwrap();
+    let mut hdr3 = Vec::new();
+    let mut body3 = Vec::new();
+    Scheme::reencrypt(&mut &rk[..], &mut &hdr2[..], &mut &file.get_ref()[..], &mut hdr3, &mut body3).unwrap();
+    out.clear();
+    Scheme::decrypt(k2, &mut &hdr3[..], &mut &body3[..], &mut out).unwrap();
+    assert_eq!([&pt1[..], &pt2[..]].concat(), out);
+}
+
+#[test]
+fn recrypt_append_checks_tag() {
+    type Scheme = ReCrypt<RingAes, KhPrf>;
+    let k1 = Scheme::keygen();
+    let mut hdr1 = Vec::new();
+    let mut body1 = Vec::new();
+    let mut hdr2 = Vec::new();
+    let mut body2 = Vec::new();
+    Scheme::encrypt(k1.clone(), &mut &random_vec(100)[..], &mut hdr1, &mut body1).unwrap();
+    Scheme::encrypt(k1.clone(), &mut &random_vec(100)[..], &mut hdr2, &mut body2).unwrap();
+
+    // Header and body from different ciphertexts.
+    let res = Scheme::append(k1, None, &mut &hdr1[..], &mut Cursor::new(body2), &mut &b"more"[..], &mut Vec::new());
+    assert!(res.is_err());
+}
+
+// The error from the final block is kept as the cause.
+#[test]
+fn append_keeps_block_error() {
+    let key = KhPrf::keygen();
+    let mut ct = Vec::new();
+    KhPrf::encrypt(key.clone(), &mut &random_vec(31 * 3)[..], &mut ct).unwrap();
+
+    // Not a point: x^2 = (y^2 - 1) / (d y^2 + 1) has no root for y = 2.
+    let mut bad = [0u8; 32];
+    bad[0] = 2;
+    let n = ct.len();
+    ct[n - 32..].copy_from_slice(&bad);
+    let err = KhPrf::append(key, &mut &b"more"[..], &mut Cursor::new(ct)).unwrap_err();
+    match *err.kind() {
+        ErrorKind::InvalidBlock(3) => (),
+        ref e => panic!("unexpected error: {:?}", e),
+    }
+    assert!(err.iter().nth(1).is_some());
+}
@@ -0,0 +1,205 @@
+#![cfg(feature = "async")]
+
+extern crate recrypt;
+extern crate tokio;
+
+use recrypt::generic::{Kss, MerkleReCrypt, ReCrypt};
+use recrypt::{AsyncCipher, AsyncUpEnc, AsyncUpEncCtxtIndep, Cipher, KeyHomomorphicPrf, UpEnc, UpEncCtxtIndep};
+use recrypt::{KhPrf, LwrKhPrf, RingAes, RingAesStream, RingChaCha, RistrettoKhPrf};
+
+use std::future::Future;
+use std::io::Cursor;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, ReadBuf};
+
+mod helpers;
+
+use helpers::random_vec;
+
+fn block_on<F: Future>(f: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(f)
+}
+
+// A reader which returns at most 7 bytes at a time, and is not ready on
+// every other poll, so that reads end part-way through blocks and keys.
+struct Trickle {
+    data: Vec<u8>,
+    pos: usize,
+    ready: bool,
+}
+
+fn trickle(data: &[u8]) -> Trickle {
+    Trickle { data: data.to_vec(), pos: 0, ready: false }
+}
+
+impl AsyncRead for Trickle {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        this.ready = !this.ready;
+        if !this.ready {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        let n = *[7, buf.remaining(), this.data.len() - this.pos].iter().min().unwrap();
+        buf.put_slice(&this.data[this.pos..this.pos + n]);
+        this.pos += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+// The async versions should agree with the blocking ones in both directions.
+fn cipher_async_rt<C: AsyncCipher + 'static>() where C::K: Send {
+    let pt = random_vec(1000);
+    let key = C::keygen();
+
+    let ct = block_on(C::encrypt_async(key.clone(), trickle(&pt), Vec::new())).unwrap();
+    let mut out = Vec::new();
+    C::decrypt(key.clone(), &mut &ct[..], &mut out).unwrap();
+    assert_eq!(pt, out);
+
+    let mut ct = Vec::new();
+    C::encrypt(key.clone(), &mut &pt[..], &mut ct).unwrap();
+    let out = block_on(C::decrypt_async(key.clone(), trickle(&ct), Vec::new())).unwrap();
+    assert_eq!(pt, out);
+}
+
+// Malformed ciphertexts for the key-homomorphic PRFs, which are decrypted a
+// block at a time, fail with the same errors as for the blocking version.
+fn kh_prf_async_err<C: AsyncCipher + 'static>() where C::K: Send {
+    let key = C::keygen();
+    let mut ct = Vec::new();
+    C::encrypt(key.clone(), &mut &random_vec(1000)[..], &mut ct).unwrap();
+    let mut flipped = ct.clone();
+    flipped[100] ^= 0xff;
+    for bad in &[&ct[..ct.len() - 1], &ct[..5], &flipped[..]] {
+        let err = block_on(C::decrypt_async(key.clone(), trickle(bad), Vec::new())).err();
+        let sync_err = C::decrypt(key.clone(), &mut &bad[..], &mut Vec::new()).err();
+        assert_eq!(err.map(|e| e.to_string()), sync_err.map(|e| e.to_string()));
+    }
+}
+
+// Failures are the same errors as for the blocking version.
+fn cipher_async_err<C: AsyncCipher + 'static>() where C::K: Send {
+    let mut ct = Vec::new();
+    C::encrypt(C::keygen(), &mut &random_vec(1000)[..], &mut ct).unwrap();
+    let wrong = C::keygen();
+    let err = block_on(C::decrypt_async(wrong.clone(), Cursor::new(ct.clone()), Vec::new())).unwrap_err();
+    let sync_err = C::decrypt(wrong, &mut &ct[..], &mut Vec::new()).unwrap_err();
+    assert_eq!(err.to_string(), sync_err.to_string());
+}
+
+fn upenc_async_rt<S: AsyncUpEnc + 'static>() where S::K: Send {
+    let pt = random_vec(1000);
+    let k1 = S::keygen();
+    let k2 = S::keygen();
+
+    let (hdr, body) = block_on(S::encrypt_async(k1.clone(), trickle(&pt), Vec::new(), Vec::new())).unwrap();
+    let rk = block_on(S::rekeygen_async(k1.clone(), k2.clone(), Cursor::new(hdr.clone()), Vec::new())).unwrap();
+    let (hdr2, body2) = block_on(S::reencrypt_async(
+        trickle(&rk), trickle(&hdr), trickle(&body), Vec::new(), Vec::new())).unwrap();
+
+    let mut out = Vec::new();
+    S::decrypt(k2.clone(), &mut &hdr2[..], &mut &body2[..], &mut out).unwrap();
+    assert_eq!(pt, out);
+    let out = block_on(S::decrypt_async(k2.clone(), trickle(&hdr2), trickle(&body2), Vec::new())).unwrap();
+    assert_eq!(pt, out);
+
+    let err = block_on(S::decrypt_async(k1.clone(), Cursor::new(hdr2.clone()), Cursor::new(body2.clone()), Vec::new())).unwrap_err();
+    let sync_err = S::decrypt(k1, &mut &hdr2[..], &mut &body2[..], &mut Vec::new()).unwrap_err();
+    assert_eq!(err.to_string(), sync_err.to_string());
+
+    // A tampered body fails the integrity check in the same way. Two bytes
+    // in the middle of the data are flipped, since `LwrKhPrf` rounds away
+    // the bottom byte of each coefficient and `MerkleReCrypt` rebuilds its
+    // tree rather than reading the one at the end of the body.
+    let mut bad = body2.clone();
+    let mid = bad.len() / 2;
+    bad[mid] ^= 0x80;
+    bad[mid + 1] ^= 0x80;
+    let err = block_on(S::decrypt_async(k2.clone(), trickle(&hdr2), trickle(&bad), Vec::new())).unwrap_err();
+    let sync_err = S::decrypt(k2, &mut &hdr2[..], &mut &bad[..], &mut Vec::new()).unwrap_err();
+    assert_eq!(err.to_string(), sync_err.to_string());
+}
+
+#[test]
+fn cipher_async() {
+    cipher_async_rt::<RingAes>();
+    cipher_async_rt::<RingChaCha>();
+    cipher_async_rt::<KhPrf>();
+    cipher_async_rt::<RistrettoKhPrf>();
+    cipher_async_rt::<LwrKhPrf>();
+    cipher_async_err::<RingAes>();
+    cipher_async_err::<RingChaCha>();
+    kh_prf_async_err::<KhPrf>();
+    kh_prf_async_err::<RistrettoKhPrf>();
+    kh_prf_async_err::<LwrKhPrf>();
+}
+
+// Updating is deterministic, so the async update should match the blocking
+// one byte for byte.
+fn kh_prf_async_update<C: AsyncUpEncCtxtIndep + KeyHomomorphicPrf + 'static>() where C::K: Send {
+    let pt = random_vec(1000);
+    let k1 = C::keygen();
+    let k2 = C::keygen_next(&k1).unwrap();
+    let mut ct = Vec::new();
+    C::encrypt(k1.clone(), &mut &pt[..], &mut ct).unwrap();
+
+    let rk = block_on(C::rekeygen_async(k1, k2.clone(), Vec::new())).unwrap();
+    let new_ct = block_on(C::reencrypt_async(trickle(&rk), trickle(&ct), Vec::new())).unwrap();
+    let mut sync_ct = Vec::new();
+    C::reencrypt(&mut &rk[..], &mut &ct[..], &mut sync_ct).unwrap();
+    assert_eq!(new_ct, sync_ct);
+    let mut out = Vec::new();
+    C::decrypt(k2, &mut &new_ct[..], &mut out).unwrap();
+    assert_eq!(pt, out);
+}
+
+#[test]
+fn kh_prf_async_updates() {
+    kh_prf_async_update::<KhPrf>();
+    kh_prf_async_update::<RistrettoKhPrf>();
+    kh_prf_async_update::<LwrKhPrf>();
+}
+
+#[test]
+fn kss_async_rt() {
+    upenc_async_rt::<Kss<RingAes, RingAes>>();
+    upenc_async_rt::<Kss<RingAes, RingAesStream>>();
+}
+
+#[test]
+fn recrypt_async_rt() {
+    upenc_async_rt::<ReCrypt<RingAes, KhPrf>>();
+    upenc_async_rt::<ReCrypt<RingAes, RistrettoKhPrf>>();
+    upenc_async_rt::<ReCrypt<RingAes, LwrKhPrf>>();
+}
+
+#[test]
+fn merkle_async_rt() {
+    upenc_async_rt::<MerkleReCrypt<RingAes, KhPrf>>();
+}
+
+fn upenc_aad_async_rt<S: AsyncUpEnc + 'static>() where S::K: Send {
+    let pt = random_vec(1000);
+    let k1 = S::keygen();
+    let k2 = S::keygen();
+    let aad = b"tenant/a.txt".to_vec();
+
+    let (hdr, body) = block_on(S::encrypt_aad_async(k1.clone(), aad.clone(), trickle(&pt), Vec::new(), Vec::new())).unwrap();
+    let rk = block_on(S::rekeygen_aad_async(k1.clone(), k2.clone(), aad.clone(), trickle(&hdr), Vec::new())).unwrap();
+    let (hdr2, body2) = block_on(S::reencrypt_aad_async(
+        trickle(&rk), aad.clone(), trickle(&hdr), trickle(&body), Vec::new(), Vec::new())).unwrap();
+
+    let out = block_on(S::decrypt_aad_async(k2.clone(), aad, trickle(&hdr2), trickle(&body2), Vec::new())).unwrap();
+    assert_eq!(pt, out);
+    let res = block_on(S::decrypt_aad_async(k2, b"tenant/b.txt".to_vec(), trickle(&hdr2), trickle(&body2), Vec::new()));
+    assert!(res.is_err());
+}
+
+#[test]
+fn aad_async_rt() {
+    upenc_aad_async_rt::<Kss<RingAes, RingAes>>();
+    upenc_aad_async_rt::<ReCrypt<RingAes, KhPrf>>();
+}
@@ -0,0 +1,131 @@
+extern crate recrypt;
+
+use recrypt::container::{read_suite, MAGIC};
+use recrypt::errors::ErrorKind;
+use recrypt::generic::{KemDem, Kss, MerkleReCrypt, Naive, ReCrypt};
+use recrypt::{Container, SuiteId, UpEnc};
+use recrypt::{KhPrf, LwrKhPrf, RingAes, RingChaCha};
+
+use std::io::Cursor;
+
+mod helpers;
+
+use helpers::random_vec;
+
+// Encrypts, rotates and decrypts through containers, with bodies spanning
+// none, one and several chunks.
+fn container_rt<S: Container>() {
+    for &size in &[0, 1000, 200000] {
+        let pt = random_vec(size);
+        let k1 = S::keygen();
+        let k2 = S::keygen();
+
+        let mut ct1 = Vec::new();
+        S::encrypt_container(k1.clone(), &mut &pt[..], &mut ct1).unwrap();
+        assert_eq!(read_suite(&mut &ct1[..]).unwrap(), S::suite_id());
+
+        let mut out = Vec::new();
+        S::decrypt_container(k1.clone(), &mut Cursor::new(&ct1), &mut out).unwrap();
+        assert_eq!(pt, out);
+
+        let mut rk = Vec::new();
+        S::rekeygen_container(k1.clone(), k2.clone(), &mut Cursor::new(&ct1), &mut rk).unwrap();
+        let mut ct2 = Vec::new();
+        S::reencrypt_container(&mut &rk[..], &mut Cursor::new(&ct1), &mut ct2).unwrap();
+
+        let mut out = Vec::new();
+        S::decrypt_container(k2, &mut Cursor::new(&ct2), &mut out).unwrap();
+        assert_eq!(pt, out);
+    }
+}
+
+#[test]
+fn naive_container() {
+    container_rt::<Naive<RingAes>>();
+    container_rt::<KemDem<RingAes>>();
+}
+
+#[test]
+fn kss_container() {
+    container_rt::<Kss<RingAes, RingAes>>();
+}
+
+#[test]
+fn recrypt_container() {
+    container_rt::<ReCrypt<RingAes, KhPrf>>();
+    container_rt::<ReCrypt<RingChaCha, LwrKhPrf>>();
+    container_rt::<MerkleReCrypt<RingAes, KhPrf>>();
+}
+
+#[test]
+fn suite_ids_differ() {
+    let ids = vec![
+        Naive::<RingAes>::suite_id(),
+        Naive::<RingChaCha>::suite_id(),
+        KemDem::<RingAes>::suite_id(),
+        Kss::<RingAes, RingAes>::suite_id(),
+        ReCrypt::<RingAes, KhPrf>::suite_id(),
+        ReCrypt::<RingAes, LwrKhPrf>::suite_id(),
+        MerkleReCrypt::<RingAes, KhPrf>::suite_id(),
+    ];
+    for (i, a) in ids.iter().enumerate() {
+        for b in &ids[i + 1..] {
+            assert_ne!(a, b);
+        }
+    }
+}
+
+fn encrypted<S: Container>(size: usize) -> (S::K, Vec<u8>) {
+    let key = S::keygen();
+    let mut ct = Vec::new();
+    S::encrypt_container(key.clone(), &mut &random_vec(size)[..], &mut ct).unwrap();
+    (key, ct)
+}
+
+#[test]
+fn container_suite_mismatch() {
+    type S = ReCrypt<RingAes, KhPrf>;
+    type T = ReCrypt<RingChaCha, KhPrf>;
+    let (_, ct) = encrypted::<S>(1000);
+    let err = T::decrypt_container(T::keygen(), &mut Cursor::new(&ct), &mut Vec::new()).unwrap_err();
+    match *err.kind() {
+        ErrorKind::SuiteMismatch(ref suite) => assert_eq!(suite, &S::suite_id()),
+        ref e => panic!("unexpected error {:?}", e),
+    }
+}
+
+#[test]
+fn container_malformed() {
+    type S = ReCrypt<RingAes, KhPrf>;
+    let (key, ct) = encrypted::<S>(100000);
+    let decrypt = |ct: &[u8]| S::decrypt_container(key.clone(), &mut Cursor::new(ct), &mut Vec::new()).unwrap_err();
+
+    let mut bad = ct.clone();
+    bad[0] ^= 1;
+    match *decrypt(&bad).kind() {
+        ErrorKind::InvalidContainer(_) => {},
+        ref e => panic!("unexpected error {:?}", e),
+    }
+
+    let mut bad = ct.clone();
+    bad[MAGIC.len()] = 0xff;
+    match *decrypt(&bad).kind() {
+        ErrorKind::UnsupportedContainerVersion(0xff) => {},
+        ref e => panic!("unexpected error {:?}", e),
+    }
+
+    // Cut off in the preamble, the body and the header.
+    for &len in &[4, 50000, ct.len() - 1] {
+        match *decrypt(&ct[..len]).kind() {
+            ErrorKind::TruncatedCiphertext(_) => {},
+            ref e => panic!("unexpected error {:?} at {}", e, len),
+        }
+    }
+
+    let mut bad = ct.clone();
+    bad.push(0);
+    match *decrypt(&bad).kind() {
+        ErrorKind::InvalidContainer(_) => {},
+        ref e => panic!("unexpected error {:?}", e),
+    }
+}
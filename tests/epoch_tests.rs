@@ -0,0 +1,105 @@
+extern crate recrypt;
+
+use recrypt::generic::ReCrypt;
+use recrypt::{Cipher, KeyEpochs, KeyHomomorphicPrf, UpEnc, UpEncCtxtIndep};
+use recrypt::{KhPrf, LwrKhPrf, RingAes};
+
+use std::io::Cursor;
+
+mod helpers;
+
+use helpers::random_vec;
+
+// Rotates a ciphertext of 20 blocks through three keys a range at a time,
+// decrypting with the keys for each epoch along the way.
+fn rotate_ranges<C: KeyHomomorphicPrf>() {
+    let pt = random_vec(20 * C::PT_BLOCK_SIZE - 1);
+    let mut keys = vec![C::keygen()];
+    for i in 0..2 {
+        let next = C::keygen_next(&keys[i]).unwrap();
+        keys.push(next);
+    }
+    let mut rk1 = Vec::new();
+    let mut rk2 = Vec::new();
+    C::rekeygen(keys[0].clone(), keys[1].clone(), &mut rk1).unwrap();
+    C::rekeygen(keys[1].clone(), keys[2].clone(), &mut rk2).unwrap();
+
+    let mut ct = Vec::new();
+    C::encrypt(keys[0].clone(), &mut &pt[..], &mut ct).unwrap();
+    let mut file = Cursor::new(ct);
+    let mut epochs = KeyEpochs::new(0);
+    let check = |file: &Cursor<Vec<u8>>, epochs: &KeyEpochs| {
+        let mut out = Vec::new();
+        C::decrypt_epochs(&keys, epochs, &mut &file.get_ref()[..], &mut out).unwrap();
+        assert_eq!(pt, out);
+    };
+
+    for &(ref blocks, rk) in &[(5..12, &rk1), (12..20, &rk1), (6..9, &rk2), (0..5, &rk1)] {
+        file.set_position(0);
+        C::reencrypt_range(&mut &rk[..], &mut file, &mut Cursor::new(Vec::new()), &mut epochs, blocks.clone()).unwrap();
+        check(&file, &epochs);
+    }
+    assert_eq!(epochs.epochs_in(0..20), vec![1, 2]);
+
+    // Blocks 8..10 span epochs 1 and 2, so no single token applies.
+    file.set_position(0);
+    assert!(C::reencrypt_range(&mut &rk2[..], &mut file, &mut Cursor::new(Vec::new()), &mut epochs, 8..10).is_err());
+    // Past the end of the ciphertext.
+    file.set_position(0);
+    assert!(C::reencrypt_range(&mut &rk2[..], &mut file, &mut Cursor::new(Vec::new()), &mut epochs, 15..21).is_err());
+
+    for blocks in vec![0..6, 9..20] {
+        file.set_position(0);
+        C::reencrypt_range(&mut &rk2[..], &mut file, &mut Cursor::new(Vec::new()), &mut epochs, blocks).unwrap();
+    }
+    let mut out = Vec::new();
+    C::decrypt(keys[2].clone(), &mut &file.get_ref()[..], &mut out).unwrap();
+    assert_eq!(pt, out);
+}
+
+#[test]
+fn kh_prf_rotate_ranges() {
+    rotate_ranges::<KhPrf>();
+}
+
+#[test]
+fn lwr_kh_prf_rotate_ranges() {
+    rotate_ranges::<LwrKhPrf>();
+}
+
+#[test]
+fn recrypt_rotate_ranges() {
+    type Scheme = ReCrypt<RingAes, KhPrf>;
+    let pt = random_vec(31 * 30);
+    let k1 = Scheme::keygen();
+    let k2 = Scheme::keygen();
+    let mut hdr = Vec::new();
+    let mut body = Vec::new();
+    Scheme::encrypt(k1.clone(), &mut &pt[..], &mut hdr, &mut body).unwrap();
+    let mut rk = Vec::new();
+    Scheme::rekeygen(k1.clone(), k2.clone(), &mut &hdr[..], &mut rk).unwrap();
+
+    let mut file = Cursor::new(body);
+    let mut epochs = KeyEpochs::new(0);
+    Scheme::reencrypt_range(&mut &rk[..], &mut file, &mut Cursor::new(Vec::new()), &mut epochs, 0..10).unwrap();
+    let mut out = Vec::new();
+    Scheme::decrypt_epochs(k1.clone(), None, &mut &rk[..], &mut &hdr[..], &mut &file.get_ref()[..], &epochs, &mut out).unwrap();
+    assert_eq!(pt, out);
+
+    // Not finished yet, and blocks cannot be updated twice.
+    file.set_position(0);
+    assert!(Scheme::finish_reencrypt_range(&mut &rk[..], None, &mut &hdr[..], &mut file, &mut epochs, &mut Vec::new()).is_err());
+    file.set_position(0);
+    assert!(Scheme::reencrypt_range(&mut &rk[..], &mut file, &mut Cursor::new(Vec::new()), &mut epochs, 5..15).is_err());
+
+    file.set_position(0);
+    Scheme::reencrypt_range(&mut &rk[..], &mut file, &mut Cursor::new(Vec::new()), &mut epochs, 10..31).unwrap();
+    file.set_position(0);
+    let mut hdr2 = Vec::new();
+    Scheme::finish_reencrypt_range(&mut &rk[..], None, &mut &hdr[..], &mut file, &mut epochs, &mut hdr2).unwrap();
+    assert_eq!(epochs, KeyEpochs::new(0));
+
+    out.clear();
+    Scheme::decrypt(k2, &mut &hdr2[..], &mut &file.get_ref()[..], &mut out).unwrap();
+    assert_eq!(pt, out);
+}
@@ -7,6 +7,7 @@ use std::fs::{metadata, remove_dir_all,create_dir,File, OpenOptions};
 use std::path::{Path,PathBuf};
 use std::io::{Write,BufWriter};
 use std::collections::HashSet;
+use std::ops::{Add, Sub};
 
 use std::sync::{Once, ONCE_INIT};
 
@@ -65,6 +66,42 @@ pub fn random_vec(n: usize) -> Vec<u8> {
     v
 }
 
+// Decodes a hex string, for test vectors.
+pub fn hex(s: &str) -> Vec<u8> {
+    (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+}
+
+// Serialises a key with `write_key`.
+pub fn key_bytes<K: Key>(key: &K) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    key.write_key(&mut bytes).unwrap();
+    bytes
+}
+
+// Round trips plaintexts of several sizes through an AEAD which adds
+// `overhead` bytes, and checks that another key does not decrypt them.
+pub fn aead_rt<C: Cipher>(overhead: usize) {
+    for &size in &[0, 1, 1000, 100000] {
+        let pt = random_vec(size);
+        let key = C::keygen();
+        let mut ct = Vec::new();
+        C::encrypt(key.clone(), &mut &pt[..], &mut ct).unwrap();
+        assert_eq!(ct.len(), size + overhead);
+
+        let mut out = Vec::new();
+        C::decrypt(key.clone(), &mut &ct[..], &mut out).unwrap();
+        assert_eq!(pt, out);
+        assert!(C::decrypt(C::keygen(), &mut &ct[..], &mut Vec::new()).is_err());
+    }
+}
+
+// Checks that adding and then subtracting a key, as the header ciphers of
+// `Kss` do with XOR, gives back the original key.
+pub fn key_xor<C: Cipher>() where for<'a> &'a C::K: Add<Output=C::K> + Sub<Output=C::K> {
+    let (a, b) = (C::keygen(), C::keygen());
+    assert_eq!(&(&a + &b) - &b, a);
+}
+
 
 // Runs: `diff f1 f2` and reports the result.
 pub fn diff_files(f1: &Path, f2: &Path) -> bool {
@@ -262,7 +299,7 @@ pub fn read_write_key<Scheme: UpEnc>(k: Scheme::K) {
     let mut ofile = open_file(&kfile);
     k.write_key( &mut ofile).unwrap();
     let mut ifile = open_file(&kfile);
-    let _k = Scheme::K::read_key(&mut ifile).unwrap();
+    let _k = Scheme::K::read_key_file(&mut ifile).unwrap();
     assert_eq!(k, _k);
 }
 
@@ -317,7 +354,7 @@ pub fn update_tokenfile_sane<Scheme: UpEncCtxtIndep>() {
 
     // Create files for the new keyfile and token file.
     let mut tokenfile = File::create(&get_tmp_fname("upenc")).unwrap();
-    let k1 = Scheme::K::read_key(&mut kfile).unwrap();
+    let k1 = Scheme::K::read_key_file(&mut kfile).unwrap();
     let k2 = Scheme::keygen();
     // Create an update tokenfile.
     Scheme::rekeygen(k1, k2, &mut tokenfile).unwrap();
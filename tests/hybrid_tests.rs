@@ -1,46 +1,109 @@
 extern crate recrypt;
 
 // use recrypt::generic::{KemDem, Naive};
-use recrypt::generic::{Kss, ReCrypt};
-use recrypt::RingAes;
-use recrypt::KhPrf;
+use recrypt::errors::ErrorKind;
+use recrypt::generic::{Kss, MerkleReCrypt, ReCrypt};
+use recrypt::{Cipher, KeyHomomorphicPrf, RingAes, UpEnc, UpEncCtxtIndep};
+use recrypt::{KhPrf, LwrKhPrf, RistrettoKhPrf};
 
 mod helpers;
 
+use helpers::random_vec;
+
+// `kh_prf_lwr::MAX_UPDATES`
+const LWR_MAX_UPDATES: usize = 128;
+
 type RegCipher = RingAes;
 
 #[test]
 fn keygen_sane() {
     helpers::keygen_sane::<Kss<RegCipher, RegCipher>>();
     helpers::keygen_sane::<ReCrypt<RegCipher, KhPrf>>();
+    helpers::keygen_sane::<ReCrypt<RegCipher, RistrettoKhPrf>>();
+    helpers::keygen_sane::<ReCrypt<RegCipher, LwrKhPrf>>();
 }
 
 #[test]
 fn write_keyfile_sane() {
    helpers::write_keyfile_sane::<Kss<RegCipher, RegCipher>>();
    helpers::write_keyfile_sane::<ReCrypt<RegCipher, KhPrf>>();
+   helpers::write_keyfile_sane::<ReCrypt<RegCipher, RistrettoKhPrf>>();
+   helpers::write_keyfile_sane::<ReCrypt<RegCipher, LwrKhPrf>>();
 }
 
 #[test]
 fn keyfile_rt() {
     helpers::keyfile_rt::<Kss<RegCipher, RegCipher>>();
     helpers::keyfile_rt::<ReCrypt<RegCipher, KhPrf>>();
+    helpers::keyfile_rt::<ReCrypt<RegCipher, RistrettoKhPrf>>();
+    helpers::keyfile_rt::<ReCrypt<RegCipher, LwrKhPrf>>();
 }
 
 #[test]
 fn filecrypt_rt() {
     helpers::filecrypt_rt::<Kss<RegCipher, RegCipher>>(32);
     helpers::filecrypt_rt::<ReCrypt<RegCipher, KhPrf>>(31);
+    helpers::filecrypt_rt::<ReCrypt<RegCipher, RistrettoKhPrf>>(30);
+    helpers::filecrypt_rt::<ReCrypt<RegCipher, LwrKhPrf>>(32);
+    helpers::filecrypt_rt::<MerkleReCrypt<RegCipher, KhPrf>>(31);
 }
 
 #[test]
 fn encrypt_update_once() {
     helpers::encrypt_update_once::<Kss<RegCipher, RegCipher>>(32);
     helpers::encrypt_update_once::<ReCrypt<RegCipher, KhPrf>>(31);
+    helpers::encrypt_update_once::<ReCrypt<RegCipher, RistrettoKhPrf>>(30);
+    helpers::encrypt_update_once::<ReCrypt<RegCipher, LwrKhPrf>>(32);
+    helpers::encrypt_update_once::<MerkleReCrypt<RegCipher, KhPrf>>(31);
 }
 
 #[test]
 fn encrypt_update_many() {
     helpers::encrypt_update_many::<Kss<RegCipher, RegCipher>>(32);
     helpers::encrypt_update_many::<ReCrypt<RegCipher, KhPrf>>(32);
+    helpers::encrypt_update_many::<ReCrypt<RegCipher, RistrettoKhPrf>>(30);
+    helpers::encrypt_update_many::<ReCrypt<RegCipher, LwrKhPrf>>(32);
+}
+
+fn too_many_updates<T: std::fmt::Debug>(res: recrypt::errors::Result<T>) {
+    match *res.unwrap_err().kind() {
+        ErrorKind::TooManyUpdates(LWR_MAX_UPDATES) => {},
+        ref e => panic!("unexpected error {:?}", e),
+    }
+}
+
+// A ciphertext still decrypts after the last update it can take, and the
+// one after that is refused.
+#[test]
+fn lwr_update_limit() {
+    type Scheme = ReCrypt<RegCipher, LwrKhPrf>;
+    let pt = random_vec(100);
+    let mut key = Scheme::keygen();
+    let (mut hdr, mut body) = (Vec::new(), Vec::new());
+    Scheme::encrypt(key.clone(), &mut &pt[..], &mut hdr, &mut body).unwrap();
+    for _ in 0..LWR_MAX_UPDATES {
+        let new_key = Scheme::keygen();
+        let mut rk = Vec::new();
+        Scheme::rekeygen(key, new_key.clone(), &mut &hdr[..], &mut rk).unwrap();
+        let (mut hdr2, mut body2) = (Vec::new(), Vec::new());
+        Scheme::reencrypt(&mut &rk[..], &mut &hdr[..], &mut &body[..], &mut hdr2, &mut body2).unwrap();
+        hdr = hdr2;
+        body = body2;
+        key = new_key;
+    }
+    let mut out = Vec::new();
+    Scheme::decrypt(key.clone(), &mut &hdr[..], &mut &body[..], &mut out).unwrap();
+    assert_eq!(pt, out);
+    too_many_updates(Scheme::rekeygen(key, Scheme::keygen(), &mut &hdr[..], &mut Vec::new()));
+
+    // Used directly, the count is carried from key to key.
+    let mut key = LwrKhPrf::keygen();
+    for _ in 0..LWR_MAX_UPDATES {
+        let next = LwrKhPrf::keygen_next(&key).unwrap();
+        LwrKhPrf::rekeygen(key, next.clone(), &mut Vec::new()).unwrap();
+        key = next;
+    }
+    too_many_updates(LwrKhPrf::keygen_next(&key));
+    let fresh = LwrKhPrf::keygen();
+    assert!(LwrKhPrf::rekeygen(key, fresh, &mut Vec::new()).is_err());
 }
@@ -0,0 +1,211 @@
+extern crate recrypt;
+
+use recrypt::generic::ReCrypt;
+use recrypt::{Cipher, KeyEpochs, KeyHomomorphicPrf, UpEnc, UpEncCtxtIndep};
+use recrypt::{KhPrf, LwrKhPrf, RingAes};
+
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+mod helpers;
+
+use helpers::random_vec;
+
+// Simulates a crash by failing every write after the first `budget` bytes,
+// so the last write may be torn part-way through.
+struct Crashing<'a> {
+    inner: &'a mut Cursor<Vec<u8>>,
+    budget: usize,
+}
+
+impl<'a> Read for Crashing<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<'a> Write for Crashing<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.budget == 0 {
+            return Err(io::Error::new(io::ErrorKind::Other, "crashed"));
+        }
+        let n = self.inner.write(&buf[..buf.len().min(self.budget)])?;
+        self.budget -= n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<'a> Seek for Crashing<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+fn crashing(inner: &mut Cursor<Vec<u8>>, budget: usize) -> Crashing {
+    Crashing { inner: inner, budget: budget }
+}
+
+// Rotates a ciphertext in place, first letting the file (or the journal) crash
+// after `budget` bytes, then resuming.
+fn rotate_after_crash<C: KeyHomomorphicPrf>(n_blocks: usize, budget: usize, crash_journal: bool) {
+    let pt = random_vec(n_blocks * (C::PT_BLOCK_SIZE - 1));
+    let k1 = C::keygen();
+    let k2 = C::keygen_next(&k1).unwrap();
+    let mut ct = Vec::new();
+    C::encrypt(k1.clone(), &mut &pt[..], &mut ct).unwrap();
+    let mut rk = Vec::new();
+    C::rekeygen(k1, k2.clone(), &mut rk).unwrap();
+
+    let mut file = Cursor::new(ct);
+    let mut journal = Cursor::new(Vec::new());
+    let res = if crash_journal {
+        C::reencrypt_in_place(&mut &rk[..], &mut file, &mut crashing(&mut journal, budget))
+    } else {
+        C::reencrypt_in_place(&mut &rk[..], &mut crashing(&mut file, budget), &mut journal)
+    };
+    assert!(res.is_err());
+
+    file.set_position(0);
+    C::reencrypt_in_place(&mut &rk[..], &mut file, &mut journal).unwrap();
+    let mut out = Vec::new();
+    C::decrypt(k2.clone(), &mut &file.get_ref()[..], &mut out).unwrap();
+    assert_eq!(pt, out);
+
+    // The finished update is not applied a second time.
+    let rotated = file.get_ref().clone();
+    file.set_position(0);
+    C::reencrypt_in_place(&mut &rk[..], &mut file, &mut journal).unwrap();
+    assert_eq!(&rotated, file.get_ref());
+}
+
+// Rotates a range of blocks in place, letting the file crash after `budget`
+// bytes, then resuming. The rest of the blocks are rotated afterwards.
+fn rotate_range_after_crash<C: KeyHomomorphicPrf>(n_blocks: u64, range: Range<u64>, budget: usize) {
+    let pt = random_vec(n_blocks as usize * C::PT_BLOCK_SIZE - 1);
+    let k1 = C::keygen();
+    let k2 = C::keygen_next(&k1).unwrap();
+    let mut ct = Vec::new();
+    C::encrypt(k1.clone(), &mut &pt[..], &mut ct).unwrap();
+    let mut rk = Vec::new();
+    C::rekeygen(k1, k2.clone(), &mut rk).unwrap();
+
+    let mut file = Cursor::new(ct);
+    let mut journal = Cursor::new(Vec::new());
+    let mut epochs = KeyEpochs::new(0);
+    let res = C::reencrypt_range(&mut &rk[..], &mut crashing(&mut file, budget), &mut journal, &mut epochs, range.clone());
+    assert!(res.is_err());
+    assert_eq!(epochs, KeyEpochs::new(0));
+
+    file.set_position(0);
+    C::reencrypt_range(&mut &rk[..], &mut file, &mut journal, &mut epochs, range.clone()).unwrap();
+
+    // If the epochs were lost after the update finished, running it again
+    // only records them.
+    let rotated = file.get_ref().clone();
+    let mut lost = KeyEpochs::new(0);
+    file.set_position(0);
+    C::reencrypt_range(&mut &rk[..], &mut file, &mut journal, &mut lost, range.clone()).unwrap();
+    assert_eq!(&rotated, file.get_ref());
+    assert_eq!(epochs, lost);
+
+    for rest in vec![0..range.start, range.end..n_blocks] {
+        file.set_position(0);
+        C::reencrypt_range(&mut &rk[..], &mut file, &mut Cursor::new(Vec::new()), &mut epochs, rest).unwrap();
+    }
+    let mut out = Vec::new();
+    C::decrypt(k2, &mut &file.get_ref()[..], &mut out).unwrap();
+    assert_eq!(pt, out);
+}
+
+#[test]
+fn kh_prf_range_resumes_after_torn_write() {
+    // Crash part-way through the second batch of 1024 blocks of the range.
+    rotate_range_after_crash::<KhPrf>(2200, 100..2150, 32 * 1024 + 500);
+}
+
+#[test]
+fn lwr_kh_prf_range_resumes_after_torn_write() {
+    rotate_range_after_crash::<LwrKhPrf>(1500, 10..1400, 64 * 1024 + 64 * 3);
+}
+
+#[test]
+fn kh_prf_in_place_matches_reencrypt() {
+    let pt = random_vec(500);
+    let k1 = KhPrf::keygen();
+    let k2 = KhPrf::keygen();
+    let mut ct = Vec::new();
+    KhPrf::encrypt(k1.clone(), &mut &pt[..], &mut ct).unwrap();
+    let mut rk = Vec::new();
+    KhPrf::rekeygen(k1, k2, &mut rk).unwrap();
+
+    let mut expected = Vec::new();
+    KhPrf::reencrypt(&mut &rk[..], &mut &ct[..], &mut expected).unwrap();
+    let mut file = Cursor::new(ct);
+    KhPrf::reencrypt_in_place(&mut &rk[..], &mut file, &mut Cursor::new(Vec::new())).unwrap();
+    assert_eq!(&expected, file.get_ref());
+}
+
+#[test]
+fn kh_prf_in_place_resumes_after_torn_write() {
+    // Crash part-way through the second batch of 1024 blocks.
+    rotate_after_crash::<KhPrf>(2100, 32 * 1024 + 500, false);
+}
+
+#[test]
+fn kh_prf_in_place_resumes_after_torn_journal() {
+    // Crash while journaling the second batch.
+    rotate_after_crash::<KhPrf>(2100, 32 * 1024 + 100, true);
+}
+
+#[test]
+fn lwr_kh_prf_in_place_resumes_after_torn_write() {
+    rotate_after_crash::<LwrKhPrf>(1500, 64 * 1024 + 64 * 3, false);
+}
+
+#[test]
+fn in_place_rejects_other_tokens_journal() {
+    let pt = random_vec(31 * 1500);
+    let k1 = KhPrf::keygen();
+    let mut ct = Vec::new();
+    KhPrf::encrypt(k1.clone(), &mut &pt[..], &mut ct).unwrap();
+    let mut rk1 = Vec::new();
+    let mut rk2 = Vec::new();
+    KhPrf::rekeygen(k1.clone(), KhPrf::keygen(), &mut rk1).unwrap();
+    KhPrf::rekeygen(k1, KhPrf::keygen(), &mut rk2).unwrap();
+
+    let mut file = Cursor::new(ct);
+    let mut journal = Cursor::new(Vec::new());
+    assert!(KhPrf::reencrypt_in_place(&mut &rk1[..], &mut crashing(&mut file, 32 * 1024 + 10), &mut journal).is_err());
+    file.set_position(0);
+    assert!(KhPrf::reencrypt_in_place(&mut &rk2[..], &mut file, &mut journal).is_err());
+}
+
+#[test]
+fn recrypt_in_place_resumes_after_torn_write() {
+    type Scheme = ReCrypt<RingAes, KhPrf>;
+    let pt = random_vec(31 * 1500);
+    let k1 = Scheme::keygen();
+    let k2 = Scheme::keygen();
+    let mut hdr = Vec::new();
+    let mut body = Vec::new();
+    Scheme::encrypt(k1.clone(), &mut &pt[..], &mut hdr, &mut body).unwrap();
+    let mut rk = Vec::new();
+    Scheme::rekeygen(k1, k2.clone(), &mut &hdr[..], &mut rk).unwrap();
+
+    let mut file = Cursor::new(body);
+    let mut journal = Cursor::new(Vec::new());
+    let res = Scheme::reencrypt_in_place(&mut &rk[..], None, &mut &hdr[..], &mut crashing(&mut file, 32 * 1024 + 7),
+                                         &mut journal, &mut Vec::new());
+    assert!(res.is_err());
+
+    file.set_position(0);
+    let mut hdr2 = Vec::new();
+    Scheme::reencrypt_in_place(&mut &rk[..], None, &mut &hdr[..], &mut file, &mut journal, &mut hdr2).unwrap();
+    let mut out = Vec::new();
+    Scheme::decrypt(k2, &mut &hdr2[..], &mut &file.get_ref()[..], &mut out).unwrap();
+    assert_eq!(pt, out);
+}
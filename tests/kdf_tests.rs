@@ -0,0 +1,140 @@
+extern crate recrypt;
+
+use recrypt::container::{read_kdf_params, read_suite};
+use recrypt::errors::ErrorKind;
+use recrypt::generic::{Kss, ReCrypt};
+use recrypt::{Cipher, Container, Kdf, KdfParams, UpEnc};
+use recrypt::{KhPrf, RingAes, RingAes256, XChaCha};
+
+use std::io::Cursor;
+
+mod helpers;
+
+use helpers::{hex, key_bytes, random_vec};
+
+// Cheap enough for tests; real keys should use `Kdf::default()`.
+const ARGON2: Kdf = Kdf::Argon2id { memory_kib: 256, iterations: 1, parallelism: 1 };
+
+fn assert_invalid_kdf<T: std::fmt::Debug>(res: recrypt::errors::Result<T>) {
+    match *res.unwrap_err().kind() {
+        ErrorKind::InvalidKdf(_) => {},
+        ref e => panic!("unexpected error {:?}", e),
+    }
+}
+
+// The PBKDF2-HMAC-SHA256 vector with a 36-byte salt, truncated to one block.
+#[test]
+fn pbkdf2_test_vector() {
+    let salt = b"saltSALTsaltSALTsaltSALTsaltSALTsalt".to_vec();
+    let params = KdfParams { kdf: Kdf::Pbkdf2Sha256 { iterations: 4096 }, salt: salt.clone() };
+    let key: <RingAes256 as Cipher>::K = params.derive_key(b"passwordPASSWORDpassword").unwrap();
+    assert_eq!(key_bytes(&key), hex("348c89dbcbd32b2f32d814b8116e84cf2b17347ebc1800181c4e2a1fb8dd53e1"));
+
+    let params = KdfParams { kdf: Kdf::Pbkdf2Sha256 { iterations: 0 }, salt: salt };
+    assert_invalid_kdf(params.derive_key::<<RingAes as Cipher>::K>(b"password"));
+}
+
+// Short salts and excessive costs are refused when deriving, writing and
+// reading, so a container cannot ask for an arbitrarily expensive derivation.
+#[test]
+fn kdf_params_limits() {
+    let salt = KdfParams::new(Kdf::default()).salt;
+    let bad = vec![
+        KdfParams { kdf: Kdf::Pbkdf2Sha256 { iterations: 4096 }, salt: b"salt".to_vec() },
+        KdfParams { kdf: ARGON2, salt: salt[..recrypt::kdf::SALT_SIZE - 1].to_vec() },
+        KdfParams { kdf: Kdf::Pbkdf2Sha256 { iterations: recrypt::kdf::MAX_PBKDF2_ITERATIONS + 1 }, salt: salt.clone() },
+        KdfParams { kdf: Kdf::Argon2id { memory_kib: u32::max_value(), iterations: 1, parallelism: 1 }, salt: salt.clone() },
+        KdfParams { kdf: Kdf::Argon2id { memory_kib: 256, iterations: u32::max_value(), parallelism: 1 }, salt: salt.clone() },
+        KdfParams { kdf: Kdf::Argon2id { memory_kib: 256, iterations: 1, parallelism: 1000 }, salt: salt.clone() },
+    ];
+    for params in bad {
+        assert_invalid_kdf(params.derive_key::<<XChaCha as Cipher>::K>(b"correct horse"));
+        assert_invalid_kdf(params.write(&mut Vec::new()));
+
+        // As a container from elsewhere would encode them.
+        let (id, costs) = match params.kdf {
+            Kdf::Pbkdf2Sha256 { iterations } => (1, vec![iterations]),
+            Kdf::Argon2id { memory_kib, iterations, parallelism } => (2, vec![memory_kib, iterations, parallelism]),
+        };
+        let mut bytes = vec![id];
+        for cost in costs {
+            bytes.extend_from_slice(&[(cost >> 24) as u8, (cost >> 16) as u8, (cost >> 8) as u8, cost as u8]);
+        }
+        bytes.push(params.salt.len() as u8);
+        bytes.extend_from_slice(&params.salt);
+        assert_invalid_kdf(KdfParams::read(&mut &bytes[..]));
+    }
+}
+
+#[test]
+fn argon2_derivation() {
+    type K = <XChaCha as Cipher>::K;
+    let params = KdfParams::new(ARGON2);
+    let key: K = params.derive_key(b"correct horse").unwrap();
+    assert_eq!(key, params.derive_key::<K>(b"correct horse").unwrap());
+    assert!(key != params.derive_key::<K>(b"battery staple").unwrap());
+    assert!(key != KdfParams::new(ARGON2).derive_key::<K>(b"correct horse").unwrap());
+
+    let bad = KdfParams { kdf: Kdf::Argon2id { memory_kib: 1, iterations: 1, parallelism: 1 }, salt: params.salt.clone() };
+    assert!(bad.derive_key::<K>(b"correct horse").is_err());
+}
+
+#[test]
+fn kdf_params_rt() {
+    for kdf in &[Kdf::Pbkdf2Sha256 { iterations: 600000 }, Kdf::default()] {
+        let params = KdfParams::new(*kdf);
+        let mut bytes = Vec::new();
+        params.write(&mut bytes).unwrap();
+        assert_eq!(KdfParams::read(&mut &bytes[..]).unwrap(), params);
+        assert!(KdfParams::read(&mut &bytes[..bytes.len() - 1]).is_err());
+    }
+}
+
+// Encrypts under one passphrase and rotates to another, recording the
+// parameters for each in the container.
+fn password_rt<S: Container>() where S::K: recrypt::kdf::DerivableKey {
+    let pt = random_vec(1000);
+    let params1 = KdfParams::new(ARGON2);
+    let mut ct1 = Vec::new();
+    S::encrypt_container_with_password(b"old passphrase", &params1, &mut &pt[..], &mut ct1).unwrap();
+    assert_eq!(read_suite(&mut &ct1[..]).unwrap(), S::suite_id());
+    assert_eq!(read_kdf_params(&mut &ct1[..]).unwrap(), Some(params1.clone()));
+
+    let mut out = Vec::new();
+    S::decrypt_container_with_password(b"old passphrase", &mut Cursor::new(&ct1), &mut out).unwrap();
+    assert_eq!(pt, out);
+    assert!(S::decrypt_container_with_password(b"wrong passphrase", &mut Cursor::new(&ct1), &mut Vec::new()).is_err());
+
+    let params2 = KdfParams::new(Kdf::Pbkdf2Sha256 { iterations: 1000 });
+    let k1 = params1.derive_key(b"old passphrase").unwrap();
+    let k2 = params2.derive_key(b"new passphrase").unwrap();
+    let mut rk = Vec::new();
+    S::rekeygen_container(k1, k2, &mut Cursor::new(&ct1), &mut rk).unwrap();
+    let mut ct2 = Vec::new();
+    S::reencrypt_container_with_kdf(&mut &rk[..], &params2, &mut Cursor::new(&ct1), &mut ct2).unwrap();
+    assert_eq!(read_kdf_params(&mut &ct2[..]).unwrap(), Some(params2));
+
+    let mut out = Vec::new();
+    S::decrypt_container_with_password(b"new passphrase", &mut Cursor::new(&ct2), &mut out).unwrap();
+    assert_eq!(pt, out);
+}
+
+#[test]
+fn password_containers() {
+    password_rt::<ReCrypt<RingAes256, KhPrf>>();
+    password_rt::<ReCrypt<XChaCha, KhPrf>>();
+    password_rt::<Kss<RingAes, RingAes>>();
+}
+
+#[test]
+fn password_needs_kdf_params() {
+    type S = ReCrypt<RingAes256, KhPrf>;
+    let mut ct = Vec::new();
+    S::encrypt_container(S::keygen(), &mut &random_vec(100)[..], &mut ct).unwrap();
+    assert_eq!(read_kdf_params(&mut &ct[..]).unwrap(), None);
+    let err = S::decrypt_container_with_password(b"passphrase", &mut Cursor::new(&ct), &mut Vec::new()).unwrap_err();
+    match *err.kind() {
+        ErrorKind::InvalidKdf(_) => {},
+        ref e => panic!("unexpected error {:?}", e),
+    }
+}
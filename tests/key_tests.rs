@@ -0,0 +1,125 @@
+extern crate recrypt;
+
+use recrypt::errors::{ErrorKind, Result};
+use recrypt::{Cipher, Key, KeyHomomorphicPrf, UpEncCtxtIndep};
+use recrypt::{AesGcmSiv, KhPrf, LwrKhPrf, RingAes, RingAes256, RingChaCha, RistrettoKhPrf, XChaCha};
+
+use std::io::Cursor;
+use std::ops::Add;
+
+// Key `Debug` output should not include the key bytes.
+fn debug_redacted<C: Cipher>() {
+    let key = C::keygen();
+    let debug = format!("{:?}", key);
+    assert!(debug.contains("<redacted>"));
+
+    let mut bytes = Vec::new();
+    key.write_key(&mut bytes).unwrap();
+    assert!(!debug.contains(&bytes[..4].iter().map(|b| b.to_string()).collect::<Vec<_>>().join(", ")));
+}
+
+#[test]
+fn key_debug_redacted() {
+    debug_redacted::<RingAes>();
+    debug_redacted::<RingAes256>();
+    debug_redacted::<RingChaCha>();
+    debug_redacted::<KhPrf>();
+    debug_redacted::<RistrettoKhPrf>();
+    debug_redacted::<LwrKhPrf>();
+}
+
+fn key_len<C: Cipher>() -> usize {
+    let mut bytes = Vec::new();
+    C::keygen().write_key(&mut bytes).unwrap();
+    bytes.len()
+}
+
+#[test]
+fn key_sizes() {
+    assert_eq!(key_len::<RingAes>(), 16);
+    assert_eq!(key_len::<RingAes256>(), 32);
+    assert_eq!(key_len::<RingChaCha>(), 32);
+    assert_eq!(key_len::<AesGcmSiv>(), 32);
+    assert_eq!(key_len::<XChaCha>(), 32);
+}
+
+// Key files must hold exactly one key.
+fn key_file_length<C: Cipher>() {
+    let key = C::keygen();
+    let mut bytes = Vec::new();
+    key.write_key(&mut bytes).unwrap();
+    assert_eq!(C::K::read_key_file(&mut &bytes[..]).unwrap(), key);
+
+    for bad in &[&bytes[..bytes.len() - 1], &bytes[..0], &[&bytes[..], &[0u8]].concat()[..]] {
+        match *C::K::read_key_file(&mut &bad[..]).unwrap_err().kind() {
+            ErrorKind::InvalidKey(_) => {},
+            ref e => panic!("unexpected error {:?}", e),
+        }
+    }
+}
+
+#[test]
+fn key_file_wrong_length() {
+    key_file_length::<RingAes>();
+    key_file_length::<RingAes256>();
+    key_file_length::<RingChaCha>();
+    key_file_length::<AesGcmSiv>();
+    key_file_length::<XChaCha>();
+    key_file_length::<KhPrf>();
+    key_file_length::<RistrettoKhPrf>();
+    key_file_length::<LwrKhPrf>();
+}
+
+fn counter_overflow<C: Cipher>() where C::K: Add<u64, Output = Result<C::K>> {
+    assert!((C::keygen() + 1).is_ok());
+    match *(C::keygen() + u64::max_value()).unwrap_err().kind() {
+        ErrorKind::CounterOverflow => {},
+        ref e => panic!("unexpected error {:?}", e),
+    }
+}
+
+#[test]
+fn key_counter_overflow() {
+    counter_overflow::<KhPrf>();
+    counter_overflow::<RistrettoKhPrf>();
+    counter_overflow::<LwrKhPrf>();
+}
+
+// A key may start at the last counter, as long as the ciphertext has only one
+// block.
+fn last_counter<C: KeyHomomorphicPrf + 'static>() where C::K: Add<u64, Output = Result<C::K>> + Send + Sync + 'static {
+    let k1 = (C::keygen() + (u64::max_value() - 1)).unwrap();
+    let k2 = (C::keygen_next(&k1).unwrap() + (u64::max_value() - 1)).unwrap();
+    let pt = vec![0x5a; C::PT_BLOCK_SIZE - 1];
+
+    let mut ct = Vec::new();
+    C::encrypt(k1.clone(), &mut &pt[..], &mut ct).unwrap();
+    let mut rk = Vec::new();
+    C::rekeygen(k1.clone(), k2.clone(), &mut rk).unwrap();
+    let mut ct2 = Vec::new();
+    C::reencrypt(&mut &rk[..], &mut &ct[..], &mut ct2).unwrap();
+    let mut out = Vec::new();
+    C::decrypt(k2.clone(), &mut &ct2[..], &mut out).unwrap();
+    assert_eq!(pt, out);
+
+    let mut out = Vec::new();
+    C::decrypt_range(k2.clone(), &mut Cursor::new(&ct2), 1..5, &mut out).unwrap();
+    assert_eq!(&pt[1..5], &out[..]);
+    let mut out = Vec::new();
+    C::decrypt_parallel(k2, &mut &ct2[..], &mut out, 2).unwrap();
+    assert_eq!(pt, out);
+
+    // A full block needs a second block of padding, with no counter left.
+    let pt = vec![0x5a; C::PT_BLOCK_SIZE];
+    match *C::encrypt(k1, &mut &pt[..], &mut Vec::new()).unwrap_err().kind() {
+        ErrorKind::CounterOverflow => {},
+        ref e => panic!("unexpected error {:?}", e),
+    }
+}
+
+#[test]
+fn key_last_counter() {
+    last_counter::<KhPrf>();
+    last_counter::<RistrettoKhPrf>();
+    last_counter::<LwrKhPrf>();
+}
@@ -0,0 +1,170 @@
+extern crate recrypt;
+
+use recrypt::errors::ErrorKind;
+use recrypt::generic::ReCrypt;
+use recrypt::{Cipher, UpEnc, UpEncCtxtIndep};
+use recrypt::{KhPrf, LwrKhPrf, RingAes, RistrettoKhPrf};
+
+use std::io::{self, Read};
+
+mod helpers;
+
+use helpers::random_vec;
+
+// y = 2 is not the y-coordinate of any point on the Edwards curve.
+const INVALID_EDWARDS: [u8; 32] = [2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                                   0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+// Not a canonical field element, so never a Ristretto encoding.
+const INVALID_RISTRETTO: [u8; 32] = [0xff; 32];
+
+// Size of the nonce at the start of each KH-PRF ciphertext.
+const NONCE_SIZE: usize = 16;
+
+// Replaces the second ciphertext block, starting from `offset`.
+fn corrupt_block(ct: &mut Vec<u8>, offset: usize, invalid: &[u8; 32]) {
+    ct[offset + 32..offset + 64].copy_from_slice(invalid);
+}
+
+#[test]
+fn kh_prf_reports_invalid_block() {
+    let key = KhPrf::keygen();
+    let mut ct = Vec::new();
+    KhPrf::encrypt(key.clone(), &mut &random_vec(100)[..], &mut ct).unwrap();
+    corrupt_block(&mut ct, NONCE_SIZE, &INVALID_EDWARDS);
+
+    match *KhPrf::decrypt(key, &mut &ct[..], &mut Vec::new()).unwrap_err().kind() {
+        ErrorKind::InvalidBlock(1) => (),
+        ref e => panic!("unexpected error: {:?}", e),
+    }
+}
+
+#[test]
+fn ristretto_kh_prf_reports_invalid_block() {
+    let k1 = RistrettoKhPrf::keygen();
+    let k2 = RistrettoKhPrf::keygen();
+    let mut ct = Vec::new();
+    RistrettoKhPrf::encrypt(k1.clone(), &mut &random_vec(100)[..], &mut ct).unwrap();
+    corrupt_block(&mut ct, NONCE_SIZE, &INVALID_RISTRETTO);
+
+    let mut rk = Vec::new();
+    RistrettoKhPrf::rekeygen(k1, k2, &mut rk).unwrap();
+    match *RistrettoKhPrf::reencrypt(&mut &rk[..], &mut &ct[..], &mut Vec::new()).unwrap_err().kind() {
+        ErrorKind::InvalidBlock(1) => (),
+        ref e => panic!("unexpected error: {:?}", e),
+    }
+}
+
+#[test]
+fn kh_prf_reports_truncated_block() {
+    let key = KhPrf::keygen();
+    let mut ct = Vec::new();
+    KhPrf::encrypt(key.clone(), &mut &random_vec(100)[..], &mut ct).unwrap();
+    ct.truncate(NONCE_SIZE + 32 * 2 + 5);
+
+    match *KhPrf::decrypt(key, &mut &ct[..], &mut Vec::new()).unwrap_err().kind() {
+        ErrorKind::TruncatedBlock(2, 5) => (),
+        ref e => panic!("unexpected error: {:?}", e),
+    }
+}
+
+#[test]
+fn recrypt_rejects_malformed_inputs() {
+    type Scheme = ReCrypt<RingAes, KhPrf>;
+    let k1 = Scheme::keygen();
+    let k2 = Scheme::keygen();
+    let mut hdr = Vec::new();
+    let mut body = Vec::new();
+    Scheme::encrypt(k1.clone(), &mut &random_vec(100)[..], &mut hdr, &mut body).unwrap();
+
+    // Truncated token
+    let mut rk = Vec::new();
+    Scheme::rekeygen(k1.clone(), k2, &mut &hdr[..], &mut rk).unwrap();
+    let short = &rk[..20];
+    let res = Scheme::reencrypt(&mut &short[..], &mut &hdr[..], &mut &body[..], &mut Vec::new(), &mut Vec::new());
+    match *res.unwrap_err().kind() {
+        ErrorKind::InvalidToken => (),
+        ref e => panic!("unexpected error: {:?}", e),
+    }
+
+    // Corrupt body block, after the 41-byte body key and the nonce
+    corrupt_block(&mut body, 41 + NONCE_SIZE, &INVALID_EDWARDS);
+    match *Scheme::decrypt(k1, &mut &hdr[..], &mut &body[..], &mut Vec::new()).unwrap_err().kind() {
+        ErrorKind::InvalidBlock(1) => (),
+        ref e => panic!("unexpected error: {:?}", e),
+    }
+}
+
+#[test]
+fn truncated_inputs_report_offset() {
+    let mut ct = Vec::new();
+    RingAes::encrypt(RingAes::keygen(), &mut &random_vec(100)[..], &mut ct).unwrap();
+    match *RingAes::decrypt(RingAes::keygen(), &mut &ct[..5], &mut Vec::new()).unwrap_err().kind() {
+        ErrorKind::TruncatedCiphertext(5) => (),
+        ref e => panic!("unexpected error: {:?}", e),
+    }
+
+    let key = KhPrf::keygen();
+    let mut ct = Vec::new();
+    KhPrf::encrypt(key.clone(), &mut &random_vec(100)[..], &mut ct).unwrap();
+    match *KhPrf::decrypt(key, &mut &ct[..10], &mut Vec::new()).unwrap_err().kind() {
+        ErrorKind::TruncatedCiphertext(10) => (),
+        ref e => panic!("unexpected error: {:?}", e),
+    }
+}
+
+// Fails every read, as a broken disk or connection would.
+struct Failing;
+
+impl Read for Failing {
+    fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::Other, "read failed"))
+    }
+}
+
+#[test]
+fn errors_tell_tampering_from_wrong_key_and_io() {
+    type Scheme = ReCrypt<RingAes, LwrKhPrf>;
+    let key = Scheme::keygen();
+    let (mut hdr1, mut body1) = (Vec::new(), Vec::new());
+    Scheme::encrypt(key.clone(), &mut &random_vec(100)[..], &mut hdr1, &mut body1).unwrap();
+
+    // Flip the top bit of the first coefficient of block 0, after the
+    // 4108-byte body key and the nonce: the body still decrypts, but to a
+    // different plaintext which does not match the tag.
+    let mut tampered = body1.clone();
+    tampered[4108 + NONCE_SIZE] ^= 0x80;
+    match *Scheme::decrypt(key.clone(), &mut &hdr1[..], &mut &tampered[..], &mut Vec::new()).unwrap_err().kind() {
+        ErrorKind::IntegrityCheckFailed => (),
+        ref e => panic!("unexpected error: {:?}", e),
+    }
+
+    // Wrong key: the header does not authenticate.
+    match *Scheme::decrypt(Scheme::keygen(), &mut &hdr1[..], &mut &body1[..], &mut Vec::new()).unwrap_err().kind() {
+        ErrorKind::DecryptionFailed => (),
+        ref e => panic!("unexpected error: {:?}", e),
+    }
+
+    // Wrong associated data: the header does not authenticate either.
+    let (mut hdr3, mut body3) = (Vec::new(), Vec::new());
+    Scheme::encrypt_aad(key.clone(), b"a.txt", &mut &random_vec(100)[..], &mut hdr3, &mut body3).unwrap();
+    match *Scheme::decrypt_aad(key.clone(), b"b.txt", &mut &hdr3[..], &mut &body3[..], &mut Vec::new()).unwrap_err().kind() {
+        ErrorKind::DecryptionFailed => (),
+        ref e => panic!("unexpected error: {:?}", e),
+    }
+
+    // A token for another object with the same associated data.
+    let (mut hdr4, mut body4) = (Vec::new(), Vec::new());
+    Scheme::encrypt_aad(key.clone(), b"a.txt", &mut &random_vec(100)[..], &mut hdr4, &mut body4).unwrap();
+    let mut rk = Vec::new();
+    Scheme::rekeygen_aad(key.clone(), Scheme::keygen(), b"a.txt", &mut &hdr3[..], &mut rk).unwrap();
+    let res = Scheme::reencrypt_aad(&mut &rk[..], b"a.txt", &mut &hdr4[..], &mut &body4[..], &mut Vec::new(), &mut Vec::new());
+    match *res.unwrap_err().kind() {
+        ErrorKind::AssociatedDataMismatch => (),
+        ref e => panic!("unexpected error: {:?}", e),
+    }
+
+    match *RingAes::decrypt(RingAes::keygen(), &mut Failing, &mut Vec::new()).unwrap_err().kind() {
+        ErrorKind::Io(_) => (),
+        ref e => panic!("unexpected error: {:?}", e),
+    }
+}
@@ -0,0 +1,142 @@
+extern crate recrypt;
+
+use recrypt::generic::MerkleReCrypt;
+use recrypt::{Key, KeyHomomorphicPrf, UpEnc};
+use recrypt::{KhPrf, LwrKhPrf, RingAes};
+
+use std::io::Cursor;
+
+mod helpers;
+
+use helpers::random_vec;
+
+type Scheme = MerkleReCrypt<RingAes, KhPrf>;
+
+// Plaintext bytes in each chunk of the hash tree, for `KhPrf`.
+const CHUNK_LEN: usize = 32 * 31;
+
+#[test]
+fn merkle_decrypt_range() {
+    for &len in &[1, CHUNK_LEN - 1, CHUNK_LEN, 5 * CHUNK_LEN + 100] {
+        let pt = random_vec(len);
+        let key = Scheme::keygen();
+        let mut hdr = Vec::new();
+        let mut body = Vec::new();
+        Scheme::encrypt_parallel(key.clone(), &mut &pt[..], &mut hdr, &mut body, 3).unwrap();
+        let mut out = Vec::new();
+        Scheme::decrypt_parallel(key.clone(), &mut &hdr[..], &mut &body[..], &mut out, 3).unwrap();
+        assert_eq!(pt, out);
+
+        let len = len as u64;
+        let chunk = CHUNK_LEN as u64;
+        for &(start, end) in &[(0, len), (0, 1), (len - 1, len), (len / 2, len + 10), (chunk - 1, chunk + 1), (len, len + 1)] {
+            let mut out = Vec::new();
+            Scheme::decrypt_range(key.clone(), &mut &hdr[..], &mut Cursor::new(&body), start..end, &mut out).unwrap();
+            let (start, end) = (start.min(len) as usize, end.min(len) as usize);
+            assert_eq!(&pt[start..end.max(start)], &out[..]);
+        }
+    }
+}
+
+#[test]
+fn merkle_rejects_tampered_chunk() {
+    let pt = random_vec(4 * CHUNK_LEN);
+    let key = Scheme::keygen();
+    let mut hdr = Vec::new();
+    let mut body = Vec::new();
+    Scheme::encrypt(key.clone(), &mut &pt[..], &mut hdr, &mut body).unwrap();
+
+    // Swap two blocks in the third chunk, after the 41-byte body key and the
+    // 16-byte nonce.
+    let block = 41 + 16 + 32 * (2 * 32 + 3);
+    let (a, b) = body.split_at_mut(block + 32);
+    a[block..].swap_with_slice(&mut b[..32]);
+
+    // The first chunk still verifies, but the third does not.
+    let mut out = Vec::new();
+    Scheme::decrypt_range(key.clone(), &mut &hdr[..], &mut Cursor::new(&body), 0..100, &mut out).unwrap();
+    assert_eq!(&pt[..100], &out[..]);
+    out.clear();
+    assert!(Scheme::decrypt_range(key.clone(), &mut &hdr[..], &mut Cursor::new(&body), 2 * CHUNK_LEN as u64..2 * CHUNK_LEN as u64 + 1, &mut out).is_err());
+    assert!(out.is_empty());
+    assert!(Scheme::decrypt(key.clone(), &mut &hdr[..], &mut &body[..], &mut out).is_err());
+
+    // `decrypt` streams the chunks before the root is checked, so only
+    // `decrypt_verified` withholds them.
+    out.clear();
+    let mut spool = Cursor::new(Vec::new());
+    assert!(Scheme::decrypt_verified(key, &mut &hdr[..], &mut &body[..], &mut spool, &mut out).is_err());
+    assert!(out.is_empty());
+}
+
+// Rotates a ciphertext, edits two of its chunks in place, and rotates it again.
+fn edit_chunks<B: KeyHomomorphicPrf>(chunk_len: usize) {
+    let mut pt = random_vec(3 * chunk_len + 10);
+    let k1 = MerkleReCrypt::<RingAes, B>::keygen();
+    let k2 = MerkleReCrypt::<RingAes, B>::keygen();
+    let k3 = MerkleReCrypt::<RingAes, B>::keygen();
+    let mut hdr1 = Vec::new();
+    let mut body1 = Vec::new();
+    MerkleReCrypt::<RingAes, B>::encrypt(k1.clone(), &mut &pt[..], &mut hdr1, &mut body1).unwrap();
+
+    let mut rk = Vec::new();
+    MerkleReCrypt::<RingAes, B>::rekeygen(k1, k2.clone(), &mut &hdr1[..], &mut rk).unwrap();
+    let mut hdr = Vec::new();
+    let mut body = Vec::new();
+    MerkleReCrypt::<RingAes, B>::reencrypt(&mut &rk[..], &mut &hdr1[..], &mut &body1[..], &mut hdr, &mut body).unwrap();
+    let mut file = Cursor::new(body);
+
+    // Edit a middle chunk and the shorter final chunk.
+    for &(index, len) in &[(1, chunk_len), (3, 10)] {
+        let data = random_vec(len);
+        let mut new_hdr = Vec::new();
+        file.set_position(0);
+        MerkleReCrypt::<RingAes, B>::write_chunk(k2.clone(), &mut &hdr[..], &mut file, index as u64, &data, &mut new_hdr).unwrap();
+        pt[index * chunk_len..index * chunk_len + len].copy_from_slice(&data);
+        hdr = new_hdr;
+
+        let mut out = Vec::new();
+        MerkleReCrypt::<RingAes, B>::decrypt(k2.clone(), &mut &hdr[..], &mut &file.get_ref()[..], &mut out).unwrap();
+        assert_eq!(pt, out);
+    }
+
+    // Writing back the same data still moves every block of the chunk to a
+    // fresh keystream.
+    let before = file.get_ref().clone();
+    let data = pt[..chunk_len].to_vec();
+    let mut new_hdr = Vec::new();
+    file.set_position(0);
+    MerkleReCrypt::<RingAes, B>::write_chunk(k2.clone(), &mut &hdr[..], &mut file, 0, &data, &mut new_hdr).unwrap();
+    hdr = new_hdr;
+    let mut rest = &before[..];
+    B::K::read_key(&mut rest).unwrap();
+    let start = before.len() - rest.len() + 16;
+    for i in 0..32 {
+        let block = start + i * B::CT_BLOCK_SIZE..start + (i + 1) * B::CT_BLOCK_SIZE;
+        assert!(before[block.clone()] != file.get_ref()[block]);
+    }
+
+    // Chunks must keep their length.
+    file.set_position(0);
+    assert!(MerkleReCrypt::<RingAes, B>::write_chunk(k2.clone(), &mut &hdr[..], &mut file, 0, &random_vec(5), &mut Vec::new()).is_err());
+
+    // Edited ciphertexts can still be rotated.
+    let mut rk = Vec::new();
+    MerkleReCrypt::<RingAes, B>::rekeygen(k2, k3.clone(), &mut &hdr[..], &mut rk).unwrap();
+    let mut hdr3 = Vec::new();
+    let mut body3 = Vec::new();
+    MerkleReCrypt::<RingAes, B>::reencrypt(&mut &rk[..], &mut &hdr[..], &mut &file.get_ref()[..], &mut hdr3, &mut body3).unwrap();
+    let mut out = Vec::new();
+    MerkleReCrypt::<RingAes, B>::decrypt_range(k3, &mut &hdr3[..], &mut Cursor::new(&body3), 0..pt.len() as u64, &mut out).unwrap();
+    assert_eq!(pt, out);
+}
+
+#[test]
+fn kh_prf_merkle_edit_chunks() {
+    edit_chunks::<KhPrf>(CHUNK_LEN);
+}
+
+#[test]
+fn lwr_kh_prf_merkle_edit_chunks() {
+    edit_chunks::<LwrKhPrf>(32 * 32);
+}
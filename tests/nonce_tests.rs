@@ -0,0 +1,49 @@
+extern crate recrypt;
+
+use recrypt::{Cipher, KeyHomomorphicPrf, UpEncCtxtIndep};
+use recrypt::{KhPrf, LwrKhPrf, RistrettoKhPrf};
+
+mod helpers;
+
+use helpers::random_vec;
+
+// Two ciphertexts of the same plaintext under one key should not share any
+// blocks, and a single token should update both.
+fn nonce_separates_ciphertexts<C: KeyHomomorphicPrf>(ct_block_size: usize) {
+    let pt = random_vec(ct_block_size * 4);
+    let k1 = C::keygen();
+    let k2 = C::keygen_next(&k1).unwrap();
+
+    let mut ct1 = Vec::new();
+    let mut ct2 = Vec::new();
+    C::encrypt(k1.clone(), &mut &pt[..], &mut ct1).unwrap();
+    C::encrypt(k1.clone(), &mut &pt[..], &mut ct2).unwrap();
+    for (b1, b2) in ct1.chunks(ct_block_size).zip(ct2.chunks(ct_block_size)) {
+        assert!(b1 != b2);
+    }
+
+    let mut rk = Vec::new();
+    C::rekeygen(k1, k2.clone(), &mut rk).unwrap();
+    for ct in vec![ct1, ct2] {
+        let mut new_ct = Vec::new();
+        C::reencrypt(&mut &rk[..], &mut &ct[..], &mut new_ct).unwrap();
+        let mut rec = Vec::new();
+        C::decrypt(k2.clone(), &mut &new_ct[..], &mut rec).unwrap();
+        assert_eq!(pt, rec);
+    }
+}
+
+#[test]
+fn kh_prf_nonce() {
+    nonce_separates_ciphertexts::<KhPrf>(32);
+}
+
+#[test]
+fn ristretto_kh_prf_nonce() {
+    nonce_separates_ciphertexts::<RistrettoKhPrf>(32);
+}
+
+#[test]
+fn lwr_kh_prf_nonce() {
+    nonce_separates_ciphertexts::<LwrKhPrf>(64);
+}
@@ -0,0 +1,108 @@
+extern crate recrypt;
+
+use recrypt::generic::ReCrypt;
+use recrypt::{Cipher, KeyHomomorphicPrf, UpEnc, UpEncCtxtIndep};
+use recrypt::{KhPrf, RingAes, RistrettoKhPrf};
+
+mod helpers;
+
+use helpers::random_vec;
+
+// Sizes either side of a batch boundary for 1, 2 and 3 workers.
+fn get_sizes(block_size: usize) -> Vec<usize> {
+    vec![0, 1, block_size, 1024 * block_size - 1, 1024 * block_size,
+         2048 * block_size + 1, 3 * 1024 * block_size + 5]
+}
+
+#[test]
+fn kh_prf_parallel_matches_sequential() {
+    for size in get_sizes(31) {
+        let pt = random_vec(size);
+        let k1 = KhPrf::keygen();
+        let k2 = KhPrf::keygen();
+        let mut rk = Vec::new();
+        KhPrf::rekeygen(k1.clone(), k2.clone(), &mut rk).unwrap();
+
+        let mut ct = Vec::new();
+        KhPrf::encrypt(k1.clone(), &mut &pt[..], &mut ct).unwrap();
+        let mut ct2 = Vec::new();
+        KhPrf::reencrypt(&mut &rk[..], &mut &ct[..], &mut ct2).unwrap();
+
+        for workers in 1..4 {
+            // Only the nonce differs from `encrypt`.
+            let mut par_ct = Vec::new();
+            KhPrf::encrypt_parallel(k1.clone(), &mut &pt[..], &mut par_ct, workers).unwrap();
+            assert_eq!(ct.len(), par_ct.len());
+            let mut par_rec = Vec::new();
+            KhPrf::decrypt(k1.clone(), &mut &par_ct[..], &mut par_rec).unwrap();
+            assert_eq!(pt, par_rec);
+
+            let mut par_ct2 = Vec::new();
+            KhPrf::reencrypt_parallel(&mut &rk[..], &mut &ct[..], &mut par_ct2, workers).unwrap();
+            assert_eq!(ct2, par_ct2);
+
+            let mut par_pt = Vec::new();
+            KhPrf::decrypt_parallel(k2.clone(), &mut &par_ct2[..], &mut par_pt, workers).unwrap();
+            assert_eq!(pt, par_pt);
+        }
+    }
+}
+
+#[test]
+fn ristretto_kh_prf_parallel_matches_sequential() {
+    for size in get_sizes(30) {
+        let pt = random_vec(size);
+        let k1 = RistrettoKhPrf::keygen();
+        let k2 = RistrettoKhPrf::keygen();
+        let mut rk = Vec::new();
+        RistrettoKhPrf::rekeygen(k1.clone(), k2.clone(), &mut rk).unwrap();
+
+        let mut ct = Vec::new();
+        RistrettoKhPrf::encrypt(k1.clone(), &mut &pt[..], &mut ct).unwrap();
+        let mut ct2 = Vec::new();
+        RistrettoKhPrf::reencrypt(&mut &rk[..], &mut &ct[..], &mut ct2).unwrap();
+
+        for workers in 1..4 {
+            // Only the nonce differs from `encrypt`.
+            let mut par_ct = Vec::new();
+            RistrettoKhPrf::encrypt_parallel(k1.clone(), &mut &pt[..], &mut par_ct, workers).unwrap();
+            assert_eq!(ct.len(), par_ct.len());
+            let mut par_rec = Vec::new();
+            RistrettoKhPrf::decrypt(k1.clone(), &mut &par_ct[..], &mut par_rec).unwrap();
+            assert_eq!(pt, par_rec);
+
+            let mut par_ct2 = Vec::new();
+            RistrettoKhPrf::reencrypt_parallel(&mut &rk[..], &mut &ct[..], &mut par_ct2, workers).unwrap();
+            assert_eq!(ct2, par_ct2);
+
+            let mut par_pt = Vec::new();
+            RistrettoKhPrf::decrypt_parallel(k2.clone(), &mut &par_ct2[..], &mut par_pt, workers).unwrap();
+            assert_eq!(pt, par_pt);
+        }
+    }
+}
+
+#[test]
+fn recrypt_reencrypt_parallel_matches_sequential() {
+    type Scheme = ReCrypt<RingAes, KhPrf>;
+    let pt = random_vec(2048 * 31 + 7);
+    let k1 = Scheme::keygen();
+    let k2 = Scheme::keygen();
+
+    let mut ct_hdr = Vec::new();
+    let mut ct_body = Vec::new();
+    Scheme::encrypt(k1.clone(), &mut &pt[..], &mut ct_hdr, &mut ct_body).unwrap();
+    let mut rk = Vec::new();
+    Scheme::rekeygen(k1, k2.clone(), &mut &ct_hdr[..], &mut rk).unwrap();
+
+    let (mut hdr, mut body) = (Vec::new(), Vec::new());
+    Scheme::reencrypt(&mut &rk[..], &mut &ct_hdr[..], &mut &ct_body[..], &mut hdr, &mut body).unwrap();
+    let (mut par_hdr, mut par_body) = (Vec::new(), Vec::new());
+    Scheme::reencrypt_parallel(&mut &rk[..], None, &mut &ct_hdr[..], &mut &ct_body[..], &mut par_hdr, &mut par_body, 3).unwrap();
+    assert_eq!(hdr, par_hdr);
+    assert_eq!(body, par_body);
+
+    let mut rec = Vec::new();
+    Scheme::decrypt(k2, &mut &par_hdr[..], &mut &par_body[..], &mut rec).unwrap();
+    assert_eq!(pt, rec);
+}
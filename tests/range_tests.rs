@@ -0,0 +1,91 @@
+extern crate recrypt;
+
+use recrypt::errors::ErrorKind;
+use recrypt::generic::ReCrypt;
+use recrypt::{Cipher, KeyHomomorphicPrf, UpEnc};
+use recrypt::{KhPrf, RingAes, RistrettoKhPrf};
+
+use std::io::Cursor;
+
+mod helpers;
+
+use helpers::random_vec;
+
+// Ranges to check for a plaintext of length `len` split into blocks of
+// `block_size`, including ones touching the padded final block.
+fn get_ranges(len: u64, block_size: u64) -> Vec<(u64, u64)> {
+    vec![(0, len), (0, 1), (1, block_size + 1), (block_size - 1, 2 * block_size + 1),
+         (len / 2, len), (len - 1, len), (len - 3, len + 10), (len, len + 5),
+         (block_size, 2 * block_size), (5, 5)]
+}
+
+#[test]
+fn kh_prf_decrypt_range() {
+    for len in vec![31 * 4, 31 * 4 + 7, 31 * 10 - 1] {
+        let pt = random_vec(len);
+        let key = KhPrf::keygen();
+        let mut ct = Vec::new();
+        KhPrf::encrypt(key.clone(), &mut &pt[..], &mut ct).unwrap();
+
+        for (start, end) in get_ranges(len as u64, 31) {
+            let mut out = Vec::new();
+            KhPrf::decrypt_range(key.clone(), &mut Cursor::new(&ct), start..end, &mut out).unwrap();
+            let (start, end) = (start.min(len as u64) as usize, end.min(len as u64) as usize);
+            assert_eq!(&pt[start..end.max(start)], &out[..]);
+        }
+    }
+}
+
+#[test]
+fn ristretto_kh_prf_decrypt_range() {
+    for len in vec![30 * 4, 30 * 4 + 7, 30 * 10 - 1] {
+        let pt = random_vec(len);
+        let key = RistrettoKhPrf::keygen();
+        let mut ct = Vec::new();
+        RistrettoKhPrf::encrypt(key.clone(), &mut &pt[..], &mut ct).unwrap();
+
+        for (start, end) in get_ranges(len as u64, 30) {
+            let mut out = Vec::new();
+            RistrettoKhPrf::decrypt_range(key.clone(), &mut Cursor::new(&ct), start..end, &mut out).unwrap();
+            let (start, end) = (start.min(len as u64) as usize, end.min(len as u64) as usize);
+            assert_eq!(&pt[start..end.max(start)], &out[..]);
+        }
+    }
+}
+
+#[test]
+fn recrypt_decrypt_range() {
+    type Scheme = ReCrypt<RingAes, KhPrf>;
+    let len = 31 * 8 + 3;
+    let pt = random_vec(len);
+    let key = Scheme::keygen();
+    let mut ct_hdr = Vec::new();
+    let mut ct_body = Vec::new();
+    Scheme::encrypt(key.clone(), &mut &pt[..], &mut ct_hdr, &mut ct_body).unwrap();
+
+    for (start, end) in get_ranges(len as u64, 31) {
+        let mut out = Vec::new();
+        Scheme::decrypt_range(key.clone(), None, &mut &ct_hdr[..], &mut Cursor::new(&ct_body), start..end, &mut out).unwrap();
+        let (start, end) = (start.min(len as u64) as usize, end.min(len as u64) as usize);
+        assert_eq!(&pt[start..end.max(start)], &out[..]);
+    }
+}
+
+#[test]
+fn decrypt_range_keeps_block_error() {
+    let key = KhPrf::keygen();
+    let mut ct = Vec::new();
+    KhPrf::encrypt(key.clone(), &mut &random_vec(31 * 3)[..], &mut ct).unwrap();
+
+    // Not a point: x^2 = (y^2 - 1) / (d y^2 + 1) has no root for y = 2.
+    let mut bad = [0u8; 32];
+    bad[0] = 2;
+    let n = ct.len();
+    ct[n - 32..].copy_from_slice(&bad);
+    let err = KhPrf::decrypt_range(key, &mut Cursor::new(&ct), 0..10, &mut Vec::new()).unwrap_err();
+    match *err.kind() {
+        ErrorKind::InvalidBlock(3) => (),
+        ref e => panic!("unexpected error: {:?}", e),
+    }
+    assert!(err.iter().nth(1).is_some());
+}
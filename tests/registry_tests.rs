@@ -0,0 +1,85 @@
+extern crate recrypt;
+
+use recrypt::errors::ErrorKind;
+use recrypt::generic::{KemDem, Kss, MerkleReCrypt, ReCrypt};
+use recrypt::{Container, Registry, SuiteId, UpEnc};
+use recrypt::{KhPrf, LwrKhPrf, RingAes, RingChaCha};
+
+use std::io::Cursor;
+
+mod helpers;
+
+use helpers::{key_bytes, random_vec};
+
+// Containers written by the typed API are decrypted and rotated by the
+// registry without naming the scheme, and the results read back with the
+// typed API.
+fn registry_rt<S: Container>(registry: &Registry) {
+    let pt = random_vec(1000);
+    let k1 = S::keygen();
+    let k2 = S::keygen();
+    let mut ct1 = Vec::new();
+    S::encrypt_container(k1.clone(), &mut &pt[..], &mut ct1).unwrap();
+
+    let mut out = Vec::new();
+    registry.decrypt(&key_bytes(&k1), &mut Cursor::new(&ct1), &mut out).unwrap();
+    assert_eq!(pt, out);
+
+    let mut rk = Vec::new();
+    registry.rekeygen(&key_bytes(&k1), &key_bytes(&k2), &mut Cursor::new(&ct1), &mut rk).unwrap();
+    let mut ct2 = Vec::new();
+    registry.reencrypt(&mut &rk[..], &mut Cursor::new(&ct1), &mut ct2).unwrap();
+
+    let mut out = Vec::new();
+    S::decrypt_container(k2, &mut Cursor::new(&ct2), &mut out).unwrap();
+    assert_eq!(pt, out);
+}
+
+#[test]
+fn registry_dispatch() {
+    let registry = Registry::default();
+    registry_rt::<KemDem<RingAes>>(&registry);
+    registry_rt::<Kss<RingAes, RingChaCha>>(&registry);
+    registry_rt::<ReCrypt<RingAes, KhPrf>>(&registry);
+    registry_rt::<ReCrypt<RingChaCha, LwrKhPrf>>(&registry);
+    registry_rt::<MerkleReCrypt<RingAes, KhPrf>>(&registry);
+}
+
+#[test]
+fn registry_encrypt() {
+    let registry = Registry::default();
+    let suite = ReCrypt::<RingChaCha, KhPrf>::suite_id();
+    let pt = random_vec(1000);
+    let mut key = Vec::new();
+    registry.keygen(&suite, &mut key).unwrap();
+
+    let mut ct = Vec::new();
+    registry.encrypt(&suite, &key, &mut &pt[..], &mut ct).unwrap();
+    let scheme = registry.detect(&mut Cursor::new(&ct)).unwrap();
+    assert_eq!(scheme.suite_id(), suite);
+
+    let mut out = Vec::new();
+    registry.decrypt(&key, &mut Cursor::new(&ct), &mut out).unwrap();
+    assert_eq!(pt, out);
+
+    // Keys must be exactly one key long.
+    key.push(0);
+    assert!(registry.decrypt(&key, &mut Cursor::new(&ct), &mut Vec::new()).is_err());
+}
+
+#[test]
+fn registry_unknown_suite() {
+    let mut registry = Registry::new();
+    registry.register::<ReCrypt<RingAes, KhPrf>>();
+    assert_eq!(registry.suites(), vec![ReCrypt::<RingAes, KhPrf>::suite_id()]);
+
+    type S = ReCrypt<RingAes, LwrKhPrf>;
+    let key = S::keygen();
+    let mut ct = Vec::new();
+    S::encrypt_container(key.clone(), &mut &random_vec(100)[..], &mut ct).unwrap();
+    let err = registry.decrypt(&key_bytes(&key), &mut Cursor::new(&ct), &mut Vec::new()).unwrap_err();
+    match *err.kind() {
+        ErrorKind::UnknownSuite(ref suite) => assert_eq!(suite, &S::suite_id()),
+        ref e => panic!("unexpected error {:?}", e),
+    }
+}
@@ -0,0 +1,72 @@
+extern crate aes_gcm_siv;
+extern crate recrypt;
+
+use aes_gcm_siv::aead::{Aead, KeyInit, Payload};
+use aes_gcm_siv::{Aes256GcmSiv, Nonce};
+use recrypt::generic::{KemDem, Kss, MerkleReCrypt, ReCrypt};
+use recrypt::{AesGcmSiv, Cipher, Key};
+use recrypt::{KhPrf, LwrKhPrf, RingAes, RistrettoKhPrf};
+
+mod helpers;
+
+use helpers::{hex, key_bytes};
+
+#[test]
+fn siv_rt() {
+    helpers::aead_rt::<AesGcmSiv>(12 + 16);
+}
+
+// RFC 8452, appendix C.2: the first AEAD_AES_256_GCM_SIV vector, written as
+// nonce || ciphertext.
+#[test]
+fn siv_test_vector() {
+    let key = <AesGcmSiv as Cipher>::K::read_key(&mut &hex(&format!("01{}", "00".repeat(31)))[..]).unwrap();
+    let ct = hex("030000000000000000000000\
+                  07f5f4169bbf55a8400cd47ea6fd400f");
+    let mut out = Vec::new();
+    AesGcmSiv::decrypt(key, &mut &ct[..], &mut out).unwrap();
+    assert!(out.is_empty());
+}
+
+// RFC 8452, appendix C.2: the AEAD_AES_256_GCM_SIV vector with a 1-byte AAD
+// and an 8-byte plaintext. The nonce is random on encryption, so the exact
+// ciphertext is checked by sealing with the vector's nonce under the key as
+// `AesGcmSiv` reads it.
+#[test]
+fn siv_test_vector_aad() {
+    let raw_key = hex(&format!("01{}", "00".repeat(31)));
+    let key = <AesGcmSiv as Cipher>::K::read_key(&mut &raw_key[..]).unwrap();
+    let (aad, pt) = (hex("01"), hex("0200000000000000"));
+    let ct = hex("030000000000000000000000\
+                  1de22967237a813291213f267e3b452f02d01ae33e4ec854");
+
+    assert_eq!(key_bytes(&key), raw_key);
+    let cipher = Aes256GcmSiv::new_from_slice(&raw_key).unwrap();
+    let sealed = cipher.encrypt(Nonce::from_slice(&ct[..12]), Payload { msg: &pt, aad: &aad }).unwrap();
+    assert_eq!(&sealed[..], &ct[12..]);
+
+    let mut out = Vec::new();
+    AesGcmSiv::decrypt_aad(key.clone(), &aad, &mut &ct[..], &mut out).unwrap();
+    assert_eq!(out, pt);
+
+    let mut bad = ct.clone();
+    bad[12] ^= 1;
+    assert!(AesGcmSiv::decrypt_aad(key.clone(), &aad, &mut &bad[..], &mut Vec::new()).is_err());
+    assert!(AesGcmSiv::decrypt_aad(key.clone(), &hex("02"), &mut &ct[..], &mut Vec::new()).is_err());
+    assert!(AesGcmSiv::decrypt(key, &mut &ct[..], &mut Vec::new()).is_err());
+}
+
+#[test]
+fn siv_key_xor() {
+    helpers::key_xor::<AesGcmSiv>();
+}
+
+#[test]
+fn siv_header_cipher() {
+    helpers::filecrypt_rt::<KemDem<AesGcmSiv>>(16);
+    helpers::encrypt_update_once::<Kss<AesGcmSiv, RingAes>>(16);
+    helpers::encrypt_update_once::<ReCrypt<AesGcmSiv, KhPrf>>(31);
+    helpers::encrypt_update_once::<ReCrypt<AesGcmSiv, RistrettoKhPrf>>(30);
+    helpers::encrypt_update_once::<ReCrypt<AesGcmSiv, LwrKhPrf>>(256);
+    helpers::encrypt_update_once::<MerkleReCrypt<AesGcmSiv, KhPrf>>(31);
+}
@@ -0,0 +1,86 @@
+extern crate recrypt;
+
+use recrypt::generic::{KemDem, Kss};
+use recrypt::{Cipher, RingAes, RingAesStream, RingChaCha, RingChaChaStream};
+
+mod helpers;
+
+use helpers::random_vec;
+
+const SEGMENT: usize = 64 * 1024;
+
+fn stream_rt<C: Cipher>() {
+    for &size in &[0, 1, SEGMENT - 1, SEGMENT, SEGMENT + 1, 3 * SEGMENT + 100] {
+        let pt = random_vec(size);
+        let key = C::keygen();
+        let mut ct = Vec::new();
+        C::encrypt(key.clone(), &mut &pt[..], &mut ct).unwrap();
+
+        let mut out = Vec::new();
+        C::decrypt(key, &mut &ct[..], &mut out).unwrap();
+        assert_eq!(pt, out);
+    }
+}
+
+// Segments can't be dropped, reordered or cut short.
+fn stream_tamper<C: Cipher>(tag_len: usize) {
+    let key = C::keygen();
+    let mut ct = Vec::new();
+    C::encrypt(key.clone(), &mut &random_vec(3 * SEGMENT + 100)[..], &mut ct).unwrap();
+    let decrypt = |ct: &[u8]| C::decrypt(key.clone(), &mut &ct[..], &mut Vec::new());
+
+    let segment = SEGMENT + tag_len;
+    let (prefix, body) = ct.split_at(7);
+
+    // Truncated at a segment boundary, or within a segment.
+    for &len in &[segment, 2 * segment, 3 * segment, 3 * segment + 50, 1] {
+        assert!(decrypt(&ct[..7 + len]).is_err());
+    }
+
+    // The first two segments swapped.
+    let mut swapped = prefix.to_vec();
+    swapped.extend_from_slice(&body[segment..2 * segment]);
+    swapped.extend_from_slice(&body[..segment]);
+    swapped.extend_from_slice(&body[2 * segment..]);
+    assert!(decrypt(&swapped).is_err());
+
+    // The second segment dropped.
+    let mut dropped = prefix.to_vec();
+    dropped.extend_from_slice(&body[..segment]);
+    dropped.extend_from_slice(&body[2 * segment..]);
+    assert!(decrypt(&dropped).is_err());
+
+    assert!(decrypt(&ct).is_ok());
+}
+
+#[test]
+fn ring_stream_rt() {
+    stream_rt::<RingAesStream>();
+    stream_rt::<RingChaChaStream>();
+}
+
+#[test]
+fn ring_stream_tamper() {
+    stream_tamper::<RingAesStream>(16);
+    stream_tamper::<RingChaChaStream>(16);
+}
+
+#[test]
+fn ring_stream_aad() {
+    let pt = random_vec(2 * SEGMENT);
+    let key = RingAesStream::keygen();
+    let mut ct = Vec::new();
+    RingAesStream::encrypt_aad(key.clone(), b"object 1", &mut &pt[..], &mut ct).unwrap();
+    let mut out = Vec::new();
+    RingAesStream::decrypt_aad(key.clone(), b"object 1", &mut &ct[..], &mut out).unwrap();
+    assert_eq!(pt, out);
+    assert!(RingAesStream::decrypt_aad(key, b"object 2", &mut &ct[..], &mut Vec::new()).is_err());
+}
+
+#[test]
+fn stream_body_cipher() {
+    helpers::filecrypt_rt::<KemDem<RingAesStream>>(SEGMENT);
+    helpers::filecrypt_rt::<KemDem<RingChaChaStream>>(SEGMENT);
+    helpers::encrypt_update_once::<Kss<RingAes, RingAesStream>>(SEGMENT);
+    helpers::encrypt_update_once::<Kss<RingChaCha, RingChaChaStream>>(SEGMENT);
+}
@@ -0,0 +1,51 @@
+extern crate recrypt;
+
+use recrypt::generic::{Kss, ReCrypt};
+use recrypt::UpEnc;
+use recrypt::{KhPrf, LwrKhPrf, RingAes};
+
+use std::io::Cursor;
+
+mod helpers;
+
+use helpers::random_vec;
+
+// Decrypts with the header of one ciphertext and the body of another, which
+// fails the integrity check without leaving anything in `pt`.
+fn verified_withholds_plaintext<S: UpEnc>() -> Vec<u8> {
+    let pt = random_vec(1000);
+    let key = S::keygen();
+    let (mut hdr1, mut body1, mut hdr2, mut body2) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    S::encrypt(key.clone(), &mut &pt[..], &mut hdr1, &mut body1).unwrap();
+    S::encrypt(key.clone(), &mut &random_vec(1000)[..], &mut hdr2, &mut body2).unwrap();
+
+    let mut out = Vec::new();
+    let mut spool = Cursor::new(Vec::new());
+    S::decrypt_verified(key.clone(), &mut &hdr1[..], &mut &body1[..], &mut spool, &mut out).unwrap();
+    assert_eq!(pt, out);
+
+    let mut out = Vec::new();
+    let res = S::decrypt_verified(key.clone(), &mut &hdr1[..], &mut &body2[..], &mut Cursor::new(Vec::new()), &mut out);
+    assert!(res.is_err());
+    assert!(out.is_empty());
+
+    // Plain `decrypt` also fails, but may already have written plaintext.
+    let mut unverified = Vec::new();
+    assert!(S::decrypt(key, &mut &hdr1[..], &mut &body2[..], &mut unverified).is_err());
+    unverified
+}
+
+#[test]
+fn recrypt_decrypt_verified() {
+    verified_withholds_plaintext::<ReCrypt<RingAes, KhPrf>>();
+
+    // LWR blocks always decrypt to something, so plain `decrypt` writes out
+    // the garbage before the tag check fails.
+    let unverified = verified_withholds_plaintext::<ReCrypt<RingAes, LwrKhPrf>>();
+    assert!(!unverified.is_empty());
+}
+
+#[test]
+fn kss_decrypt_verified() {
+    verified_withholds_plaintext::<Kss<RingAes, RingAes>>();
+}
@@ -0,0 +1,53 @@
+extern crate recrypt;
+
+use recrypt::generic::{Kss, MerkleReCrypt, ReCrypt};
+use recrypt::{Cipher, Key, XChaCha};
+use recrypt::{KhPrf, LwrKhPrf, RingChaCha};
+
+mod helpers;
+
+use helpers::hex;
+
+#[test]
+fn xchacha_rt() {
+    helpers::aead_rt::<XChaCha>(24 + 16);
+}
+
+// draft-irtf-cfrg-xchacha-03, appendix A.3.1, written as nonce || ciphertext
+// || tag.
+#[test]
+fn xchacha_test_vector() {
+    let key = <XChaCha as Cipher>::K::read_key(&mut &hex("808182838485868788898a8b8c8d8e8f\
+                                                          909192939495969798999a9b9c9d9e9f")[..]).unwrap();
+    let aad = hex("50515253c0c1c2c3c4c5c6c7");
+    let pt = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+    let ct = hex("404142434445464748494a4b4c4d4e4f5051525354555657\
+                  bd6d179d3e83d43b9576579493c0e939572a1700252bfaccbed2902c21396cbb\
+                  731c7f1b0b4aa6440bf3a82f4eda7e39ae64c6708c54c216cb96b72e1213b452\
+                  2f8c9ba40db5d945b11b69b982c1bb9e3f3fac2bc369488f76b2383565d3fff9\
+                  21f9664c97637da9768812f615c68b13b52e\
+                  c0875924c1c7987947deafd8780acf49");
+
+    let mut out = Vec::new();
+    XChaCha::decrypt_aad(key.clone(), &aad, &mut &ct[..], &mut out).unwrap();
+    assert_eq!(&out[..], &pt[..]);
+
+    let mut bad = ct.clone();
+    bad[30] ^= 1;
+    assert!(XChaCha::decrypt_aad(key.clone(), &aad, &mut &bad[..], &mut Vec::new()).is_err());
+    assert!(XChaCha::decrypt(key, &mut &ct[..], &mut Vec::new()).is_err());
+}
+
+#[test]
+fn xchacha_key_xor() {
+    helpers::key_xor::<XChaCha>();
+}
+
+#[test]
+fn xchacha_header_cipher() {
+    helpers::encrypt_update_once::<Kss<XChaCha, RingChaCha>>(16);
+    helpers::encrypt_update_once::<Kss<XChaCha, XChaCha>>(16);
+    helpers::encrypt_update_once::<ReCrypt<XChaCha, KhPrf>>(31);
+    helpers::encrypt_update_once::<ReCrypt<XChaCha, LwrKhPrf>>(256);
+    helpers::encrypt_update_once::<MerkleReCrypt<XChaCha, KhPrf>>(31);
+}